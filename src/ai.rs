@@ -0,0 +1,206 @@
+use std::f32::consts::TAU;
+
+use crate::{systems, entity::{Ai, AiMode, AiState, Entities, Entity, Transform}, level::Level, map::{self, Map, MapCoordinate, MapPosition}, path::{self, Connectivity, PathRules, Pathfinder}, world::{self, WorldLength, WorldPosition, WorldVector}};
+
+// Enemies go around corners rather than brushing against them.
+const PATH_RULES:           PathRules   = PathRules { connectivity: Connectivity::Eight, opens_doors: false };
+// How close to a patrol point counts as being there.
+const ARRIVAL_DISTANCE:     WorldLength = 0.1;
+// How close to where the player was last noticed counts as having looked there.
+const SEARCH_DISTANCE:      WorldLength = 0.5;
+const PATROL_PAUSE_SECONDS: f32         = 1.0;
+// Looking around where the player was last noticed, before going back to what they were doing.
+const SEARCH_SECONDS:       f32         = 3.0;
+// Fleeing enemies stop running this long after they last noticed the player.
+const CALM_DOWN_SECONDS:    f32         = 5.0;
+
+/*
+* Every enemy goes through a state machine, one tick at a time:
+*
+*   idle   --(pause over, with a patrol)-->  patrol  --(patrol point reached)-->  idle
+*   any    --(player noticed)-->             chase   --(touching the player)-->   attack
+*   chase  --(player not found where last noticed)-->  idle
+*   any    --(player noticed, low on health)-->  flee  --(player not noticed for a while)-->  idle
+*   any    --(out of health)-->  dead, see `systems::run_deaths`
+*
+* Angles are clockwise from the top of the map, like the camera's.
+*/
+pub fn run_ai(entities: &mut Entities, level: &dyn Level, pathfinder: &mut Pathfinder, player: Entity, seconds: f32) {
+    let (target, target_radius) = match (entities.transforms.get(player), entities.colliders.get(player)) {
+        (Some(transform), collider) => (transform.position, collider.map_or(0.0, |collider| collider.radius)),
+        (None, _)                   => return,
+    };
+
+    let enemies: Vec<_> = entities.ais.iter().map(|(entity, ai)| (entity, *ai)).collect();
+    let mut damage      = 0.0;
+
+    for (entity, ai) in enemies {
+        let (mut transform, mut state) = match (entities.transforms.get(entity), entities.ai_states.get(entity)) {
+            (Some(transform), Some(state)) if state.mode != AiMode::Dead => (*transform, *state),
+            _                                                            => continue,
+        };
+
+        let radius      = entities.colliders.get(entity).map_or(0.0, |collider| collider.radius);
+        let contact     = radius + target_radius;
+        let is_scared   = entities.healths.get(entity).is_some_and(|health| health.current < health.maximum * ai.flee_below);
+        let is_noticing = notices(&ai, &state, &transform, target, level);
+        let is_touching = transform.position.distance(target) - contact <= systems::TOUCH_MARGIN;
+
+        if is_noticing {
+            state.target         = Some(target);
+            state.unseen_seconds = 0.0;
+        } else {
+            state.unseen_seconds += seconds;
+        }
+
+        let mode = choose_mode(&state, transform.position, is_noticing, is_scared, is_touching, entities.patrols.contains(entity));
+
+        if mode != state.mode {
+            state.mode         = mode;
+            state.mode_seconds = 0.0;
+        } else {
+            state.mode_seconds += seconds;
+        }
+
+        let step = ai.speed * seconds;
+
+        match state.mode {
+            AiMode::Idle | AiMode::Dead => (),
+
+            AiMode::Patrol => match entities.patrols.get_mut(entity) {
+                Some(patrol) if !patrol.points.is_empty() => {
+                    let point = patrol.points[patrol.next % patrol.points.len()];
+
+                    if transform.position.distance(point) <= ARRIVAL_DISTANCE {
+                        patrol.next        = (patrol.next + 1) % patrol.points.len();
+                        state.mode         = AiMode::Idle;
+                        state.mode_seconds = 0.0;
+                    } else if let Some((motion, _)) = find_way(level, pathfinder, transform.position, point) {
+                        walk(level, &mut transform, motion, step.min(motion.length()), radius);
+                    }
+                },
+
+                _ => state.mode = AiMode::Idle,
+            },
+
+            // Enemies stop when touching the player, not when their centres meet.
+            AiMode::Chase => match state.target.and_then(|goal| find_way(level, pathfinder, transform.position, goal)) {
+                Some((motion, true)) if is_noticing => walk(level, &mut transform, motion, step.min(motion.length() - contact), radius),
+                Some((motion, _))                   => walk(level, &mut transform, motion, step.min(motion.length()), radius),
+                None                                => state.target = None,
+            },
+
+            AiMode::Attack => {
+                transform.angle  = world::get_clockwise_angle(target - transform.position);
+                damage          += ai.touch_damage * seconds;
+            },
+
+            AiMode::Flee => {
+                let motion = find_way_away(level, pathfinder, transform.position, target);
+
+                walk(level, &mut transform, motion, step, radius);
+            },
+        }
+
+        entities.transforms.insert(entity, transform);
+        entities.ai_states.insert(entity, state);
+    }
+
+    if let Some(health) = entities.healths.get_mut(player) {
+        health.current -= damage;
+    }
+}
+
+/*
+* The player is heard through walls. They are seen when within the view cone, with nothing opaque in between:
+* the line of sight is the DDA walk the renderer casts its rays with. Enemies on the alert look all around.
+*/
+fn notices(ai: &Ai, state: &AiState, transform: &Transform, target: WorldPosition, level: &dyn Level) -> bool {
+    let to_target = target - transform.position;
+    let distance  = to_target.length();
+
+    if distance <= ai.hearing_range {
+        return true;
+    }
+
+    if distance > ai.sight_range {
+        return false;
+    }
+
+    let is_alert  = matches!(state.mode, AiMode::Chase | AiMode::Attack | AiMode::Flee);
+    let facing    = world::rotate_clockwise(world::TOP_UNIT_VECTOR, transform.angle);
+    let in_cone   = is_alert || ai.view_cone >= TAU || facing.dot(to_target) >= distance * (ai.view_cone / 2.0).cos();
+
+    in_cone && level.line_of_sight(transform.position, target)
+}
+
+// See `run_ai`. `state` is that of the previous tick, with what was just noticed.
+fn choose_mode(state: &AiState, position: WorldPosition, is_noticing: bool, is_scared: bool, is_touching: bool, has_patrol: bool) -> AiMode {
+    let is_searched = state.unseen_seconds >= SEARCH_SECONDS && state.target.filter(|target| target.distance(position) > SEARCH_DISTANCE).is_none();
+
+    match state.mode {
+        AiMode::Dead                                                             => AiMode::Dead,
+        _ if is_noticing && is_scared                                            => AiMode::Flee,
+        _ if is_noticing && is_touching                                          => AiMode::Attack,
+        _ if is_noticing                                                         => AiMode::Chase,
+        AiMode::Flee if state.unseen_seconds < CALM_DOWN_SECONDS                 => AiMode::Flee,
+        AiMode::Chase | AiMode::Attack if !is_searched && !is_scared             => AiMode::Chase,
+        AiMode::Idle if has_patrol && state.mode_seconds >= PATROL_PAUSE_SECONDS => AiMode::Patrol,
+        AiMode::Patrol                                                           => AiMode::Patrol,
+        _                                                                        => AiMode::Idle,
+    }
+}
+
+/*
+* Which way to walk to get to `goal`, and whether it is straight there. Enemies walk straight when nothing
+* solid is in the way, and follow a path over the tiles otherwise, cutting across it wherever they can. None
+* when there is no way, or when the level has no tiles to find it over.
+*/
+fn find_way(level: &dyn Level, pathfinder: &mut Pathfinder, position: WorldPosition, goal: WorldPosition) -> Option<(WorldVector, bool)> {
+    if level.trace_ray(position, goal - position, position.distance(goal), &|tile| tile.blocks_movement()).is_none() {
+        return Some((goal - position, true));
+    }
+
+    let map  = level.get_tile_map()?;
+    let path = pathfinder.find_path(map, get_cell(map, position)?, get_cell(map, goal)?, PATH_RULES)?;
+    let next = path.iter().skip(1).rev().find(|cell| path::is_straight_way(map, position, **cell, &PATH_RULES)).or(path.get(1));
+
+    match next {
+        Some(next) => Some((path::get_waypoint(*next) - position, false)),
+        None       => Some((goal - position, true)),
+    }
+}
+
+// Towards whatever cell is furthest from the player, or straight away from them when there is none.
+fn find_way_away(level: &dyn Level, pathfinder: &mut Pathfinder, position: WorldPosition, target: WorldPosition) -> WorldVector {
+    let away = level.get_tile_map().and_then(|map| {
+        let field = pathfinder.get_flow_field(map, get_cell(map, target)?, PATH_RULES);
+
+        field.get_farther(map, get_cell(map, position)?)
+    });
+
+    match away {
+        Some(cell) => path::get_waypoint(cell) - position,
+        None       => position - target,
+    }
+}
+
+// Walks `distance` along `motion`, facing where they go.
+fn walk(level: &dyn Level, transform: &mut Transform, motion: WorldVector, distance: WorldLength, radius: WorldLength) {
+    let direction = motion.normalize_or_zero();
+
+    if direction == WorldVector::ZERO || distance <= 0.0 {
+        return;
+    }
+
+    let movement = level.move_through(transform.position, direction * distance, radius);
+
+    transform.position = movement.position;
+    transform.angle    = world::get_clockwise_angle(direction) + movement.rotation;
+}
+
+fn get_cell(map: &Map, position: WorldPosition) -> Option<MapPosition> {
+    let cell = map::world_position_to_signed_map_position(position);
+
+    map.contains(&cell).then_some(MapPosition { x: cell.x as MapCoordinate, y: cell.y as MapCoordinate })
+}
@@ -0,0 +1,42 @@
+use std::{fmt, fs, path::{Path, PathBuf}};
+
+use serde::de::DeserializeOwned;
+
+// Where materials, maps and textures are looked for, relative to the working directory.
+pub const ASSETS_DIRECTORY: &str = "assets";
+
+// Something went wrong while loading a file. Messages are meant to be shown to whoever wrote the file.
+#[derive(Debug)]
+pub enum LoadError {
+    Io      { path: PathBuf, error: std::io::Error },
+    Parse   { path: PathBuf, message: String },
+    Invalid { path: PathBuf, message: String },
+}
+
+impl LoadError {
+    pub fn invalid<M: Into<String>>(path: &Path, message: M) -> LoadError {
+        LoadError::Invalid { path: path.to_owned(), message: message.into() }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io      { path, error }   => write!(formatter, "Could not read {}: {}", path.display(), error),
+            LoadError::Parse   { path, message } => write!(formatter, "Could not parse {}: {}", path.display(), message),
+            LoadError::Invalid { path, message } => write!(formatter, "Invalid {}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+pub fn get_asset_path(relative_path: &str) -> PathBuf {
+    Path::new(ASSETS_DIRECTORY).join(relative_path)
+}
+
+pub fn read_toml<T: DeserializeOwned>(path: &Path) -> Result<T, LoadError> {
+    let text = fs::read_to_string(path).map_err(|error| LoadError::Io { path: path.to_owned(), error })?;
+
+    toml::from_str(&text).map_err(|error| LoadError::Parse { path: path.to_owned(), message: error.to_string() })
+}
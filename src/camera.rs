@@ -1,6 +1,17 @@
+use std::f32::consts::TAU;
+
 use crate::{world::{WorldPosition, WorldAngle, WorldVector, rotate_clockwise, self, WorldDirection}};
 
+// In screen heights, see `Camera::get_horizon`.
+pub const MAX_PITCH:             f32        = 0.5;
+// Horizontal, the camera segment being 0.2 long on each side of a view direction of length 1.
+pub const DEFAULT_FIELD_OF_VIEW: WorldAngle = 0.394_791_1;
+
 /*
+* Positions and angles follow the world coordinate system (see world.rs). At a rotation of 0, the player
+* looks towards the top of the map, and the camera segment extends towards the right of the map, which
+* is the right of the screen.
+*
 *       Cam segment
 *    < -------------- >
 *      \    ^      /
@@ -10,11 +21,13 @@ use crate::{world::{WorldPosition, WorldAngle, WorldVector, rotate_clockwise, se
 *          V
 *        Player
 */
+#[derive(Clone, Debug)]
 pub struct Camera {
         field_of_view_ratio:     f32,
         rotation:                WorldAngle,
         cached_direction:        WorldVector,
         cached_camera_vector:    WorldVector,
+        pitch:                   f32,
     pub position:                WorldPosition,
 }
 
@@ -32,17 +45,48 @@ impl Camera {
             field_of_view_ratio,
             cached_camera_vector,
 
-            rotation: direction_angle_radians,
+            pitch:    0.0,
+            rotation: direction_angle_radians.rem_euclid(TAU),
         }
     }
 
+    // Horizontal, between 0 and π. Vertically, a wall 1 away is always as high as the screen.
+    pub fn set_field_of_view(&mut self, field_of_view: WorldAngle) {
+        self.field_of_view_ratio  = (field_of_view / 2.0).tan();
+        self.cached_camera_vector = Camera::get_camera_vector(self.field_of_view_ratio, self.rotation);
+    }
+
     pub fn rotate_clockwise(&mut self, direction_angle_radians: WorldAngle) {
-        self.rotation             += direction_angle_radians;
+        self.rotation              = (self.rotation + direction_angle_radians).rem_euclid(TAU);
 
         self.cached_direction      = Camera::get_direction(self.rotation);
         self.cached_camera_vector  = Camera::get_camera_vector(self.field_of_view_ratio, self.rotation);
     }
 
+    /*
+    * Looking up and down is faked by moving the horizon on the screen (y-shearing), which keeps walls
+    * vertical. The pitch is the offset of the horizon from the middle of the screen, in screen heights.
+    */
+    pub fn look_up(&mut self, screen_heights: f32) {
+        self.pitch = num::clamp(self.pitch + screen_heights, -MAX_PITCH, MAX_PITCH);
+    }
+
+    pub fn get_horizon(&self, screen_height: f32) -> f32 {
+        screen_height * (0.5 + self.pitch)
+    }
+
+    pub fn get_rotation(&self) -> WorldAngle {
+        self.rotation
+    }
+
+    pub fn get_pitch(&self) -> f32 {
+        self.pitch
+    }
+
+    pub fn get_view_direction(&self) -> WorldDirection {
+        self.cached_direction
+    }
+
     pub fn get_ray_direction(&self, x: f32, width: f32) -> WorldDirection {
         let camera_vector_scale = (x / width - 0.5) * 2.0;
 
@@ -0,0 +1,481 @@
+use std::{collections::HashMap, f32::consts::TAU, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{asset::{self, LoadError}, sprite::{self, Sprite, SpriteTextureId}, weapon::{self, WeaponId, WEAPONS}, world::{WorldAngle, WorldLength, WorldPosition, WorldVector}};
+
+/*
+* Game objects (the player, enemies, items, projectiles...) are entities: plain handles, whose data lives in
+* one component storage per kind of component. Systems (see systems.rs) go through the storages they care
+* about, so that new behaviours only need new components and systems.
+*
+* Handles carry a generation, bumped whenever their slot is reused, so that a handle kept after its entity
+* was despawned does not silently point to whatever was spawned in its place.
+*/
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct Entity {
+    index:      u32,
+    generation: u32,
+}
+
+// Components of one kind, indexed by entity slot.
+pub struct Components<T> {
+    slots: Vec<Option<(u32, T)>>,
+}
+
+// Where an entity stands, and which way it looks.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Transform {
+    pub position: WorldPosition,
+    pub angle:    WorldAngle,
+}
+
+// What the sprite pass draws for an entity, see `sprite::Sprite`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Billboard {
+    #[serde(serialize_with = "serialize_sprite_texture", deserialize_with = "deserialize_sprite_texture")]
+    pub texture: SpriteTextureId,
+    pub width:   WorldLength,
+    pub height:  WorldLength,
+}
+
+// Walls stop colliders `radius` away from them. Solid colliders also keep other colliders away.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Collider {
+    pub radius:   WorldLength,
+    #[serde(default)]
+    pub is_solid: bool,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Health {
+    pub current: f32,
+    pub maximum: f32,
+}
+
+/*
+* Enemy behaviour, see ai.rs. Enemies notice the player when seeing them (within `sight_range`, within their
+* view cone, with nothing in between) or hearing them (within `hearing_range`, through walls). They then chase
+* them, and hurt them while touching them. Below `flee_below` of their health, they run away instead.
+*/
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ai {
+    // In tiles per second.
+    pub speed:         f32,
+    pub sight_range:   WorldLength,
+    // Health taken from the player per second.
+    pub touch_damage:  f32,
+    // Whole width of what they see, in radians. All around by default.
+    #[serde(default = "get_full_view_cone")]
+    pub view_cone:     WorldAngle,
+    #[serde(default)]
+    pub hearing_range: WorldLength,
+    // Fraction of their maximum health, 0 never to flee.
+    #[serde(default)]
+    pub flee_below:    f32,
+    // What is left of them when they die. They disappear without one.
+    #[serde(default, serialize_with = "serialize_optional_sprite_texture", deserialize_with = "deserialize_optional_sprite_texture")]
+    pub corpse:        Option<SpriteTextureId>,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AiMode {
+    #[default]
+    Idle,
+    Patrol,
+    Chase,
+    Attack,
+    Flee,
+    Dead,
+}
+
+// Where an enemy is in its state machine, see ai.rs.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AiState {
+    pub mode:           AiMode,
+    // Seconds since the mode last changed.
+    pub mode_seconds:   f32,
+    // Where the player was last noticed.
+    pub target:         Option<WorldPosition>,
+    // Seconds since the player was last noticed.
+    pub unseen_seconds: f32,
+}
+
+// Places an enemy walks between, in order, pausing at each, when it has nothing better to do.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Patrol {
+    pub points: Vec<WorldPosition>,
+    // Index of the point walked to.
+    pub next:   usize,
+}
+
+// Taken by the player when walking over it.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pickup {
+    #[serde(default)]
+    pub heal: f32,
+    #[serde(default)]
+    pub ammo: Option<Ammo>,
+}
+
+// Ammo for one weapon, see weapon.rs.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ammo {
+    #[serde(serialize_with = "weapon::serialize_weapon", deserialize_with = "weapon::deserialize_weapon")]
+    pub weapon: WeaponId,
+    pub amount: u32,
+}
+
+// Weapons carried, all of them, with their ammo by weapon ID. See weapon.rs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Arsenal {
+    #[serde(serialize_with = "weapon::serialize_weapon", deserialize_with = "weapon::deserialize_weapon")]
+    pub selected: WeaponId,
+    pub ammo:     Vec<u32>,
+    // Seconds before the selected weapon can fire again.
+    pub cooldown: f32,
+}
+
+// Flies straight until it hits something, see weapon.rs.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Projectile {
+    // In tiles per second.
+    pub velocity: WorldVector,
+    pub damage:   f32,
+    // Seconds before it is gone, whatever it hit.
+    pub lifetime: f32,
+}
+
+pub struct Entities {
+        generations: Vec<u32>,
+        is_alive:    Vec<bool>,
+        free_slots:  Vec<u32>,
+    pub transforms:  Components<Transform>,
+    pub billboards:  Components<Billboard>,
+    pub colliders:   Components<Collider>,
+    pub healths:     Components<Health>,
+    pub ais:         Components<Ai>,
+    pub ai_states:   Components<AiState>,
+    pub patrols:     Components<Patrol>,
+    pub pickups:     Components<Pickup>,
+    pub arsenals:    Components<Arsenal>,
+    pub projectiles: Components<Projectile>,
+}
+
+// Entity to spawn when a level starts, made from the template of that name.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EntitySpawn {
+    pub template: String,
+    pub position: WorldPosition,
+    #[serde(default)]
+    pub angle:    WorldAngle,
+    // Patrol points of enemies, see `Patrol`.
+    #[serde(default)]
+    pub patrol:   Vec<WorldPosition>,
+}
+
+/*
+* Components given to the entities made from a template, except for their transform, which comes from where
+* they are spawned. Templates are read from a TOML file (see assets/entities.toml):
+*
+*   [templates.barrel]
+*   billboard = { texture = "barrel", width = 0.6, height = 0.7 }
+*   collider  = { radius = 0.25, is_solid = true }
+*   health    = 30.0
+*/
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EntityTemplate {
+    pub billboard: Option<Billboard>,
+    pub collider:  Option<Collider>,
+    pub health:    Option<f32>,
+    pub ai:        Option<Ai>,
+    pub pickup:    Option<Pickup>,
+}
+
+pub struct EntityTemplates {
+    templates: HashMap<String, EntityTemplate>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TemplatesFile {
+    templates: HashMap<String, EntityTemplate>,
+}
+
+impl Entity {
+    // Slot of the entity, shared with whatever is spawned there after it.
+    pub fn get_index(&self) -> u32 {
+        self.index
+    }
+}
+
+impl<T> Components<T> {
+    pub fn new() -> Components<T> {
+        Components { slots: vec![] }
+    }
+
+    // Replaces the component the entity had, if any.
+    pub fn insert(&mut self, entity: Entity, component: T) {
+        let index = entity.index as usize;
+
+        if index >= self.slots.len() {
+            self.slots.resize_with(index + 1, || None);
+        }
+
+        self.slots[index] = Some((entity.generation, component));
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        self.get(entity)?;
+
+        self.slots[entity.index as usize].take().map(|(_, component)| component)
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        match self.slots.get(entity.index as usize) {
+            Some(Some((generation, component))) if *generation == entity.generation => Some(component),
+            _                                                                        => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        match self.slots.get_mut(entity.index as usize) {
+            Some(Some((generation, component))) if *generation == entity.generation => Some(component),
+            _                                                                        => None,
+        }
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.get(entity).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.as_ref().map(|(generation, component)| (Entity { index: index as u32, generation: *generation }, component))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.slots.iter_mut().enumerate().filter_map(|(index, slot)| {
+            slot.as_mut().map(|(generation, component)| (Entity { index: index as u32, generation: *generation }, component))
+        })
+    }
+}
+
+impl<T> Default for Components<T> {
+    fn default() -> Components<T> {
+        Components::new()
+    }
+}
+
+impl Health {
+    pub fn new(maximum: f32) -> Health {
+        Health { maximum, current: maximum }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+}
+
+impl Arsenal {
+    // What the player starts with: every weapon, the first one in hand.
+    pub fn new() -> Arsenal {
+        Arsenal { selected: 0, ammo: WEAPONS.iter().map(|weapon| weapon.start_ammo).collect(), cooldown: 0.0 }
+    }
+
+    // How far from ready to fire the selected weapon is, from 0 (ready) to 1 (it just fired).
+    pub fn get_recoil(&self) -> f32 {
+        (self.cooldown * WEAPONS[self.selected].fire_rate).clamp(0.0, 1.0)
+    }
+}
+
+impl Default for Arsenal {
+    fn default() -> Arsenal {
+        Arsenal::new()
+    }
+}
+
+impl Entities {
+    pub fn new() -> Entities {
+        Entities {
+            generations: vec![],
+            is_alive:    vec![],
+            free_slots:  vec![],
+            transforms:  Components::new(),
+            billboards:  Components::new(),
+            colliders:   Components::new(),
+            healths:     Components::new(),
+            ais:         Components::new(),
+            ai_states:   Components::new(),
+            patrols:     Components::new(),
+            pickups:     Components::new(),
+            arsenals:    Components::new(),
+            projectiles: Components::new(),
+        }
+    }
+
+    // New entity, without any component.
+    pub fn spawn(&mut self) -> Entity {
+        match self.free_slots.pop() {
+            Some(index) => {
+                self.is_alive[index as usize] = true;
+
+                Entity { index, generation: self.generations[index as usize] }
+            },
+            None        => {
+                self.generations.push(0);
+                self.is_alive.push(true);
+
+                Entity { index: (self.generations.len() - 1) as u32, generation: 0 }
+            },
+        }
+    }
+
+    // New entity with the components of `template`.
+    pub fn spawn_from_template(&mut self, template: &EntityTemplate, position: WorldPosition, angle: WorldAngle) -> Entity {
+        let entity = self.spawn();
+
+        self.transforms.insert(entity, Transform { position, angle });
+
+        if let Some(billboard) = template.billboard { self.billboards.insert(entity, billboard); }
+        if let Some(collider)  = template.collider  { self.colliders.insert(entity, collider); }
+        if let Some(health)    = template.health    { self.healths.insert(entity, Health::new(health)); }
+        if let Some(ai)        = template.ai        { self.ais.insert(entity, ai); self.ai_states.insert(entity, AiState::default()); }
+        if let Some(pickup)    = template.pickup    { self.pickups.insert(entity, pickup); }
+
+        entity
+    }
+
+    // Removes the entity and all of its components. Despawning twice does nothing.
+    pub fn despawn(&mut self, entity: Entity) {
+        if !self.is_alive(entity) {
+            return;
+        }
+
+        self.transforms.remove(entity);
+        self.billboards.remove(entity);
+        self.colliders.remove(entity);
+        self.healths.remove(entity);
+        self.ais.remove(entity);
+        self.ai_states.remove(entity);
+        self.patrols.remove(entity);
+        self.pickups.remove(entity);
+        self.arsenals.remove(entity);
+        self.projectiles.remove(entity);
+
+        let index = entity.index as usize;
+
+        self.is_alive[index]     = false;
+        self.generations[index] += 1;
+        self.free_slots.push(entity.index);
+    }
+
+    // Every entity alive, in slot order.
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self
+        .is_alive
+        .iter()
+        .enumerate()
+        .filter(|(_, is_alive)| **is_alive)
+        .map(|(index, _)| Entity { index: index as u32, generation: self.generations[index] })
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        let index = entity.index as usize;
+
+        index < self.is_alive.len() && self.is_alive[index] && self.generations[index] == entity.generation
+    }
+
+    pub fn get_entity_sprites(&self) -> Vec<(Entity, Sprite)> {
+        self
+        .billboards
+        .iter()
+        .filter_map(|(entity, billboard)| {
+            self.transforms.get(entity).map(|transform| (entity, Sprite::new(transform.position, billboard.texture, billboard.width, billboard.height, transform.angle)))
+        })
+        .collect()
+    }
+}
+
+impl Default for Entities {
+    fn default() -> Entities {
+        Entities::new()
+    }
+}
+
+impl EntityTemplates {
+    pub fn load(path: &Path) -> Result<EntityTemplates, LoadError> {
+        let file: TemplatesFile = asset::read_toml(path)?;
+
+        Ok(EntityTemplates { templates: file.templates })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&EntityTemplate> {
+        self.templates.get(name)
+    }
+
+    /*
+    * Entities of the level, made from the templates. Fails on the first spawn whose template is unknown, or
+    * that has a patrol without being an enemy.
+    */
+    pub fn spawn_all(&self, entities: &mut Entities, spawns: &[EntitySpawn]) -> Result<Vec<Entity>, String> {
+        spawns
+        .iter()
+        .map(|spawn| {
+            let template = self.get(&spawn.template).ok_or_else(|| format!("there is no entity template named {:?}", spawn.template))?;
+
+            if !spawn.patrol.is_empty() && template.ai.is_none() {
+                return Err(format!("{:?} at ({}, {}) has a patrol, but no ai", spawn.template, spawn.position.x, spawn.position.y));
+            }
+
+            let entity   = entities.spawn_from_template(template, spawn.position, spawn.angle);
+
+            if !spawn.patrol.is_empty() {
+                entities.patrols.insert(entity, Patrol { points: spawn.patrol.clone(), next: 0 });
+            }
+
+            Ok(entity)
+        })
+        .collect()
+    }
+}
+
+fn get_full_view_cone() -> WorldAngle {
+    TAU
+}
+
+fn serialize_sprite_texture<S: serde::Serializer>(texture: &SpriteTextureId, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(sprite::get_sprite_texture_name(*texture))
+}
+
+fn deserialize_sprite_texture<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<SpriteTextureId, D::Error> {
+    let name = String::deserialize(deserializer)?;
+
+    sprite::find_sprite_texture(&name).ok_or_else(|| serde::de::Error::custom(format!("there is no sprite texture named {:?}", name)))
+}
+
+fn serialize_optional_sprite_texture<S: serde::Serializer>(texture: &Option<SpriteTextureId>, serializer: S) -> Result<S::Ok, S::Error> {
+    match texture {
+        Some(texture) => serializer.serialize_some(sprite::get_sprite_texture_name(*texture)),
+        None          => serializer.serialize_none(),
+    }
+}
+
+fn deserialize_optional_sprite_texture<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<SpriteTextureId>, D::Error> {
+    let name = Option::<String>::deserialize(deserializer)?;
+
+    name.map(|name| sprite::find_sprite_texture(&name).ok_or_else(|| serde::de::Error::custom(format!("there is no sprite texture named {:?}", name)))).transpose()
+}
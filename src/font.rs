@@ -0,0 +1,268 @@
+use std::path::Path;
+
+use ggez::graphics::Color;
+
+use crate::{asset::LoadError, raycaster::Raycaster, texture::Texture};
+
+// Font sheets are grids of this many glyphs across and down, see `Font::load`.
+const SHEET_COLUMNS:        u32  = 16;
+const SHEET_ROWS:           u32  = 6;
+// Every font has a glyph for each of these, in ASCII order.
+const FIRST_CHARACTER:      char = ' ';
+const LAST_CHARACTER:       char = '~';
+// Drawn for characters that fonts have no glyph for.
+const MISSING_CHARACTER:    char = '?';
+// The built-in glyphs are 3 pixels wide and 5 high, in cells one pixel wider and higher to space them.
+const BUILTIN_GLYPH_WIDTH:  u16  = 4;
+const BUILTIN_GLYPH_HEIGHT: u16  = 6;
+
+/*
+* Glyphs of the built-in font. Each row is 3 bits, the highest being on the left. Lowercase letters are
+* drawn as uppercase ones, other characters missing here as a question mark.
+*/
+const BUILTIN_GLYPHS: [(char, [u8; 5]); 49] = [
+    (' ',  [0b000, 0b000, 0b000, 0b000, 0b000]),
+    ('0',  [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1',  [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2',  [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3',  [0b111, 0b001, 0b011, 0b001, 0b111]),
+    ('4',  [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5',  [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6',  [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7',  [0b111, 0b001, 0b001, 0b010, 0b010]),
+    ('8',  [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9',  [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('A',  [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B',  [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C',  [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D',  [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E',  [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F',  [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G',  [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H',  [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I',  [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J',  [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K',  [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L',  [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M',  [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N',  [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O',  [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P',  [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q',  [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R',  [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S',  [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T',  [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U',  [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V',  [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W',  [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X',  [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y',  [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z',  [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('.',  [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (',',  [0b000, 0b000, 0b000, 0b010, 0b100]),
+    (':',  [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('!',  [0b010, 0b010, 0b010, 0b000, 0b010]),
+    ('?',  [0b110, 0b001, 0b010, 0b000, 0b010]),
+    ('-',  [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('+',  [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('/',  [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('%',  [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('\'', [0b010, 0b010, 0b000, 0b000, 0b000]),
+    ('(',  [0b001, 0b010, 0b010, 0b010, 0b001]),
+    (')',  [0b100, 0b010, 0b010, 0b010, 0b100]),
+];
+
+// Where lines go relative to the point they are drawn at.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Alignment {
+    // The point is on the left of the lines.
+    Left,
+    Centre,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TextStyle {
+    pub color:     Color,
+    // Screen pixels per font pixel, each way.
+    pub scale:     u16,
+    pub alignment: Alignment,
+    // Drawn behind the text, a little down and to the right, so that it can be read over anything.
+    pub shadow:    Option<Color>,
+}
+
+/*
+* Fixed-width bitmap font, drawn into the framebuffer. Glyphs are cells of the same size, spacing included,
+* so that text is as wide as its glyph count times the glyph width. Pixels are either ink or nothing.
+*/
+pub struct Font {
+    glyph_width:  u16,
+    glyph_height: u16,
+    // Ink of each glyph, row by row, from `FIRST_CHARACTER` to `LAST_CHARACTER`.
+    glyphs:       Vec<Vec<bool>>,
+}
+
+impl TextStyle {
+    // Left-aligned, without a shadow.
+    pub fn new(color: Color, scale: u16) -> TextStyle {
+        TextStyle { color, scale, alignment: Alignment::Left, shadow: None }
+    }
+}
+
+impl Font {
+    // Small and blocky, but always there.
+    pub fn builtin() -> Font {
+        let glyphs = (FIRST_CHARACTER ..= LAST_CHARACTER).map(|character| {
+            let find = |character: char| BUILTIN_GLYPHS.iter().find(|(glyph, _)| *glyph == character).map(|(_, rows)| rows);
+            let rows = find(character.to_ascii_uppercase()).or_else(|| find(MISSING_CHARACTER)).unwrap();
+
+            (0 .. BUILTIN_GLYPH_HEIGHT)
+            .flat_map(|y| (0 .. BUILTIN_GLYPH_WIDTH).map(move |x| (x, y)))
+            .map(|(x, y)| x < 3 && y < 5 && rows[y as usize] & (0b100 >> x) != 0)
+            .collect()
+        })
+        .collect();
+
+        Font { glyph_width: BUILTIN_GLYPH_WIDTH, glyph_height: BUILTIN_GLYPH_HEIGHT, glyphs }
+    }
+
+    /*
+    * Font sheets are images of 16 glyphs across and 6 down, from space to tilde in ASCII order, their cells
+    * splitting the image evenly. Pixels at least half opaque are ink, the others are left out.
+    */
+    pub fn load(path: &Path) -> Result<Font, LoadError> {
+        let sheet = Texture::load(path)?;
+
+        if sheet.width == 0 || sheet.height == 0 || sheet.width % SHEET_COLUMNS != 0 || sheet.height % SHEET_ROWS != 0 {
+            return Err(LoadError::invalid(path, format!("font sheets are grids of {}x{} glyphs, {}x{} pixels cannot be split into them", SHEET_COLUMNS, SHEET_ROWS, sheet.width, sheet.height)));
+        }
+
+        let glyph_width  = sheet.width / SHEET_COLUMNS;
+        let glyph_height = sheet.height / SHEET_ROWS;
+        let is_ink       = |x: u32, y: u32| sheet.sample(((x as f32) + 0.5) / (sheet.width as f32), ((y as f32) + 0.5) / (sheet.height as f32)).a >= 0.5;
+
+        let glyphs = (0 .. SHEET_COLUMNS * SHEET_ROWS).map(|index| {
+            let left = (index % SHEET_COLUMNS) * glyph_width;
+            let top  = (index / SHEET_COLUMNS) * glyph_height;
+
+            (0 .. glyph_height)
+            .flat_map(|y| (0 .. glyph_width).map(move |x| (x, y)))
+            .map(|(x, y)| is_ink(left + x, top + y))
+            .collect()
+        })
+        .take(get_glyph_count())
+        .collect();
+
+        Ok(Font { glyph_width: glyph_width as u16, glyph_height: glyph_height as u16, glyphs })
+    }
+
+    // Width of one line, in font pixels.
+    pub fn measure(&self, line: &str) -> u16 {
+        (line.chars().count() as u16).saturating_mul(self.glyph_width)
+    }
+
+    /*
+    * Splits `text` into lines at most `width` font pixels wide, at spaces where possible and within words
+    * that are too long for a line. Line breaks in the text are kept. Spaces where lines are split are
+    * dropped.
+    */
+    pub fn wrap<'a>(&self, text: &'a str, width: u16) -> Vec<&'a str> {
+        let columns   = ((width / self.glyph_width) as usize).max(1);
+        let mut lines = vec![];
+
+        for paragraph in text.split('\n') {
+            let mut rest = paragraph.trim_end();
+
+            while rest.chars().count() > columns {
+                // Where the line would end without spaces, and where it ends at the last space before that.
+                let limit = rest.char_indices().nth(columns).map_or(rest.len(), |(index, _)| index);
+                let space = if rest[limit ..].starts_with(' ') { Some(limit) } else { rest[.. limit].rfind(' ').filter(|space| *space > 0) };
+                let end   = space.unwrap_or(limit);
+
+                lines.push(rest[.. end].trim_end());
+
+                rest = rest[end ..].trim_start_matches(' ');
+            }
+
+            lines.push(rest);
+        }
+
+        lines
+    }
+
+    /*
+    * Draws `text` at `x` and `y`, in screen pixels, the top of its first line being at `y`. Each line is
+    * aligned on `x` on its own. Whatever falls outside of the screen is cut.
+    */
+    pub fn draw(&self, raycaster: &mut Raycaster, text: &str, x: i32, y: i32, style: &TextStyle) {
+        for (index, line) in text.split('\n').enumerate() {
+            self.draw_line(raycaster, line, x, y + (index as i32) * self.get_line_advance(style), style);
+        }
+    }
+
+    // Wrapped within `width` screen pixels, see `wrap`. Returns how high the text is, in screen pixels.
+    pub fn draw_wrapped(&self, raycaster: &mut Raycaster, text: &str, x: i32, y: i32, width: u16, style: &TextStyle) -> i32 {
+        let lines = self.wrap(text, width / style.scale.max(1));
+
+        for (index, line) in lines.iter().enumerate() {
+            self.draw_line(raycaster, line, x, y + (index as i32) * self.get_line_advance(style), style);
+        }
+
+        (lines.len() as i32) * self.get_line_advance(style)
+    }
+
+    // In screen pixels.
+    pub fn get_line_advance(&self, style: &TextStyle) -> i32 {
+        (self.glyph_height as i32) * (style.scale.max(1) as i32)
+    }
+
+    fn draw_line(&self, raycaster: &mut Raycaster, line: &str, x: i32, y: i32, style: &TextStyle) {
+        let scale = style.scale.max(1) as i32;
+        let width = (self.measure(line) as i32) * scale;
+
+        let left  = match style.alignment {
+            Alignment::Left   => x,
+            Alignment::Centre => x - width / 2,
+            Alignment::Right  => x - width,
+        };
+
+        if let Some(shadow) = style.shadow {
+            let offset = (scale / 2).max(1);
+
+            self.draw_glyphs(raycaster, line, left + offset, y + offset, scale, &shadow);
+        }
+
+        self.draw_glyphs(raycaster, line, left, y, scale, &style.color);
+    }
+
+    fn draw_glyphs(&self, raycaster: &mut Raycaster, line: &str, left: i32, top: i32, scale: i32, color: &Color) {
+        let glyph_width = self.glyph_width as usize;
+
+        for (index, character) in line.chars().enumerate() {
+            let glyph_left = left + (index as i32) * (glyph_width as i32) * scale;
+
+            for (pixel, _) in self.get_glyph(character).iter().enumerate().filter(|(_, is_ink)| **is_ink) {
+                let x = glyph_left + ((pixel % glyph_width) as i32) * scale;
+                let y = top + ((pixel / glyph_width) as i32) * scale;
+
+                raycaster.fill_rectangle(x, y, scale, scale, color);
+            }
+        }
+    }
+
+    fn get_glyph(&self, character: char) -> &[bool] {
+        let index = |character: char| (character as usize).checked_sub(FIRST_CHARACTER as usize).filter(|index| *index < self.glyphs.len());
+
+        &self.glyphs[index(character).or_else(|| index(MISSING_CHARACTER)).unwrap_or(0)]
+    }
+}
+
+impl Default for Font {
+    fn default() -> Font {
+        Font::builtin()
+    }
+}
+
+fn get_glyph_count() -> usize {
+    (LAST_CHARACTER as usize) - (FIRST_CHARACTER as usize) + 1
+}
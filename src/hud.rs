@@ -0,0 +1,157 @@
+use ggez::graphics::Color;
+
+use crate::{entity::Health, font::{Alignment, Font, TextStyle}, raycaster::Raycaster, weapon::{WeaponId, WEAPONS}};
+
+// The HUD is laid out for screens this many pixels high, and scaled by whole pixels for taller ones.
+const HUD_REFERENCE_HEIGHT: u16   = 150;
+// In HUD pixels, see `HudLayout`.
+const HUD_MARGIN:           u16   = 3;
+const CROSSHAIR_GAP:        u16   = 1;
+const CROSSHAIR_ARM:        u16   = 2;
+// Health turns red below this share of its maximum. Ammo does when there is none left.
+const LOW_HEALTH:           f32   = 0.25;
+const MESSAGE_SECONDS:      f32   = 3.0;
+// Share of the width of the screen messages are wrapped to, leaving room for the minimap and the frame rate.
+const MESSAGE_WIDTH:        f32   = 0.6;
+// Older messages go away first when there are more.
+const MAX_MESSAGES:         usize = 4;
+
+// What the HUD shows of the player, updated every frame. Nothing is shown of what is missing.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HudStatus {
+    pub health: Option<Health>,
+    // The weapon in hand, and its ammo.
+    pub weapon: Option<(WeaponId, u32)>,
+}
+
+/*
+* Drawn over the 3D view, into the framebuffer: health at the bottom left, the weapon and its ammo at the
+* bottom right, a crosshair in the middle, the frame rate at the top right and messages at the top, wrapped
+* to the width of the screen. Text is drawn with a shadow, to be read over bright walls.
+*/
+pub struct Hud {
+    pub status:            HudStatus,
+    pub show_fps:          bool,
+    // Font pixels are HUD pixels.
+    pub font:              Font,
+        messages:          Vec<HudMessage>,
+        // Frames counted over the current second, and over the last whole one.
+        frames:            u32,
+        frame_seconds:     f32,
+        frames_per_second: u32,
+}
+
+struct HudMessage {
+    text:         String,
+    seconds_left: f32,
+}
+
+// Where things go on a screen, in screen pixels. HUD pixels are `scale` screen pixels wide.
+struct HudLayout {
+    scale:  u16,
+    margin: i32,
+    width:  i32,
+    height: i32,
+}
+
+impl Hud {
+    pub fn new() -> Hud {
+        Hud { status: HudStatus::default(), show_fps: false, font: Font::builtin(), messages: vec![], frames: 0, frame_seconds: 0.0, frames_per_second: 0 }
+    }
+
+    pub fn show_message(&mut self, text: &str) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+
+        self.messages.push(HudMessage { text: text.to_owned(), seconds_left: MESSAGE_SECONDS });
+    }
+
+    // Once per frame, `seconds` after the last one.
+    pub fn update(&mut self, seconds: f32) {
+        for message in &mut self.messages {
+            message.seconds_left -= seconds;
+        }
+
+        self.messages.retain(|message| message.seconds_left > 0.0);
+
+        self.frames        += 1;
+        self.frame_seconds += seconds;
+
+        if self.frame_seconds >= 1.0 {
+            self.frames_per_second = ((self.frames as f32) / self.frame_seconds).round() as u32;
+            self.frames            = 0;
+            self.frame_seconds     = 0.0;
+        }
+    }
+
+    pub fn draw(&self, raycaster: &mut Raycaster) {
+        let layout = HudLayout::new(raycaster.get_width(), raycaster.get_height());
+        let style  = layout.get_text_style(Alignment::Left);
+        let bottom = layout.height - layout.margin - self.font.get_line_advance(&style);
+
+        if let Some(health) = self.status.health {
+            let color = if health.current < health.maximum * LOW_HEALTH { Color::RED } else { Color::WHITE };
+
+            self.font.draw(raycaster, &format!("HEALTH {}", health.current.max(0.0).ceil() as u32), layout.margin, bottom, &TextStyle { color, ..style });
+        }
+
+        if let Some((weapon, ammo)) = self.status.weapon {
+            let color = if ammo == 0 { Color::RED } else { Color::WHITE };
+            let text  = format!("{} {}", WEAPONS[weapon].name, ammo);
+
+            self.font.draw(raycaster, &text, layout.width - layout.margin, bottom, &TextStyle { color, ..layout.get_text_style(Alignment::Right) });
+        }
+
+        if self.show_fps {
+            let text = format!("{} FPS", self.frames_per_second);
+
+            self.font.draw(raycaster, &text, layout.width - layout.margin, layout.margin, &TextStyle { color: Color::YELLOW, ..layout.get_text_style(Alignment::Right) });
+        }
+
+        let mut top = layout.margin;
+        let width   = ((layout.width as f32) * MESSAGE_WIDTH) as u16;
+
+        for message in &self.messages {
+            top += self.font.draw_wrapped(raycaster, &message.text, layout.width / 2, top, width, &layout.get_text_style(Alignment::Centre));
+        }
+
+        layout.draw_crosshair(raycaster);
+    }
+}
+
+impl Default for Hud {
+    fn default() -> Hud {
+        Hud::new()
+    }
+}
+
+impl HudLayout {
+    fn new(screen_width: u16, screen_height: u16) -> HudLayout {
+        let scale = (screen_height / HUD_REFERENCE_HEIGHT).max(1);
+
+        HudLayout { scale, margin: (HUD_MARGIN * scale) as i32, width: screen_width as i32, height: screen_height as i32 }
+    }
+
+    // Shadowed, in white unless changed.
+    fn get_text_style(&self, alignment: Alignment) -> TextStyle {
+        TextStyle { alignment, shadow: Some(Color::BLACK), ..TextStyle::new(Color::WHITE, self.scale) }
+    }
+
+    // A cross with a gap in the middle, so that what is aimed at stays visible. It is centred on the screen.
+    fn draw_crosshair(&self, raycaster: &mut Raycaster) {
+        let scale     = self.scale as i32;
+        let centre_x  = self.width / 2;
+        let centre_y  = self.height / 2;
+        let near      = ((CROSSHAIR_GAP as i32) * scale).max(1);
+        let length    = (CROSSHAIR_ARM as i32) * scale;
+        let thickness = (scale / 2).max(1);
+        let side      = thickness / 2;
+        let color     = Color::WHITE;
+
+        raycaster.fill_rectangle(centre_x - near - length, centre_y - side, length, thickness, &color);
+        raycaster.fill_rectangle(centre_x + near,          centre_y - side, length, thickness, &color);
+        raycaster.fill_rectangle(centre_x - side,          centre_y - near - length, thickness, length, &color);
+        raycaster.fill_rectangle(centre_x - side,          centre_y + near,          thickness, length, &color);
+    }
+}
@@ -0,0 +1,370 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, fmt, fs, path::Path};
+
+use ggez::event::{Axis, Button, KeyCode, MouseButton};
+use serde::{Deserialize, Serialize};
+
+use crate::asset::{self, LoadError};
+
+// Sticks pushed further than this press their direction, for actions that happen once (and for rebinding).
+const AXIS_PRESS_THRESHOLD: f32 = 0.5;
+
+/*
+* What the player can do, whatever the device. Movement actions have an amount, from 0 to 1, others happen
+* once when one of their bindings is pressed.
+*/
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    StrafeLeft,
+    StrafeRight,
+    TurnLeft,
+    TurnRight,
+    LookUp,
+    LookDown,
+    Use,
+    Fire,
+    NextWeapon,
+    PreviousWeapon,
+    ToggleMap,
+    ToggleProfiler,
+    QuickSave,
+    QuickLoad,
+    // Loads its slot, or saves to it while `SaveToSlot` is held.
+    Slot1,
+    Slot2,
+    Slot3,
+    Slot4,
+    SaveToSlot,
+    Saves,
+    Rebind,
+    Quit,
+}
+
+/*
+* Something the player presses or pushes. Binding files name them:
+*
+*   "W", "Space", "F5"...      keys, see `KEY_CODES`
+*   "Mouse:Left"               mouse buttons: Left, Right, Middle
+*   "Gamepad:South"            gamepad buttons, see `BUTTONS`
+*   "Gamepad:LeftStickY+"      a direction of a gamepad axis, see `AXES`
+*/
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Button(Button),
+    Axis { axis: Axis, is_positive: bool },
+}
+
+/*
+* Bindings of every action. An action can have any number of them, and a binding can trigger several
+* actions. Files only need the actions whose bindings differ from the defaults:
+*
+*   move_forward = ["W", "Up", "Gamepad:LeftStickY+"]
+*   fire         = []
+*/
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    actions: BTreeMap<Action, Vec<Binding>>,
+}
+
+/*
+* What is held down right now, for all devices. Gamepads are not told apart. Axis values within `deadzone` of
+* the centre are 0, values past it are rescaled to go from 0 to 1.
+*/
+pub struct Input {
+    pub bindings: Bindings,
+        deadzone: f32,
+        pressed:  HashSet<Binding>,
+        axes:     HashMap<Axis, f32>,
+}
+
+// Keys that can be bound, by the name files give them.
+const KEY_CODES: &[KeyCode] = &[
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G, KeyCode::H, KeyCode::I,
+    KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N, KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R,
+    KeyCode::S, KeyCode::T, KeyCode::U, KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+    KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+    KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+    KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+    KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+    KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right,
+    KeyCode::Space, KeyCode::Return, KeyCode::Escape, KeyCode::Tab, KeyCode::Back,
+    KeyCode::LShift, KeyCode::RShift, KeyCode::LControl, KeyCode::RControl, KeyCode::LAlt, KeyCode::RAlt,
+    KeyCode::Minus, KeyCode::Equals, KeyCode::Comma, KeyCode::Period,
+];
+
+const MOUSE_BUTTONS: &[MouseButton] = &[MouseButton::Left, MouseButton::Right, MouseButton::Middle];
+
+const BUTTONS: &[Button] = &[
+    Button::South, Button::East, Button::North, Button::West, Button::C, Button::Z,
+    Button::LeftTrigger, Button::LeftTrigger2, Button::RightTrigger, Button::RightTrigger2,
+    Button::Select, Button::Start, Button::Mode, Button::LeftThumb, Button::RightThumb,
+    Button::DPadUp, Button::DPadDown, Button::DPadLeft, Button::DPadRight,
+];
+
+const AXES: &[Axis] = &[
+    Axis::LeftStickX, Axis::LeftStickY, Axis::LeftZ, Axis::RightStickX, Axis::RightStickY, Axis::RightZ,
+    Axis::DPadX, Axis::DPadY,
+];
+
+impl Action {
+    pub const ALL: [Action; 24] = [
+        Action::MoveForward, Action::MoveBackward, Action::StrafeLeft, Action::StrafeRight,
+        Action::TurnLeft, Action::TurnRight, Action::LookUp, Action::LookDown,
+        Action::Use, Action::Fire, Action::NextWeapon, Action::PreviousWeapon, Action::ToggleMap,
+        Action::ToggleProfiler, Action::QuickSave, Action::QuickLoad,
+        Action::Slot1, Action::Slot2, Action::Slot3, Action::Slot4, Action::SaveToSlot, Action::Saves,
+        Action::Rebind, Action::Quit,
+    ];
+
+    // As written in binding files.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Action::MoveForward    => "move_forward",
+            Action::MoveBackward   => "move_backward",
+            Action::StrafeLeft     => "strafe_left",
+            Action::StrafeRight    => "strafe_right",
+            Action::TurnLeft       => "turn_left",
+            Action::TurnRight      => "turn_right",
+            Action::LookUp         => "look_up",
+            Action::LookDown       => "look_down",
+            Action::Use            => "use",
+            Action::Fire           => "fire",
+            Action::NextWeapon     => "next_weapon",
+            Action::PreviousWeapon => "previous_weapon",
+            Action::ToggleMap      => "toggle_map",
+            Action::ToggleProfiler => "toggle_profiler",
+            Action::QuickSave      => "quick_save",
+            Action::QuickLoad      => "quick_load",
+            Action::Slot1          => "slot_1",
+            Action::Slot2          => "slot_2",
+            Action::Slot3          => "slot_3",
+            Action::Slot4          => "slot_4",
+            Action::SaveToSlot     => "save_to_slot",
+            Action::Saves          => "saves",
+            Action::Rebind         => "rebind",
+            Action::Quit           => "quit",
+        }
+    }
+
+    pub fn find(name: &str) -> Option<Action> {
+        Action::ALL.iter().copied().find(|action| action.get_name() == name)
+    }
+
+    pub fn get_label(&self) -> &'static str {
+        match self {
+            Action::MoveForward    => "Move forward",
+            Action::MoveBackward   => "Move backward",
+            Action::StrafeLeft     => "Strafe left",
+            Action::StrafeRight    => "Strafe right",
+            Action::TurnLeft       => "Turn left",
+            Action::TurnRight      => "Turn right",
+            Action::LookUp         => "Look up",
+            Action::LookDown       => "Look down",
+            Action::Use            => "Use",
+            Action::Fire           => "Fire",
+            Action::NextWeapon     => "Next weapon",
+            Action::PreviousWeapon => "Previous weapon",
+            Action::ToggleMap      => "Toggle map",
+            Action::ToggleProfiler => "Toggle profiler",
+            Action::QuickSave      => "Quick save",
+            Action::QuickLoad      => "Quick load",
+            Action::Slot1          => "Slot 1",
+            Action::Slot2          => "Slot 2",
+            Action::Slot3          => "Slot 3",
+            Action::Slot4          => "Slot 4",
+            Action::SaveToSlot     => "Save to slots (hold)",
+            Action::Saves          => "Saves",
+            Action::Rebind         => "Change bindings",
+            Action::Quit           => "Quit",
+        }
+    }
+
+    fn get_default_bindings(&self) -> Vec<Binding> {
+        let key    = Binding::Key;
+        let button = Binding::Button;
+        let axis   = |axis: Axis, is_positive: bool| Binding::Axis { axis, is_positive };
+
+        // Gamepad sticks are up and right when positive.
+        match self {
+            Action::MoveForward    => vec![key(KeyCode::W),        axis(Axis::LeftStickY, true),   button(Button::DPadUp)],
+            Action::MoveBackward   => vec![key(KeyCode::S),        axis(Axis::LeftStickY, false),  button(Button::DPadDown)],
+            Action::StrafeLeft     => vec![key(KeyCode::A),        axis(Axis::LeftStickX, false)],
+            Action::StrafeRight    => vec![key(KeyCode::D),        axis(Axis::LeftStickX, true)],
+            Action::TurnLeft       => vec![key(KeyCode::Left),     axis(Axis::RightStickX, false), button(Button::DPadLeft)],
+            Action::TurnRight      => vec![key(KeyCode::Right),    axis(Axis::RightStickX, true),  button(Button::DPadRight)],
+            Action::LookUp         => vec![key(KeyCode::Up),       axis(Axis::RightStickY, true)],
+            Action::LookDown       => vec![key(KeyCode::Down),     axis(Axis::RightStickY, false)],
+            Action::Use            => vec![key(KeyCode::E),        button(Button::South)],
+            Action::Fire           => vec![key(KeyCode::LControl), Binding::Mouse(MouseButton::Left), button(Button::RightTrigger2)],
+            Action::NextWeapon     => vec![key(KeyCode::Period),   Binding::Mouse(MouseButton::Right), button(Button::North)],
+            Action::PreviousWeapon => vec![key(KeyCode::Comma),    button(Button::West)],
+            Action::ToggleMap      => vec![key(KeyCode::Tab),      button(Button::Select)],
+            Action::ToggleProfiler => vec![key(KeyCode::F12)],
+            Action::QuickSave      => vec![key(KeyCode::F5)],
+            Action::QuickLoad      => vec![key(KeyCode::F9)],
+            Action::Slot1          => vec![key(KeyCode::F1)],
+            Action::Slot2          => vec![key(KeyCode::F2)],
+            Action::Slot3          => vec![key(KeyCode::F3)],
+            Action::Slot4          => vec![key(KeyCode::F4)],
+            Action::SaveToSlot     => vec![key(KeyCode::LShift),   key(KeyCode::RShift)],
+            Action::Saves          => vec![key(KeyCode::F8),       button(Button::Start)],
+            Action::Rebind         => vec![key(KeyCode::F10)],
+            Action::Quit           => vec![key(KeyCode::Escape)],
+        }
+    }
+}
+
+impl Binding {
+    // Whether binding files can name it, keys missing from `KEY_CODES` cannot be written for instance.
+    pub fn has_name(&self) -> bool {
+        Binding::try_from(self.to_string()).is_ok()
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Binding::Key(key_code)              => write!(formatter, "{:?}", key_code),
+            Binding::Mouse(button)              => write!(formatter, "Mouse:{:?}", button),
+            Binding::Button(button)             => write!(formatter, "Gamepad:{:?}", button),
+            Binding::Axis { axis, is_positive } => write!(formatter, "Gamepad:{:?}{}", axis, if *is_positive { "+" } else { "-" }),
+        }
+    }
+}
+
+impl From<Binding> for String {
+    fn from(binding: Binding) -> String {
+        binding.to_string()
+    }
+}
+
+// Names are those written by `Display`, whatever the case.
+impl TryFrom<String> for Binding {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Binding, String> {
+
+        let candidates: Vec<Binding> = if name.to_ascii_lowercase().starts_with("mouse:") {
+            MOUSE_BUTTONS.iter().map(|button| Binding::Mouse(*button)).collect()
+        } else if name.to_ascii_lowercase().starts_with("gamepad:") {
+            BUTTONS
+            .iter()
+            .map(|button| Binding::Button(*button))
+            .chain(AXES.iter().flat_map(|axis| [true, false].map(|is_positive| Binding::Axis { axis: *axis, is_positive })))
+            .collect()
+        } else {
+            KEY_CODES.iter().map(|key_code| Binding::Key(*key_code)).collect()
+        };
+
+        candidates.into_iter().find(|candidate| candidate.to_string().eq_ignore_ascii_case(&name)).ok_or_else(|| {
+            format!("there is nothing named {:?} to bind, try a key (\"W\", \"Space\", \"F5\"...), \"Mouse:Left\", \"Gamepad:South\" or \"Gamepad:LeftStickY+\"", name)
+        })
+    }
+}
+
+impl Bindings {
+    // Actions the file leaves out keep their defaults.
+    pub fn load(path: &Path) -> Result<Bindings, LoadError> {
+        let mut bindings = Bindings::default();
+        let file: BTreeMap<String, Vec<Binding>> = asset::read_toml(path)?;
+
+        for (name, action_bindings) in file {
+            let action = Action::find(&name).ok_or_else(|| LoadError::invalid(path, format!("there is no action named {:?}", name)))?;
+
+            bindings.actions.insert(action, action_bindings);
+        }
+
+        Ok(bindings)
+    }
+
+    // Every action is written, so that the file shows what can be bound.
+    pub fn write(&self, path: &Path) -> Result<(), LoadError> {
+        let file: BTreeMap<&str, &Vec<Binding>> = self.actions.iter().map(|(action, bindings)| (action.get_name(), bindings)).collect();
+        let text = toml::to_string(&file).map_err(|error| LoadError::invalid(path, error.to_string()))?;
+
+        fs::write(path, text).map_err(|error| LoadError::Io { path: path.to_owned(), error })
+    }
+
+    pub fn get(&self, action: Action) -> &[Binding] {
+        self.actions.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    // Actions triggered by `binding`.
+    pub fn get_actions(&self, binding: Binding) -> Vec<Action> {
+        self.actions.iter().filter(|(_, bindings)| bindings.contains(&binding)).map(|(action, _)| *action).collect()
+    }
+
+    // Bindings already there are not added twice.
+    pub fn add(&mut self, action: Action, binding: Binding) {
+        let bindings = self.actions.entry(action).or_default();
+
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn clear(&mut self, action: Action) {
+        self.actions.insert(action, vec![]);
+    }
+
+    pub fn reset(&mut self, action: Action) {
+        self.actions.insert(action, action.get_default_bindings());
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        Bindings { actions: Action::ALL.iter().map(|action| (*action, action.get_default_bindings())).collect() }
+    }
+}
+
+impl Input {
+    pub fn new(bindings: Bindings, deadzone: f32) -> Input {
+        Input { bindings, deadzone, pressed: HashSet::new(), axes: HashMap::new() }
+    }
+
+    // Whether `binding` was just pressed, rather than held already (keys repeat).
+    pub fn press(&mut self, binding: Binding) -> bool {
+        self.pressed.insert(binding)
+    }
+
+    pub fn release(&mut self, binding: Binding) {
+        self.pressed.remove(&binding);
+    }
+
+    // The direction of the axis that was just pushed past halfway, if any.
+    pub fn move_axis(&mut self, axis: Axis, value: f32) -> Option<Binding> {
+        let previous = self.axes.insert(axis, value).unwrap_or(0.0);
+
+        match value {
+            _ if value >= AXIS_PRESS_THRESHOLD && previous < AXIS_PRESS_THRESHOLD    => Some(Binding::Axis { axis, is_positive: true }),
+            _ if value <= -AXIS_PRESS_THRESHOLD && previous > -AXIS_PRESS_THRESHOLD  => Some(Binding::Axis { axis, is_positive: false }),
+            _                                                                        => None,
+        }
+    }
+
+    // How much the action is asked for, from 0 to 1, the most pressed of its bindings winning.
+    pub fn get_amount(&self, action: Action) -> f32 {
+        self.bindings.get(action).iter().map(|binding| self.get_binding_amount(*binding)).fold(0.0, f32::max)
+    }
+
+    // From -1 to 1.
+    pub fn get_axis(&self, positive: Action, negative: Action) -> f32 {
+        self.get_amount(positive) - self.get_amount(negative)
+    }
+
+    fn get_binding_amount(&self, binding: Binding) -> f32 {
+        match binding {
+            Binding::Axis { axis, is_positive } => {
+                let value = self.axes.get(&axis).copied().unwrap_or(0.0) * if is_positive { 1.0 } else { -1.0 };
+
+                if value <= self.deadzone { 0.0 } else { ((value - self.deadzone) / (1.0 - self.deadzone)).min(1.0) }
+            },
+            _ => if self.pressed.contains(&binding) { 1.0 } else { 0.0 },
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{asset::{self, LoadError}, entity::{Entities, EntitySpawn, EntityTemplates}, map::{Map, MapPosition}, material::{MaterialRegistry, Tile}, portal::Portal, ray::RayHit, raycaster::Raycaster, sector::SectorMap, world::{WorldAngle, WorldDirection, WorldLength, WorldPosition, WorldVector}};
+
+// Height of the eye above the floor, half the height of a tile.
+pub const EYE_HEIGHT:         WorldLength = 0.5;
+// How high a floor can be above the current one while still being walked onto.
+pub const MAX_STEP_HEIGHT:    WorldLength = 0.3;
+
+// Bounds the work done by a single move, going back and forth between two portals facing each other.
+const MAX_MOVE_STEPS:         u32         = 8;
+// Moves going through a portal end up this far from the exit, so that they do not come back through it.
+const PORTAL_EXIT_OFFSET:     WorldLength = 1e-3;
+// How far from a line heights are looked up, to know what is on either side of it.
+pub const HEIGHT_PROBE:       WorldLength = 1e-3;
+
+// Floor and ceiling heights at some place of the world, in world units (a tile being 1 high).
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Heights {
+    pub floor:   WorldLength,
+    pub ceiling: WorldLength,
+}
+
+// Where a move ended, and how much the mover turned by going through portals.
+#[derive(Clone, Copy, Debug)]
+pub struct Movement {
+    pub position: WorldPosition,
+    pub rotation: WorldAngle,
+}
+
+// Where a level comes from, so that it can be loaded again (to replay a game, for instance).
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind", content = "path")]
+pub enum LevelSource {
+    Map(PathBuf),
+    // The demo sector map shows off what the tile grid cannot do.
+    DemoSectors,
+}
+
+// A tile that is not what it was when the level was loaded (a door that opened, a wall that was destroyed...).
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct TileChange {
+    pub position: MapPosition,
+    pub tile:     Tile,
+}
+
+// What loading a level gives: the level, the entities it starts with, and the materials it may use.
+pub struct LoadedLevel {
+    pub level:     Box<dyn Level>,
+    pub entities:  Entities,
+    pub materials: MaterialRegistry,
+}
+
+/*
+* What the renderer, the player and gameplay queries need to know about the world, so that they work the same
+* way on a grid of tiles (`Map`) and on polygonal sectors (`SectorMap`).
+*/
+pub trait Level {
+    /*
+    * Same contract as `ray::walk`, without the visited cells: every surface accepted by `is_hit` along the
+    * normalized direction is reported in order to `on_hit`, which tells whether the ray goes on. Openings
+    * between sectors are reported too. Returns how many grid cells the walk went through.
+    */
+    fn walk_ray(&self, origin: WorldPosition, ray_direction: WorldDirection, max_distance: WorldLength, is_hit: &dyn Fn(Tile) -> bool, on_hit: &mut dyn FnMut(RayHit) -> bool) -> u32;
+
+    fn get_heights(&self, position: WorldPosition) -> Heights;
+
+    // Outdoor places have no ceiling, the sky is drawn instead. So does the outside of the world.
+    fn is_open_sky(&self, position: WorldPosition) -> bool;
+
+    fn find_spawn(&self) -> WorldPosition;
+
+    // Enemies, items and other entities present when the level starts.
+    fn get_entity_spawns(&self) -> &[EntitySpawn] {
+        &[]
+    }
+
+    // Tiles changed since the level was loaded, so that saves can bring them back. Sector maps have no tiles.
+    fn get_tile_changes(&self) -> Vec<TileChange> {
+        vec![]
+    }
+
+    // Whether there is a tile there to change.
+    fn set_tile(&mut self, _position: MapPosition, _tile: Tile) -> bool {
+        false
+    }
+
+    // Levels made of tiles, for what works on cells (pathfinding...).
+    fn get_tile_map(&self) -> Option<&Map> {
+        None
+    }
+
+    // Draws the world seen from above, see minimap.rs.
+    fn draw_on_minimap(&self, raycaster: &mut Raycaster);
+
+    // The portal whose entrance was hit, if any.
+    fn find_portal(&self, _hit: &RayHit) -> Option<Portal> {
+        None
+    }
+
+    /*
+    * Gameplay ray query (line of sight, hitscan, picking...): the first surface the ray cannot go through,
+    * `stops_ray` telling which ones. Gameplay happens on the ground, so openings are always gone through.
+    * The direction does not need to be normalized.
+    */
+    fn trace_ray(&self, origin: WorldPosition, direction: WorldVector, max_distance: WorldLength, stops_ray: &dyn Fn(Tile) -> bool) -> Option<RayHit> {
+        let     direction = direction.normalize_or_zero();
+        let mut first_hit = None;
+
+        if direction == WorldVector::ZERO {
+            return None;
+        }
+
+        self.walk_ray(origin, direction, max_distance, stops_ray, &mut |hit| {
+            if hit.has_opening {
+                return true;
+            }
+
+            first_hit = Some(hit);
+
+            false
+        });
+
+        first_hit
+    }
+
+    fn line_of_sight(&self, from: WorldPosition, to: WorldPosition) -> bool {
+        self.trace_ray(from, to - from, from.distance(to), &|tile| tile.blocks_sight()).is_none()
+    }
+
+    // Whether something on the near side of an opening can walk to the far side.
+    fn is_passable(&self, hit: &RayHit) -> bool {
+        let near = self.get_heights(hit.point + hit.normal * HEIGHT_PROBE);
+        let far  = self.get_heights(hit.point - hit.normal * HEIGHT_PROBE);
+
+        hit.has_opening && far.floor - near.floor <= MAX_STEP_HEIGHT && far.ceiling - far.floor > EYE_HEIGHT
+    }
+
+    /*
+    * Moves something of the given radius from `position` by `motion`. Walls stop it `radius` away from them,
+    * and the rest of the move slides along them. Portals are walked through, the rest of the move carrying
+    * on from the exit, turned like the portal turns things.
+    */
+    fn move_through(&self, position: WorldPosition, motion: WorldVector, radius: WorldLength) -> Movement {
+        let mut movement = Movement { position, rotation: 0.0 };
+        let mut motion   = motion;
+
+        for _ in 0 .. MAX_MOVE_STEPS {
+            let length    = motion.length();
+
+            if length == 0.0 {
+                break;
+            }
+
+            // How far the wall needs to be depends on the angle, so the ray is not cut short.
+            let direction = motion / length;
+            let mut hit   = None;
+
+            self.walk_ray(movement.position, direction, WorldLength::INFINITY, &|tile| tile.blocks_movement(), &mut |candidate| {
+                if self.is_passable(&candidate) {
+                    return true;
+                }
+
+                hit = Some(candidate);
+
+                false
+            });
+
+            let hit       = match hit {
+                Some(hit) => hit,
+                None      => {
+                    movement.position += motion;
+
+                    break;
+                },
+            };
+
+            match self.find_portal(&hit) {
+                // Portals only have a radius on the other side.
+                Some(_) if hit.distance > length => {
+                    movement.position += motion;
+
+                    break;
+                },
+
+                Some(portal) => {
+                    let transform      = portal.get_transform();
+
+                    movement.position  = transform.transform_position(hit.point) + portal.exit.side.get_normal() * PORTAL_EXIT_OFFSET;
+                    movement.rotation += transform.get_rotation();
+                    motion             = transform.transform_direction(direction) * (length - hit.distance);
+                },
+
+                // The radius is kept between the mover and the wall, perpendicularly to the wall.
+                None => {
+                    let clearance      = radius / (-direction.dot(hit.normal)).max(f32::EPSILON);
+                    let advance        = (hit.distance - clearance).clamp(0.0, length);
+                    let remaining      = direction * (length - advance);
+
+                    movement.position += direction * advance;
+                    motion             = remaining - hit.normal * remaining.dot(hit.normal);
+                },
+            }
+        }
+
+        movement
+    }
+}
+
+// Materials and entity templates come from the assets directory.
+pub fn load_level(source: &LevelSource) -> Result<LoadedLevel, LoadError> {
+    let materials      = MaterialRegistry::load(&asset::get_asset_path("materials.toml"))?;
+    let templates_path = asset::get_asset_path("entities.toml");
+    let templates      = EntityTemplates::load(&templates_path)?;
+
+    let level: Box<dyn Level> = match source {
+        LevelSource::Map(path)   => Box::new(Map::load(path, &materials)?),
+        LevelSource::DemoSectors => Box::new(SectorMap::make_demo_sector_map(&materials)),
+    };
+
+    let mut entities = Entities::new();
+
+    templates.spawn_all(&mut entities, level.get_entity_spawns()).map_err(|message| LoadError::invalid(&templates_path, message))?;
+
+    Ok(LoadedLevel { level, entities, materials })
+}
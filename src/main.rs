@@ -1,24 +1,186 @@
 #![allow(non_snake_case)]
 #![allow(unused_parens)]
 
+mod ai;
 mod map;
+mod ray;
+mod sky;
+mod hud;
+mod math;
+mod path;
+mod save;
+mod font;
+mod asset;
+mod level;
 mod world;
+mod input;
 mod state;
+mod slots;
+mod random;
+mod rebind;
+mod replay;
 mod camera;
+mod entity;
+mod portal;
+mod sector;
+mod sprite;
+mod weapon;
+mod upload;
+mod minimap;
+mod systems;
+mod texture;
+mod material;
+mod settings;
+mod profiler;
 mod raycaster;
+mod viewmodel;
+mod simulation;
+
+use std::{path::Path, time::{SystemTime, UNIX_EPOCH}};
+
+use clap::Parser;
+use ggez::conf::FullscreenType;
+
+use crate::{font::Font, input::Bindings, profiler::ProfileRecorder, level::LevelSource, save::SaveSlots, settings::{CommandLine, Settings}, replay::{Replay, ReplayHeader, ReplayOutcome, ReplayRecorder}, simulation::Simulation};
+
+fn exit_with_error<E: std::fmt::Display>(error: E) -> ! {
+    eprintln!("{}", error);
+    std::process::exit(1);
+}
+
+// Plays a replay without a window, telling whether the game went the same way. Fails when it did not.
+fn verify_replay(path: &Path) -> ! {
+    let replay         = Replay::load(path).unwrap_or_else(|error| exit_with_error(error));
+    let mut simulation = replay.make_simulation().unwrap_or_else(|error| exit_with_error(error));
+    let outcome        = replay.play(&mut simulation);
+
+    println!("{}", outcome);
+
+    std::process::exit(if matches!(outcome, ReplayOutcome::Matched { .. }) { 0 } else { 1 });
+}
+
+fn list_saves(saves: &SaveSlots) -> ! {
+    let slots = saves.list().unwrap_or_else(|error| exit_with_error(error));
+
+    if slots.is_empty() {
+        println!("There are no saves.");
+    }
+
+    for slot in slots {
+        let age = slot.get_minutes_old().map_or("?".to_owned(), |minutes| minutes.to_string());
+
+        println!("{:<16} saved {} minutes ago", slot.name, age);
+    }
+
+    std::process::exit(0);
+}
+
+// Opens a window that does not wait for the screen, and uploads frames of every size to it.
+fn benchmark_upload() -> ! {
+    let sizes      = [(800, 600), (1920, 1080)];
+    let mut config = ggez::conf::Conf::new();
+
+    config.window_setup = config.window_setup.title("raycaster upload benchmark").vsync(false);
+
+    let (mut context, _event_loop) = ggez::ContextBuilder::new("raycaster", "Florian")
+    .default_conf(config)
+    .build()
+    .unwrap_or_else(|error| exit_with_error(format!("Could not open the window: {}", error)));
+
+    for (width, height) in sizes {
+        let benchmark = upload::run_benchmark(&mut context, width, height).unwrap_or_else(|error| exit_with_error(error));
+
+        println!("{}", benchmark);
+    }
+
+    std::process::exit(0);
+}
 
 fn main() {
+    let command_line = CommandLine::parse();
+    let saves        = SaveSlots::new(save::SAVE_DIRECTORY);
+
+    if let Some(path) = &command_line.replay {
+        verify_replay(path);
+    }
+
+    if command_line.list_saves {
+        list_saves(&saves);
+    }
+
+    if command_line.benchmark_upload {
+        benchmark_upload();
+    }
+
+    let settings     = Settings::load(&command_line).unwrap_or_else(|error| exit_with_error(error));
+
+    let level_source = if command_line.sectors {
+        LevelSource::DemoSectors
+    } else {
+        LevelSource::Map(settings.game.map.clone())
+    };
+
+    // Games differ from one run to the next, unless replayed.
+    let seed             = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_nanos() as u64);
+    let ticks_per_second = settings.game.ticks_per_second;
+
+    // A save carries its own level.
+    let simulation       = match &command_line.load {
+        Some(slot) => saves.load(slot, ticks_per_second).unwrap_or_else(|error| exit_with_error(error)),
+        None       => {
+            let loaded = level::load_level(&level_source).unwrap_or_else(|error| exit_with_error(error));
+
+            Simulation::new(level_source.clone(), loaded.level, loaded.entities, ticks_per_second, seed)
+        },
+    };
+
+    // Replays start from a level, not from a save, the command line does not allow both.
+    let recorder = command_line.record.as_ref().map(|path| {
+        let level_data = replay::hash_level_data(&level_source).unwrap_or_else(|error| exit_with_error(error));
+        let header     = ReplayHeader { level: level_source.clone(), level_data, seed, ticks_per_second };
+
+        ReplayRecorder::create(path, &header).unwrap_or_else(|error| exit_with_error(format!("Could not create {}: {}", path.display(), error)))
+    });
+
+    let profile_recorder = command_line.profile.as_ref().map(|path| {
+        ProfileRecorder::create(path).unwrap_or_else(|error| exit_with_error(format!("Could not create {}: {}", path.display(), error)))
+    });
+
+    // Until the rebinding screen writes some, the default bindings are used.
+    let bindings_path = &settings.controls.bindings;
+    let bindings      = if bindings_path.exists() {
+        Bindings::load(bindings_path).unwrap_or_else(|error| exit_with_error(error))
+    } else {
+        Bindings::default()
+    };
+
+    // Without a font sheet, the built-in font is used.
+    let font = match &settings.renderer.font {
+        Some(path) => Font::load(path).unwrap_or_else(|error| exit_with_error(error)),
+        None       => Font::builtin(),
+    };
+
     let mut config = ggez::conf::Conf::new();
-    let     state  = state::State::new();
+    let     state  = state::State::new(simulation, &settings, bindings, font, recorder, profile_recorder, saves);
 
-    config.window_mode.width  = raycaster::SCREEN_WIDTH as f32;
-    config.window_mode.height = raycaster::SCREEN_HEIGHT as f32;
-    config.window_setup       = config.window_setup.title("Raycaster");
+    config.window_mode.width           = settings.window.width as f32;
+    config.window_mode.height          = settings.window.height as f32;
+    config.window_mode.fullscreen_type = if settings.window.fullscreen { FullscreenType::Desktop } else { FullscreenType::Windowed };
+    config.window_setup                = config.window_setup.title(&settings.window.title).vsync(settings.window.vsync);
 
-    let (context, event_loop) = ggez::ContextBuilder::new("raycaster", "Florian")
+    let (mut context, event_loop) = ggez::ContextBuilder::new("raycaster", "Florian")
     .default_conf(config)
     .build()
-    .unwrap();
+    .unwrap_or_else(|error| exit_with_error(format!("Could not open the window: {}", error)));
+
+    // Mouse look keeps the mouse in the window.
+    if settings.controls.mouse_sensitivity > 0.0 {
+        ggez::input::mouse::set_cursor_hidden(&mut context, true);
+
+        if let Err(error) = ggez::input::mouse::set_cursor_grabbed(&mut context, true) {
+            eprintln!("Could not grab the mouse: {}", error);
+        }
+    }
 
     ggez::event::run(context, event_loop, state);
 }
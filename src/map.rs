@@ -1,104 +1,305 @@
-use ggez::graphics::Color;
-
-const DEMO_MAP_TILES_WIDTH:  MapCoordinate = 13;
-const DEMO_MAP_TILES_HEIGHT: MapCoordinate = 18;
-
-static DEMO_MAP_TILES: &'static [Tile] = &[
-    T::Wall,  T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Wall2,  T::Wall2,  T::Wall2,  T::Wall2,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall2,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall2,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Spawn,  T::Empty,  T::Wall2,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Empty,  T::Wall,
-    T::Wall,  T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,   T::Wall,
-];
+use std::{collections::HashMap, path::Path};
+
+use serde::Deserialize;
+
+use crate::{minimap, asset::{self, LoadError}, entity::EntitySpawn, level::{Heights, Level, TileChange}, material::{MaterialRegistry, Tile, TileShape}, portal::{FaceSide, Portal, WallFace}, ray::{self, RayHit, RayTrace}, raycaster::Raycaster, world::{self, WorldDirection, WorldPosition, WorldVector, WorldLength}};
 
 pub type MapCoordinate       = u32;
 pub type SignedMapCoordinate = i64;
 
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub struct MapPosition {
     pub x: MapCoordinate,
     pub y: MapCoordinate,
 }
 
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub struct SignedMapPosition {
     pub x: SignedMapCoordinate,
     pub y: SignedMapCoordinate,
 }
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
-pub enum Tile
-{
-    Wall,
-    Empty,
-    Spawn,
-    Wall2,
+pub struct Map {
+    pub height:        MapCoordinate,
+    pub width:         MapCoordinate,
+        tiles:         Vec<Tile>,
+        loaded_tiles:  Vec<Tile>,
+        portals:       Vec<Portal>,
+        spawn:         MapPosition,
+        entity_spawns: Vec<EntitySpawn>,
+        // Counts tile changes, so that what was worked out from the tiles knows when to work it out again.
+        revision:      u64,
+}
+
+// Shape of a map file, see assets/maps/demo.toml.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MapFile {
+    rows:     Vec<String>,
+    legend:   HashMap<char, String>,
+    spawn:    [MapCoordinate; 2],
+    #[serde(default)]
+    portals:  Vec<PortalDefinition>,
+    #[serde(default)]
+    entities: Vec<EntitySpawn>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PortalDefinition {
+    from: FaceDefinition,
+    to:   FaceDefinition,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FaceDefinition {
+    x:    MapCoordinate,
+    y:    MapCoordinate,
+    side: FaceSide,
+}
+
+// Flooring (rather than truncating) keeps positions left of or above the map outside of it.
+pub fn world_position_to_signed_map_position(position: WorldPosition) -> SignedMapPosition {
+    SignedMapPosition { x: position.x.floor() as SignedMapCoordinate, y: position.y.floor() as SignedMapCoordinate }
 }
 
-type T = Tile;
+impl Map {
+    // Tiles are stored row by row, starting from the top-left corner of the map.
+    pub fn new(width: MapCoordinate, height: MapCoordinate, tiles: Vec<Tile>, spawn: MapPosition) -> Map {
+        assert_eq!(tiles.len(), (width * height) as usize, "Tile count does not match the map dimensions!");
+        assert!(spawn.x < width && spawn.y < height, "Spawn is outside of the map!");
+
+        Map { width, height, spawn, loaded_tiles: tiles.clone(), tiles, portals: vec![], entity_spawns: vec![], revision: 0 }
+    }
+
+    // Tiles are materials from `materials`, referenced through the legend of the file.
+    pub fn load(path: &Path, materials: &MaterialRegistry) -> Result<Map, LoadError> {
+        let file: MapFile = asset::read_toml(path)?;
 
-impl Tile {
-    pub fn color(&self) -> Option<Color> {
-        match self {
-            Tile::Wall2 => Some(Color::GREEN),
-            Tile::Wall  => Some(Color::RED),
-            Tile::Empty => None,
-            Tile::Spawn => None,
+        let invalid = |message: String| LoadError::invalid(path, message);
+
+        let height = file.rows.len() as MapCoordinate;
+        let width  = file.rows.first().map_or(0, |row| row.chars().count()) as MapCoordinate;
+
+        if width == 0 {
+            return Err(invalid("the map has no tiles".to_owned()));
         }
+
+        let mut tiles = vec![];
+
+        for (y, row) in file.rows.iter().enumerate() {
+            if row.chars().count() as MapCoordinate != width {
+                return Err(invalid(format!("row {} is not {} tiles wide", y, width)));
+            }
+
+            for (x, symbol) in row.chars().enumerate() {
+                let id   = file.legend.get(&symbol).ok_or_else(|| invalid(format!("{:?} at ({}, {}) is not in the legend", symbol, x, y)))?;
+                let tile = materials.get(id).ok_or_else(|| invalid(format!("there is no material {:?}", id)))?;
+
+                tiles.push(tile);
+            }
+        }
+
+        let [spawn_x, spawn_y] = file.spawn;
+
+        if spawn_x >= width || spawn_y >= height {
+            return Err(invalid(format!("spawn ({}, {}) is outside of the map", spawn_x, spawn_y)));
+        }
+
+        let mut map = Map::new(width, height, tiles, MapPosition { x: spawn_x, y: spawn_y });
+
+        for portal in file.portals {
+            let faces = [portal.from, portal.to].map(|face| WallFace::new(face.x, face.y, face.side));
+
+            if let Some(face) = faces.iter().find(|face| !map.can_hold_portal(face)) {
+                return Err(invalid(format!("portal at ({}, {}) is not on the face of a block", face.cell.x, face.cell.y)));
+            }
+
+            map.link_faces(faces[0], faces[1]);
+        }
+
+        map.entity_spawns = file.entities;
+
+        Ok(map)
+    }
+
+    // Whatever goes into one face comes out of the other, both ways.
+    pub fn link_faces(&mut self, a: WallFace, b: WallFace) {
+        assert!(self.can_hold_portal(&a) && self.can_hold_portal(&b), "Portals must be on the face of a block!");
+
+        self.portals.push(Portal { entrance: a, exit: b });
+        self.portals.push(Portal { entrance: b, exit: a });
+    }
+
+    pub fn get_portals(&self) -> &[Portal] {
+        &self.portals
     }
 
-    pub fn is_visible(&self) -> bool {
-        match self {
-            Tile::Wall2 => true,
-            Tile::Wall  => true,
-            Tile::Empty => false,
-            Tile::Spawn => false,
+    pub fn get_revision(&self) -> u64 {
+        self.revision
+    }
+
+    /*
+    * Gameplay ray query (hitscan, line of sight, picking...), with every cell the ray went through. `stops_ray`
+    * decides which tiles the ray cannot go through. The direction does not need to be normalized.
+    */
+    pub fn raycast<F: Fn(Tile) -> bool>(&self, origin: WorldPosition, direction: WorldVector, max_distance: WorldLength, stops_ray: F) -> RayTrace {
+        let mut traversed = vec![];
+        let     direction = direction.normalize_or_zero();
+
+        if direction == WorldVector::ZERO {
+            return RayTrace { hit: None, traversed };
         }
+
+        let hit = ray::trace(self, origin, direction, max_distance, stops_ray, |cell| traversed.push(cell));
+
+        RayTrace { hit, traversed }
     }
-}
 
-pub struct Map {
-    pub height: MapCoordinate,
-    pub width:  MapCoordinate,
-        tiles: &'static [Tile],
-}
+    // Every cell touched by the segment going from `from` to `to`, in order.
+    pub fn cells_along(&self, from: WorldPosition, to: WorldPosition) -> Vec<MapPosition> {
+        let mut trace = self.raycast(from, to - from, from.distance(to), |_| false);
+        let     start = world_position_to_signed_map_position(from);
+
+        // A degenerate segment still lies in its own cell.
+        if trace.traversed.is_empty() && self.contains(&start) {
+            trace.traversed.push(MapPosition { x: start.x as MapCoordinate, y: start.y as MapCoordinate });
+        }
+
+        trace.traversed
+    }
 
-pub fn world_position_to_signed_map_position(position: glam::Vec2) -> SignedMapPosition {
-    SignedMapPosition { x: position.x as SignedMapCoordinate, y: position.y as SignedMapCoordinate }
+    pub fn contains(&self, position: &SignedMapPosition) -> bool {
+           position.x >= 0
+        && position.y >= 0
+        && (position.x as MapCoordinate) < self.width
+        && (position.y as MapCoordinate) < self.height
+    }
+
+    pub(crate) fn get_tile(&self, x: MapCoordinate, y: MapCoordinate) -> Tile {
+        self.tiles[(y * self.width + x) as usize]
+    }
+
+    fn can_hold_portal(&self, face: &WallFace) -> bool {
+        if face.cell.x >= self.width || face.cell.y >= self.height {
+            return false;
+        }
+
+        let tile = self.get_tile(face.cell.x, face.cell.y);
+
+        tile.is_visible() && tile.shape() == TileShape::Block
+    }
 }
 
-impl Map {
-    pub fn make_demo_map() -> Map {
-        Map { tiles: DEMO_MAP_TILES, width: DEMO_MAP_TILES_WIDTH, height: DEMO_MAP_TILES_HEIGHT }
+// Tiles are one unit high, the eye being in the middle.
+impl Level for Map {
+    fn walk_ray(&self, origin: WorldPosition, ray_direction: WorldDirection, max_distance: WorldLength, is_hit: &dyn Fn(Tile) -> bool, on_hit: &mut dyn FnMut(RayHit) -> bool) -> u32 {
+        ray::walk(self, origin, ray_direction, max_distance, is_hit, |_| (), on_hit)
+    }
+
+    // Gameplay on tiles goes through `raycast`.
+    fn trace_ray(&self, origin: WorldPosition, direction: WorldVector, max_distance: WorldLength, stops_ray: &dyn Fn(Tile) -> bool) -> Option<RayHit> {
+        self.raycast(origin, direction, max_distance, stops_ray).hit
     }
 
-    pub fn find_first_spawn(&self) -> MapPosition {
-        self
-        .tiles
-        .iter()
-        .position(|tile| *tile == Tile::Spawn)
+    fn get_heights(&self, _position: WorldPosition) -> Heights {
+        Heights { floor: 0.0, ceiling: 1.0 }
+    }
+
+    // There is no roof outside of the map.
+    fn is_open_sky(&self, position: WorldPosition) -> bool {
+        let cell = world_position_to_signed_map_position(position);
+
+        !self.contains(&cell) || self.get_tile(cell.x as MapCoordinate, cell.y as MapCoordinate).is_open_sky()
+    }
+
+    fn find_spawn(&self) -> WorldPosition {
+        world::map_point_to_world_position(self.spawn)
+    }
+
+    fn get_entity_spawns(&self) -> &[EntitySpawn] {
+        &self.entity_spawns
+    }
+
+    fn get_tile_changes(&self) -> Vec<TileChange> {
+        (0 .. self.tiles.len())
+        .filter(|index| self.tiles[*index] != self.loaded_tiles[*index])
         .map(|index| {
             let index = index as MapCoordinate;
 
-            let y = index / self.width;
-
-            MapPosition { y, x: index % self.width }
+            TileChange { position: MapPosition { x: index % self.width, y: index / self.width }, tile: self.tiles[index as usize] }
         })
-        .expect("No spawn found in map!")
+        .collect()
     }
 
-    pub(crate) fn get_tile(&self, x: MapCoordinate, y: MapCoordinate) -> Tile {
-        self.tiles[(y * self.width + x) as usize]
+    fn set_tile(&mut self, position: MapPosition, tile: Tile) -> bool {
+        if position.x >= self.width || position.y >= self.height {
+            return false;
+        }
+
+        let index = (position.y * self.width + position.x) as usize;
+
+        if self.tiles[index] != tile {
+            self.tiles[index]  = tile;
+            self.revision     += 1;
+        }
+
+        true
+    }
+
+    fn get_tile_map(&self) -> Option<&Map> {
+        Some(self)
+    }
+
+    fn draw_on_minimap(&self, raycaster: &mut Raycaster) {
+        minimap::draw_map_tiles(raycaster, self);
+    }
+
+    fn find_portal(&self, hit: &RayHit) -> Option<Portal> {
+        self.portals.iter().find(|portal| portal.entrance.cell == hit.cell && portal.entrance.side.get_normal() == hit.normal).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{material::MaterialRegistry, world::{WorldPosition, WorldVector}};
+
+    use super::{Map, MapPosition};
+
+    // 5 × 3, walls all around a corridor going east.
+    fn make_corridor() -> Map {
+        let materials = MaterialRegistry::load(Path::new("assets/materials.toml")).unwrap();
+        let wall      = materials.get("wall").unwrap();
+        let empty     = materials.get("empty").unwrap();
+        let tiles     = (0 .. 15).map(|index| if index % 5 == 0 || index % 5 == 4 || index / 5 != 1 { wall } else { empty }).collect();
+
+        Map::new(5, 3, tiles, MapPosition { x: 1, y: 1 })
+    }
+
+    #[test]
+    fn raycast_reports_the_hit_and_the_cells_on_the_way() {
+        let map   = make_corridor();
+        let trace = map.raycast(WorldPosition::new(1.5, 1.5), WorldVector::new(2.0, 0.0), 10.0, |tile| tile.blocks_movement());
+        let hit   = trace.hit.unwrap();
+
+        assert_eq!(hit.cell, MapPosition { x: 4, y: 1 });
+        assert_eq!(hit.point, WorldPosition::new(4.0, 1.5));
+        assert_eq!(hit.normal, WorldVector::new(-1.0, 0.0));
+        assert_eq!(hit.distance, 2.5);
+        assert_eq!(trace.traversed, [MapPosition { x: 1, y: 1 }, MapPosition { x: 2, y: 1 }, MapPosition { x: 3, y: 1 }, MapPosition { x: 4, y: 1 }]);
+
+        // Nothing to hit within reach.
+        assert!(map.raycast(WorldPosition::new(1.5, 1.5), WorldVector::new(1.0, 0.0), 1.0, |tile| tile.blocks_movement()).hit.is_none());
+    }
+
+    #[test]
+    fn cells_along_go_from_one_end_to_the_other() {
+        let map = make_corridor();
+
+        assert_eq!(map.cells_along(WorldPosition::new(1.5, 1.5), WorldPosition::new(3.25, 1.75)), [MapPosition { x: 1, y: 1 }, MapPosition { x: 2, y: 1 }, MapPosition { x: 3, y: 1 }]);
+        assert_eq!(map.cells_along(WorldPosition::new(2.5, 1.5), WorldPosition::new(2.5, 1.5)), [MapPosition { x: 2, y: 1 }]);
     }
 }
@@ -0,0 +1,350 @@
+use std::{collections::HashMap, fmt, hash::{Hash, Hasher}, path::{Path, PathBuf}, sync::{Mutex, OnceLock}};
+
+use ggez::graphics::Color;
+use serde::Deserialize;
+
+use crate::{asset::{self, LoadError}, portal::FaceSide, texture::{self, Texture}, world::{WorldPosition, WorldVector}};
+
+/*
+* What walls are made of. Materials are described in a TOML file (see assets/materials.toml) and looked up by
+* id, so new wall types only need a new entry in that file:
+*
+*   [[materials]]
+*   id         = "brick"
+*   color      = [0.6, 0.3, 0.2]
+*   textures   = { default = "textures/brick.png", north = "builtin:window" }
+*   visible    = true
+*   solid      = true
+*
+* Textures are either PNG files, relative to the materials file, or one of the built-in procedural textures
+* ("builtin:window", "builtin:grate", "builtin:fence" and "builtin:barrel").
+*/
+
+/*
+* Most tiles fill their whole cell. Thin and diagonal walls are a segment within it, given by its two ends,
+* in cell coordinates: (0, 0) is the top-left corner of the cell, (1, 1) its bottom-right corner.
+*/
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TileShape {
+    Block,
+    Segment(WorldPosition, WorldPosition),
+}
+
+// How a reflective tile alters what it reflects: the reflection is tinted, then mixed with the tile's own colour.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Reflection {
+    pub tint:         Color,
+    // From 0 (only the tile's colour is seen) to 1 (only the reflection is seen).
+    pub reflectivity: f32,
+}
+
+// One texture per face, faces without their own texture use the default one (if any).
+#[derive(Default)]
+pub struct FaceTextures {
+    pub default: Option<Texture>,
+    pub north:   Option<Texture>,
+    pub east:    Option<Texture>,
+    pub south:   Option<Texture>,
+    pub west:    Option<Texture>,
+}
+
+pub struct Material {
+    pub id:                 String,
+    // Used when there is no texture, and on the minimap.
+    pub color:              Option<Color>,
+    pub textures:           FaceTextures,
+    pub shape:              TileShape,
+    pub is_visible:         bool,
+    pub is_solid:           bool,
+    // Closed doors block movement, but whoever can open them finds their way through them.
+    pub is_door:            bool,
+    // Rays hitting see-through tiles go on, the renderer blends whatever is behind.
+    pub is_transparent:     bool,
+    pub blocks_projectiles: bool,
+    // Outdoor cells have no ceiling, the sky is drawn instead.
+    pub is_open_sky:        bool,
+    pub reflection:         Option<Reflection>,
+    // From 0 (lit by the map like any other wall) to 1 (full brightness whichever way it faces).
+    pub light:              f32,
+    // Health lost per second by whoever touches it.
+    pub damage_on_touch:    f32,
+}
+
+/*
+* Handle to a registered material. Materials live as long as the program so that tiles stay small and
+* copyable, like the enum they replace. Two tiles are equal when they use the same material id.
+*/
+#[derive(Clone, Copy)]
+pub struct Tile(&'static Material);
+
+#[derive(Clone)]
+pub struct MaterialRegistry {
+    tiles: HashMap<String, Tile>,
+}
+
+/*
+* Every materials file is only loaded once, its materials are then shared by whatever loads it again (the next
+* level, a save, a replay...), so that they are not made again every time, never to be freed. Files are known
+* by their canonical path.
+*/
+static LOADED_REGISTRIES: OnceLock<Mutex<HashMap<PathBuf, MaterialRegistry>>> = OnceLock::new();
+
+// Shape of the materials file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialsFile {
+    materials: Vec<MaterialDefinition>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDefinition {
+    id:                 String,
+    color:              Option<[f32; 3]>,
+    #[serde(default)]
+    textures:           TextureNames,
+    // Ends of the segment, in cell coordinates. Blocks have none.
+    segment:            Option<[[f32; 2]; 2]>,
+    #[serde(default)]
+    visible:            bool,
+    #[serde(default)]
+    solid:              bool,
+    #[serde(default)]
+    door:               bool,
+    #[serde(default)]
+    transparent:        bool,
+    // Defaults to `solid`.
+    blocks_projectiles: Option<bool>,
+    #[serde(default)]
+    open_sky:           bool,
+    reflection:         Option<ReflectionDefinition>,
+    #[serde(default)]
+    light:              f32,
+    #[serde(default)]
+    damage_on_touch:    f32,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct TextureNames {
+    default: Option<String>,
+    north:   Option<String>,
+    east:    Option<String>,
+    south:   Option<String>,
+    west:    Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReflectionDefinition {
+    tint:         [f32; 3],
+    reflectivity: f32,
+}
+
+impl Tile {
+    pub fn get_id(&self) -> &'static str {
+        &self.0.id
+    }
+
+    pub fn color(&self) -> Option<Color> {
+        self.0.color
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.0.is_visible
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.0.is_transparent
+    }
+
+    pub fn blocks_sight(&self) -> bool {
+        self.is_visible() && !self.is_transparent()
+    }
+
+    pub fn blocks_movement(&self) -> bool {
+        self.0.is_solid
+    }
+
+    pub fn is_door(&self) -> bool {
+        self.0.is_door
+    }
+
+    // Bullets and other projectiles fly between the bars of grates and through the holes of fences.
+    pub fn blocks_projectiles(&self) -> bool {
+        self.0.blocks_projectiles
+    }
+
+    pub fn shape(&self) -> TileShape {
+        self.0.shape
+    }
+
+    pub fn reflection(&self) -> Option<Reflection> {
+        self.0.reflection
+    }
+
+    pub fn is_reflective(&self) -> bool {
+        self.reflection().is_some()
+    }
+
+    pub fn is_open_sky(&self) -> bool {
+        self.0.is_open_sky
+    }
+
+    pub fn get_light(&self) -> f32 {
+        self.0.light
+    }
+
+    pub fn get_damage_on_touch(&self) -> f32 {
+        self.0.damage_on_touch
+    }
+
+    // Texture of the face looking towards `normal`. Diagonal faces use the side they mostly look at.
+    pub fn get_texture(&self, normal: WorldVector) -> Option<&'static Texture> {
+        let textures = &self.0.textures;
+
+        let face_texture = match FaceSide::from_normal(normal) {
+            FaceSide::North => &textures.north,
+            FaceSide::East  => &textures.east,
+            FaceSide::South => &textures.south,
+            FaceSide::West  => &textures.west,
+        };
+
+        face_texture.as_ref().or(textures.default.as_ref())
+    }
+}
+
+impl PartialEq for Tile {
+    fn eq(&self, other: &Tile) -> bool {
+        self.0.id == other.0.id
+    }
+}
+
+impl Eq for Tile {}
+
+impl Hash for Tile {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.id.hash(state);
+    }
+}
+
+impl fmt::Debug for Tile {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "Tile({})", self.0.id)
+    }
+}
+
+impl MaterialRegistry {
+    // Changes made to the file once it was loaded are not seen, see `LOADED_REGISTRIES`.
+    pub fn load(path: &Path) -> Result<MaterialRegistry, LoadError> {
+        let key        = path.canonicalize().map_err(|error| LoadError::Io { path: path.to_owned(), error })?;
+        let mut loaded = LOADED_REGISTRIES.get_or_init(Default::default).lock().unwrap_or_else(|error| error.into_inner());
+
+        if let Some(registry) = loaded.get(&key) {
+            return Ok(registry.clone());
+        }
+
+        let registry = MaterialRegistry::read(path)?;
+
+        loaded.insert(key, registry.clone());
+
+        Ok(registry)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Tile> {
+        self.tiles.get(id).copied()
+    }
+
+    // Materials are made here, and only here, for good.
+    fn read(path: &Path) -> Result<MaterialRegistry, LoadError> {
+        let file: MaterialsFile = asset::read_toml(path)?;
+
+        let mut tiles = HashMap::new();
+
+        for definition in file.materials {
+            let id = definition.id.clone();
+
+            if tiles.contains_key(&id) {
+                return Err(LoadError::invalid(path, format!("material {:?} is defined twice", id)));
+            }
+
+            let material = make_material(path, definition)?;
+
+            tiles.insert(id, Tile(Box::leak(Box::new(material))));
+        }
+
+        Ok(MaterialRegistry { tiles })
+    }
+}
+
+fn make_material(path: &Path, definition: MaterialDefinition) -> Result<Material, LoadError> {
+    let invalid = |message: &str| LoadError::invalid(path, format!("material {:?}: {}", definition.id, message));
+
+    let to_color = |[r, g, b]: [f32; 3]| Color::new(r, g, b, 1.0);
+
+    if definition.visible && definition.color.is_none() {
+        return Err(invalid("visible materials need a colour"));
+    }
+
+    if definition.door && !definition.solid {
+        return Err(invalid("doors must be solid, an open door is another material"));
+    }
+
+    if definition.transparent && !definition.visible {
+        return Err(invalid("only visible materials can be transparent"));
+    }
+
+    if definition.reflection.is_some() && definition.transparent {
+        return Err(invalid("transparent materials cannot reflect"));
+    }
+
+    if !(0.0 ..= 1.0).contains(&definition.light) {
+        return Err(invalid("light must be between 0 and 1"));
+    }
+
+    let shape = match definition.segment {
+        Some([from, to]) => TileShape::Segment(WorldPosition::from(from), WorldPosition::from(to)),
+        None             => TileShape::Block,
+    };
+
+    let reflection = definition.reflection.as_ref().map(|reflection| {
+        Reflection { tint: to_color(reflection.tint), reflectivity: num::clamp(reflection.reflectivity, 0.0, 1.0) }
+    });
+
+    let load  = |name: &Option<String>| name.as_deref().map(|name| load_texture(path, name)).transpose();
+    let names = &definition.textures;
+
+    let textures = FaceTextures {
+        default: load(&names.default)?,
+        north:   load(&names.north)?,
+        east:    load(&names.east)?,
+        south:   load(&names.south)?,
+        west:    load(&names.west)?,
+    };
+
+    Ok(Material {
+        shape,
+        reflection,
+        textures,
+        color:              definition.color.map(to_color),
+        is_visible:         definition.visible,
+        is_solid:           definition.solid,
+        is_door:            definition.door,
+        is_transparent:     definition.transparent,
+        blocks_projectiles: definition.blocks_projectiles.unwrap_or(definition.solid),
+        is_open_sky:        definition.open_sky,
+        light:              definition.light,
+        damage_on_touch:    definition.damage_on_touch,
+        id:                 definition.id,
+    })
+}
+
+// Image files are relative to the materials file.
+fn load_texture(materials_path: &Path, name: &str) -> Result<Texture, LoadError> {
+    let directory = materials_path.parent().unwrap_or_else(|| Path::new(""));
+
+    match name.strip_prefix("builtin:") {
+        Some(builtin) => texture::make_builtin_texture(builtin).ok_or_else(|| LoadError::invalid(materials_path, format!("there is no built-in texture named {:?}", builtin))),
+        None          => Texture::load(&directory.join(name)),
+    }
+}
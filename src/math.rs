@@ -0,0 +1,72 @@
+use crate::world::{WorldDirection, WorldLength, WorldPosition, WorldVector};
+
+pub type SegmentRatio = f32;
+
+/*
+* Solves origin + distance * direction = from + ratio * (to - from).
+*
+* Taking the 2D cross product (perp_dot) of both sides with one of the two vectors makes the other
+* term vanish, since a vector crossed with itself is 0, leaving:
+*   distance = (from - origin) × (to - from) / (direction × (to - from))
+*   ratio    = (from - origin) × direction   / (direction × (to - from))
+*
+* The denominator is 0 when the ray and the segment are parallel, in which case they never meet
+* (or overlap, which we do not consider a hit). Returns the distance along the ray (in `direction`
+* lengths), and where the segment was hit, from 0 (`from`) to 1 (`to`).
+*/
+pub fn intersect_ray_with_segment(origin: WorldPosition, direction: WorldDirection, from: WorldPosition, to: WorldPosition) -> Option<(WorldLength, SegmentRatio)> {
+    let segment     = to - from;
+    let denominator = direction.perp_dot(segment);
+
+    if denominator.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let distance = (from - origin).perp_dot(segment)   / denominator;
+    let ratio    = (from - origin).perp_dot(direction) / denominator;
+
+    if distance < 0.0 || !(0.0 ..= 1.0).contains(&ratio) {
+        return None;
+    }
+
+    Some((distance, ratio))
+}
+
+// Unit vector perpendicular to `segment`, on the side `direction` comes from.
+pub fn get_facing_normal(segment: WorldVector, direction: WorldDirection) -> WorldVector {
+    let normal = WorldVector { x: -segment.y, y: segment.x }.normalize();
+
+    if normal.dot(direction) > 0.0 { -normal } else { normal }
+}
+
+/*
+* Mirror image of `direction` about a surface of unit `normal`: the component along the normal is flipped,
+* the component along the surface is kept.
+*   reflected = direction - 2 * (direction · normal) * normal
+*/
+pub fn reflect(direction: WorldDirection, normal: WorldVector) -> WorldDirection {
+    direction - normal * (2.0 * direction.dot(normal))
+}
+
+/*
+* Even-odd rule: a point is inside a polygon when a ray going from it towards +X crosses the outline an odd
+* number of times. Each edge straddling the horizontal line of the point is crossed at some X, which counts
+* when it is to the right of the point.
+*/
+pub fn is_point_in_polygon(point: WorldPosition, outline: &[WorldPosition]) -> bool {
+    let mut is_inside = false;
+
+    for (index, from) in outline.iter().enumerate() {
+        let to = outline[(index + 1) % outline.len()];
+
+        if (from.y > point.y) != (to.y > point.y) {
+            let crossing_x = from.x + (point.y - from.y) / (to.y - from.y) * (to.x - from.x);
+
+            if crossing_x > point.x {
+                is_inside = !is_inside;
+            }
+        }
+    }
+
+    is_inside
+}
@@ -0,0 +1,139 @@
+use ggez::graphics::Color;
+
+use crate::{path, camera::Camera, level::Level, map::{Map, MapPosition}, material::TileShape, raycaster::Raycaster, sector::SectorMap, world::WorldPosition};
+
+pub const MINIMAP_TILE_SIZE:       u16 = 6;
+pub const MINIMAP_MARGIN:          u16 = 8;
+pub const MINIMAP_DIRECTION_TILES: f32 = 1.5;
+
+pub type MinimapPixel = (f32, f32);
+
+/*
+* The world and the screen both have Y going down, so the minimap is the map seen from above,
+* scaled and moved to the top-left corner of the screen.
+*/
+pub fn world_position_to_minimap_pixel(position: WorldPosition) -> MinimapPixel {
+    (
+        (MINIMAP_MARGIN as f32) + position.x * (MINIMAP_TILE_SIZE as f32),
+        (MINIMAP_MARGIN as f32) + position.y * (MINIMAP_TILE_SIZE as f32),
+    )
+}
+
+pub fn draw_minimap(raycaster: &mut Raycaster, level: &dyn Level, camera: &Camera) {
+    let camera_color = Color::YELLOW;
+
+    level.draw_on_minimap(raycaster);
+
+    for cells in std::mem::take(&mut raycaster.debug_paths) {
+        draw_path(raycaster, &cells);
+
+        raycaster.debug_paths.push(cells);
+    }
+
+    let view_end = camera.position + camera.get_view_direction() * MINIMAP_DIRECTION_TILES;
+
+    draw_line(raycaster, world_position_to_minimap_pixel(camera.position), world_position_to_minimap_pixel(view_end), &camera_color);
+
+    let (camera_x, camera_y) = world_position_to_minimap_pixel(camera.position);
+
+    fill_square(raycaster, (camera_x - 1.0, camera_y - 1.0), 3.0, &camera_color);
+}
+
+pub fn draw_map_tiles(raycaster: &mut Raycaster, map: &Map) {
+    let empty_color  = Color::new(0.1, 0.1, 0.1, 1.0);
+    let sky_color    = Color::new(0.1, 0.2, 0.4, 1.0);
+    let portal_color = Color::MAGENTA;
+    let link_color   = Color::new(0.5, 0.0, 0.5, 1.0);
+
+    for tile_y in 0 .. map.height {
+        for tile_x in 0 .. map.width {
+            let tile         = map.get_tile(tile_x, tile_y);
+            let ground_color = if tile.is_open_sky() { sky_color } else { empty_color };
+            let color        = tile.color().unwrap_or(ground_color);
+            let tile_corner  = WorldPosition { x: tile_x as f32, y: tile_y as f32 };
+            let corner       = world_position_to_minimap_pixel(tile_corner);
+
+            match tile.shape() {
+                TileShape::Block => {
+                    fill_square(raycaster, corner, MINIMAP_TILE_SIZE as f32, &color);
+                },
+
+                TileShape::Segment(from, to) => {
+                    fill_square(raycaster, corner, MINIMAP_TILE_SIZE as f32, &ground_color);
+
+                    draw_line(
+                        raycaster,
+                        world_position_to_minimap_pixel(tile_corner + from),
+                        world_position_to_minimap_pixel(tile_corner + to),
+                        &color,
+                    );
+                },
+            }
+        }
+    }
+
+    // Each link is stored both ways, which draws it twice, harmlessly.
+    for portal in map.get_portals() {
+        let (left, right) = portal.entrance.get_ends();
+
+        draw_line(raycaster, world_position_to_minimap_pixel(portal.entrance.get_center()), world_position_to_minimap_pixel(portal.exit.get_center()), &link_color);
+        draw_line(raycaster, world_position_to_minimap_pixel(left), world_position_to_minimap_pixel(right), &portal_color);
+    }
+}
+
+// The goal of the path is marked with a square.
+pub fn draw_path(raycaster: &mut Raycaster, cells: &[MapPosition]) {
+    let path_color = Color::CYAN;
+
+    for pair in cells.windows(2) {
+        draw_line(raycaster, world_position_to_minimap_pixel(path::get_waypoint(pair[0])), world_position_to_minimap_pixel(path::get_waypoint(pair[1])), &path_color);
+    }
+
+    if let Some(goal) = cells.last() {
+        let (goal_x, goal_y) = world_position_to_minimap_pixel(path::get_waypoint(*goal));
+
+        fill_square(raycaster, (goal_x - 1.0, goal_y - 1.0), 3.0, &path_color);
+    }
+}
+
+// Sectors are not filled, only their lines are drawn. Openings between sectors are dimmer than walls.
+pub fn draw_sector_lines(raycaster: &mut Raycaster, sector_map: &SectorMap) {
+    for linedef in sector_map.get_linedefs() {
+        let mut color = linedef.tile.color().unwrap_or(Color::WHITE);
+
+        if linedef.back.is_some() {
+            color.r *= 0.5;
+            color.g *= 0.5;
+            color.b *= 0.5;
+        }
+
+        draw_line(raycaster, world_position_to_minimap_pixel(linedef.from), world_position_to_minimap_pixel(linedef.to), &color);
+    }
+}
+
+pub fn draw_line(raycaster: &mut Raycaster, from: MinimapPixel, to: MinimapPixel, color: &Color) {
+    let length = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil().max(1.0);
+
+    for step in 0 ..= (length as u32) {
+        let ratio = (step as f32) / length;
+
+        set_clipped_pixel(raycaster, from.0 + (to.0 - from.0) * ratio, from.1 + (to.1 - from.1) * ratio, color);
+    }
+}
+
+fn fill_square(raycaster: &mut Raycaster, corner: MinimapPixel, size: f32, color: &Color) {
+    for y in 0 .. (size as u16) {
+        for x in 0 .. (size as u16) {
+            set_clipped_pixel(raycaster, corner.0 + (x as f32), corner.1 + (y as f32), color);
+        }
+    }
+}
+
+// Maps bigger than the screen are cut rather than wrapped around.
+fn set_clipped_pixel(raycaster: &mut Raycaster, x: f32, y: f32, color: &Color) {
+    if x < 0.0 || y < 0.0 || x >= (raycaster.get_width() as f32) || y >= (raycaster.get_height() as f32) {
+        return;
+    }
+
+    raycaster.set_pixel(x as u16, y as u16, color);
+}
@@ -0,0 +1,331 @@
+use std::{cmp::Reverse, collections::{BinaryHeap, HashMap}};
+
+use crate::{map::{Map, MapCoordinate, MapPosition, SignedMapCoordinate, SignedMapPosition}, world::{self, WorldPosition}};
+
+// Costs are in hundredths of a tile, so that they add up exactly and the same way on every machine.
+pub type PathCost = u32;
+
+const STRAIGHT_COST:    PathCost = 100;
+const DIAGONAL_COST:    PathCost = 141;
+// Cached paths and flow fields not asked for in this many ticks are dropped.
+const MAX_UNUSED_TICKS: u64      = 120;
+
+// Start, goal, and how the agent gets around.
+type PathKey = (MapPosition, MapPosition, PathRules);
+
+// North, east, south and west first, so that 4-connectivity only looks at the first four.
+const STEPS: [(SignedMapCoordinate, SignedMapCoordinate); 8] = [(0, -1), (1, 0), (0, 1), (-1, 0), (1, -1), (1, 1), (-1, 1), (-1, -1)];
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum Connectivity {
+    // North, east, south and west only.
+    Four,
+    // Diagonals too, when both cells on either side of the diagonal are free: nothing brushes against corners.
+    Eight,
+    // Diagonals too, when either cell on either side of the diagonal is free.
+    #[allow(dead_code)]
+    EightCuttingCorners,
+}
+
+// How an agent gets around. Agents that open doors go through closed doors as if they were open.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct PathRules {
+    pub connectivity: Connectivity,
+    pub opens_doors:  bool,
+}
+
+/*
+* Cheapest way to a goal from every cell of the map, for any number of agents going to the same place (the
+* player, usually). Blocked cells next to free ones get a way out too, see `find_path`.
+*/
+pub struct FlowField {
+    rules: PathRules,
+    width: MapCoordinate,
+    costs: Vec<PathCost>,
+    next:  Vec<Option<MapPosition>>,
+}
+
+/*
+* Finds paths (A*) and flow fields (Dijkstra) over the cells of a map, and keeps them for as long as they are
+* asked for. The map keeps count of its changes, everything found before a change is forgotten: a door that
+* opened or a wall that was destroyed can make any path shorter.
+*
+* `advance` is to be called once per tick.
+*/
+#[derive(Default)]
+pub struct Pathfinder {
+    revision:    u64,
+    tick:        u64,
+    paths:       HashMap<PathKey, Cached<Option<Vec<MapPosition>>>>,
+    flow_fields: HashMap<(MapPosition, PathRules), Cached<FlowField>>,
+}
+
+struct Cached<T> {
+    value:     T,
+    last_used: u64,
+}
+
+/*
+* Cells are free when nothing in them blocks movement. Thin and diagonal walls block their whole cell, even
+* though part of it can be walked: paths go from cell to cell, not around walls within a cell.
+*/
+pub fn is_free(map: &Map, cell: SignedMapPosition, rules: &PathRules) -> bool {
+    if !map.contains(&cell) {
+        return false;
+    }
+
+    let tile = map.get_tile(cell.x as MapCoordinate, cell.y as MapCoordinate);
+
+    !tile.blocks_movement() || (rules.opens_doors && tile.is_door())
+}
+
+// Where an agent walks to, to go through `cell`.
+pub fn get_waypoint(cell: MapPosition) -> WorldPosition {
+    world::map_point_to_world_position(cell)
+}
+
+// Whether an agent at `from` can walk straight to the waypoint of `cell`, over free cells. The cell it is in does not count.
+pub fn is_straight_way(map: &Map, from: WorldPosition, cell: MapPosition, rules: &PathRules) -> bool {
+    map.cells_along(from, get_waypoint(cell)).into_iter().skip(1).all(|cell| is_free(map, to_signed(cell), rules))
+}
+
+/*
+* Cells from `from` to `to`, both included, by the cheapest way. Either end may be blocked: agents stand in
+* cells that have a thin wall in them, and the player may too. None when there is no way.
+*/
+pub fn find_path(map: &Map, from: MapPosition, to: MapPosition, rules: &PathRules) -> Option<Vec<MapPosition>> {
+    if !contains(map, from) || !contains(map, to) {
+        return None;
+    }
+
+    let index        = |cell: MapPosition| (cell.y * map.width + cell.x) as usize;
+    let mut costs    = vec![PathCost::MAX; (map.width * map.height) as usize];
+    let mut previous = vec![None; costs.len()];
+    let mut open     = BinaryHeap::new();
+
+    costs[index(from)] = 0;
+    open.push(Reverse((estimate_cost(from, to, rules), 0, index(from))));
+
+    while let Some(Reverse((_, cost, current))) = open.pop() {
+        if cost > costs[current] {
+            continue;
+        }
+
+        let cell = get_cell(map, current);
+
+        if cell == to {
+            let mut path = vec![to];
+
+            while let Some(cell) = previous[index(path[path.len() - 1])] {
+                path.push(cell);
+            }
+
+            path.reverse();
+
+            return Some(path);
+        }
+
+        for (next, step_cost) in get_steps(map, cell, rules) {
+            let next_cost = cost + step_cost;
+
+            if next_cost >= costs[index(next)] || !(next == to || is_free(map, to_signed(next), rules)) {
+                continue;
+            }
+
+            costs[index(next)]    = next_cost;
+            previous[index(next)] = Some(cell);
+
+            open.push(Reverse((next_cost + estimate_cost(next, to, rules), next_cost, index(next))));
+        }
+    }
+
+    None
+}
+
+impl FlowField {
+    pub fn new(map: &Map, goal: MapPosition, rules: &PathRules) -> FlowField {
+        let count     = (map.width * map.height) as usize;
+        let mut field = FlowField { rules: *rules, width: map.width, costs: vec![PathCost::MAX; count], next: vec![None; count] };
+        let mut open  = BinaryHeap::new();
+
+        if !contains(map, goal) {
+            return field;
+        }
+
+        let goal_index = field.get_index(goal);
+
+        field.costs[goal_index] = 0;
+        open.push(Reverse((0, goal_index)));
+
+        // Steps go both ways, so going out from the goal finds the way to it.
+        while let Some(Reverse((cost, current))) = open.pop() {
+            if cost > field.costs[current] {
+                continue;
+            }
+
+            let cell = get_cell(map, current);
+
+            for (previous, step_cost) in get_steps(map, cell, rules) {
+                let previous_index = field.get_index(previous);
+                let previous_cost  = cost + step_cost;
+
+                if previous_cost >= field.costs[previous_index] {
+                    continue;
+                }
+
+                field.costs[previous_index] = previous_cost;
+                field.next[previous_index]  = Some(cell);
+
+                // Blocked cells can be left, not gone through.
+                if is_free(map, to_signed(previous), rules) {
+                    open.push(Reverse((previous_cost, previous_index)));
+                }
+            }
+        }
+
+        field
+    }
+
+    // Cost of the cheapest way from `cell` to the goal, None when there is no way.
+    pub fn get_cost(&self, cell: MapPosition) -> Option<PathCost> {
+        self.costs.get(self.get_index(cell)).copied().filter(|cost| *cost != PathCost::MAX)
+    }
+
+    // The cell to step to from `cell`, None at the goal and when there is no way.
+    #[allow(dead_code)]
+    pub fn get_next(&self, cell: MapPosition) -> Option<MapPosition> {
+        self.next.get(self.get_index(cell)).copied().flatten()
+    }
+
+    // The free cell to step to from `cell` to get as far as possible from the goal, to run away from it.
+    pub fn get_farther(&self, map: &Map, cell: MapPosition) -> Option<MapPosition> {
+        let cost = self.get_cost(cell).unwrap_or(0);
+
+        get_steps(map, cell, &self.rules)
+        .filter(|(next, _)| is_free(map, to_signed(*next), &self.rules))
+        .filter_map(|(next, _)| self.get_cost(next).map(|next_cost| (next, next_cost)))
+        .filter(|(_, next_cost)| *next_cost > cost)
+        .max_by_key(|(_, next_cost)| *next_cost)
+        .map(|(next, _)| next)
+    }
+
+    fn get_index(&self, cell: MapPosition) -> usize {
+        if cell.x >= self.width {
+            return usize::MAX;
+        }
+
+        (cell.y * self.width + cell.x) as usize
+    }
+}
+
+impl Pathfinder {
+    pub fn new() -> Pathfinder {
+        Pathfinder::default()
+    }
+
+    // Whatever was not asked for in a while is dropped.
+    pub fn advance(&mut self) {
+        let tick = self.tick + 1;
+
+        self.tick = tick;
+        self.paths.retain(|_, path| tick - path.last_used <= MAX_UNUSED_TICKS);
+        self.flow_fields.retain(|_, field| tick - field.last_used <= MAX_UNUSED_TICKS);
+    }
+
+    pub fn invalidate(&mut self) {
+        self.paths.clear();
+        self.flow_fields.clear();
+    }
+
+    // See `find_path`.
+    pub fn find_path(&mut self, map: &Map, from: MapPosition, to: MapPosition, rules: PathRules) -> Option<&[MapPosition]> {
+        self.check_revision(map);
+
+        let tick   = self.tick;
+        let cached = self.paths.entry((from, to, rules)).or_insert_with(|| Cached { value: find_path(map, from, to, &rules), last_used: tick });
+
+        cached.last_used = tick;
+        cached.value.as_deref()
+    }
+
+    pub fn get_flow_field(&mut self, map: &Map, goal: MapPosition, rules: PathRules) -> &FlowField {
+        self.check_revision(map);
+
+        let tick   = self.tick;
+        let cached = self.flow_fields.entry((goal, rules)).or_insert_with(|| Cached { value: FlowField::new(map, goal, &rules), last_used: tick });
+
+        cached.last_used = tick;
+        &cached.value
+    }
+
+    // Paths asked for since the last tick began, for the minimap.
+    pub fn get_current_paths(&self) -> impl Iterator<Item = &[MapPosition]> {
+        self.paths.values().filter(|path| path.last_used == self.tick).filter_map(|path| path.value.as_deref())
+    }
+
+    fn check_revision(&mut self, map: &Map) {
+        if map.get_revision() != self.revision {
+            self.invalidate();
+            self.revision = map.get_revision();
+        }
+    }
+}
+
+/*
+* Neighbours of `cell` within the map that can be stepped to by the connectivity and corner rules, with what
+* stepping there costs, whether they are free or not. The rules are the same both ways.
+*/
+fn get_steps<'a>(map: &'a Map, cell: MapPosition, rules: &'a PathRules) -> impl Iterator<Item = (MapPosition, PathCost)> + 'a {
+    let count = if rules.connectivity == Connectivity::Four { 4 } else { STEPS.len() };
+    let cell  = to_signed(cell);
+
+    STEPS[.. count].iter().filter_map(move |&(dx, dy)| {
+        let next = SignedMapPosition { x: cell.x + dx, y: cell.y + dy };
+
+        if !map.contains(&next) {
+            return None;
+        }
+
+        if dx == 0 || dy == 0 {
+            return Some((to_unsigned(next), STRAIGHT_COST));
+        }
+
+        let beside_x = is_free(map, SignedMapPosition { x: next.x, y: cell.y }, rules);
+        let beside_y = is_free(map, SignedMapPosition { x: cell.x, y: next.y }, rules);
+
+        let is_allowed = match rules.connectivity {
+            Connectivity::Four                => false,
+            Connectivity::Eight               => beside_x && beside_y,
+            Connectivity::EightCuttingCorners => beside_x || beside_y,
+        };
+
+        is_allowed.then(|| (to_unsigned(next), DIAGONAL_COST))
+    })
+}
+
+// Never more than the actual cost, so that A* finds the cheapest path.
+fn estimate_cost(from: MapPosition, to: MapPosition, rules: &PathRules) -> PathCost {
+    let dx = from.x.abs_diff(to.x);
+    let dy = from.y.abs_diff(to.y);
+
+    match rules.connectivity {
+        Connectivity::Four => STRAIGHT_COST * (dx + dy),
+        _                  => STRAIGHT_COST * dx.max(dy) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dy),
+    }
+}
+
+fn contains(map: &Map, cell: MapPosition) -> bool {
+    cell.x < map.width && cell.y < map.height
+}
+
+fn get_cell(map: &Map, index: usize) -> MapPosition {
+    MapPosition { x: (index as MapCoordinate) % map.width, y: (index as MapCoordinate) / map.width }
+}
+
+fn to_signed(cell: MapPosition) -> SignedMapPosition {
+    SignedMapPosition { x: cell.x as SignedMapCoordinate, y: cell.y as SignedMapCoordinate }
+}
+
+fn to_unsigned(cell: SignedMapPosition) -> MapPosition {
+    MapPosition { x: cell.x as MapCoordinate, y: cell.y as MapCoordinate }
+}
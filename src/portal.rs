@@ -0,0 +1,110 @@
+use serde::Deserialize;
+
+use crate::{map::{MapCoordinate, MapPosition}, world::{self, TILE_LENGTH, WorldAngle, WorldDirection, WorldPosition, WorldVector}};
+
+// Side of a cell, the face on that side looks away from the cell.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FaceSide {
+    North,
+    East,
+    South,
+    West,
+}
+
+// One of the four faces of a block.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct WallFace {
+    pub cell: MapPosition,
+    pub side: FaceSide,
+}
+
+/*
+* One-way link between two wall faces: whatever goes into `entrance` comes out of `exit`. Maps link faces
+* both ways, see `Map::link_faces`.
+*/
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Portal {
+    pub entrance: WallFace,
+    pub exit:     WallFace,
+}
+
+/*
+* Rigid transform taking the entrance face onto the exit face: positions are rotated around the middle of
+* the entrance, then moved to the middle of the exit. Going into the entrance means going against its normal,
+* coming out of the exit means going along its normal, so the rotation turns the first into the second.
+*
+* Being a rotation (and not a reflection), what is on your right when walking in is still on your right when
+* walking out.
+*/
+#[derive(Clone, Copy, Debug)]
+pub struct PortalTransform {
+    entrance_center: WorldPosition,
+    exit_center:     WorldPosition,
+    rotation:        WorldAngle,
+}
+
+impl FaceSide {
+    pub fn get_normal(&self) -> WorldVector {
+        match self {
+            FaceSide::North => WorldVector { x:  0.0, y: -1.0 },
+            FaceSide::East  => WorldVector { x:  1.0, y:  0.0 },
+            FaceSide::South => WorldVector { x:  0.0, y:  1.0 },
+            FaceSide::West  => WorldVector { x: -1.0, y:  0.0 },
+        }
+    }
+
+    // Side whose normal is the closest to `normal`. Exact diagonals go to the vertical sides.
+    pub fn from_normal(normal: WorldVector) -> FaceSide {
+        match (normal.x.abs() > normal.y.abs(), normal.x > 0.0, normal.y > 0.0) {
+            (true,  true,  _)     => FaceSide::East,
+            (true,  false, _)     => FaceSide::West,
+            (false, _,     true)  => FaceSide::South,
+            (false, _,     false) => FaceSide::North,
+        }
+    }
+}
+
+impl WallFace {
+    pub fn new(x: MapCoordinate, y: MapCoordinate, side: FaceSide) -> WallFace {
+        WallFace { cell: MapPosition { x, y }, side }
+    }
+
+    pub fn get_center(&self) -> WorldPosition {
+        world::map_point_to_world_position(self.cell) + self.side.get_normal() * (TILE_LENGTH / 2.0)
+    }
+
+    // Both ends of the face, from left to right for someone looking at it.
+    pub fn get_ends(&self) -> (WorldPosition, WorldPosition) {
+        let normal  = self.side.get_normal();
+        let tangent = WorldVector { x: normal.y, y: -normal.x } * (TILE_LENGTH / 2.0);
+
+        (self.get_center() - tangent, self.get_center() + tangent)
+    }
+}
+
+impl Portal {
+    pub fn get_transform(&self) -> PortalTransform {
+        let inwards  = -self.entrance.side.get_normal();
+        let outwards = self.exit.side.get_normal();
+
+        // Clockwise angle from one to the other, the 2D cross product being the sine and the dot product the cosine.
+        let rotation = inwards.perp_dot(outwards).atan2(inwards.dot(outwards));
+
+        PortalTransform { rotation, entrance_center: self.entrance.get_center(), exit_center: self.exit.get_center() }
+    }
+}
+
+impl PortalTransform {
+    pub fn transform_position(&self, position: WorldPosition) -> WorldPosition {
+        self.exit_center + world::rotate_clockwise(position - self.entrance_center, self.rotation)
+    }
+
+    pub fn transform_direction(&self, direction: WorldDirection) -> WorldDirection {
+        world::rotate_clockwise(direction, self.rotation)
+    }
+
+    pub fn get_rotation(&self) -> WorldAngle {
+        self.rotation
+    }
+}
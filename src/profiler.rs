@@ -0,0 +1,182 @@
+use std::{collections::VecDeque, fs::File, io::{self, BufWriter, Write}, path::Path};
+
+use ggez::graphics::Color;
+
+use crate::{font::{Font, TextStyle}, raycaster::{RayStats, Raycaster}};
+
+// Figures are averaged over this many frames, so that they can be read.
+const AVERAGED_FRAMES:      usize = 60;
+// The overlay is scaled by whole pixels, one for every this many pixels of screen height.
+const OVERLAY_UNIT_HEIGHT:  u16   = 300;
+// In overlay pixels.
+const OVERLAY_MARGIN:       i32   = 4;
+const HEATMAP_HEIGHT:       i32   = 4;
+
+/*
+* What one frame cost. Rendering is the time spent in `Raycaster::update_framebuffer`, uploading the time
+* spent handing the rows that changed to the GPU. Times are in seconds.
+*/
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameProfile {
+    pub frame_seconds:  f32,
+    pub render_seconds: f32,
+    pub upload_seconds: f32,
+    pub rays:           u32,
+    // DDA steps of all the rays.
+    pub steps:          u64,
+    pub max_steps:      u32,
+    pub escaped_rays:   u32,
+}
+
+/*
+* Renderer figures, shown over the screen when `show_overlay` is set: frame, render and upload times, how many
+* DDA steps rays take and how many left the level, and a strip along the top of the screen, each column of
+* which goes from blue to red with the steps of its ray.
+*/
+pub struct Profiler {
+    pub show_overlay: bool,
+        font:         Font,
+        recent:       VecDeque<FrameProfile>,
+        column_steps: Vec<u32>,
+}
+
+/*
+* Writes frame profiles to a CSV file, one line per frame after a header, for profiling runs. Times are in
+* milliseconds:
+*
+*   frame,frame_ms,render_ms,upload_ms,rays,average_steps,max_steps,escaped_rays
+*   1,16.712,4.105,0.873,800,11.52,38,0
+*/
+pub struct ProfileRecorder {
+    writer: BufWriter<File>,
+    frame:  u64,
+}
+
+impl FrameProfile {
+    pub fn new(frame_seconds: f32, render_seconds: f32, upload_seconds: f32, ray_stats: &RayStats) -> FrameProfile {
+        FrameProfile {
+            frame_seconds,
+            render_seconds,
+            upload_seconds,
+
+            rays:         ray_stats.column_steps.len() as u32,
+            steps:        ray_stats.column_steps.iter().map(|steps| *steps as u64).sum(),
+            max_steps:    ray_stats.column_steps.iter().copied().max().unwrap_or(0),
+            escaped_rays: ray_stats.escaped_rays,
+        }
+    }
+
+    pub fn get_average_steps(&self) -> f32 {
+        if self.rays == 0 { 0.0 } else { (self.steps as f32) / (self.rays as f32) }
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler { show_overlay: false, font: Font::builtin(), recent: VecDeque::with_capacity(AVERAGED_FRAMES), column_steps: vec![] }
+    }
+
+    // Steps of each column are kept for the heatmap, from the last frame only.
+    pub fn add(&mut self, profile: FrameProfile, ray_stats: &RayStats) {
+        if self.recent.len() == AVERAGED_FRAMES {
+            self.recent.pop_front();
+        }
+
+        self.recent.push_back(profile);
+
+        self.column_steps.clear();
+        self.column_steps.extend_from_slice(&ray_stats.column_steps);
+    }
+
+    // Over the last frames, the maximum steps being the largest of them. Counts are rounded down.
+    pub fn get_average(&self) -> FrameProfile {
+        let count   = self.recent.len().max(1);
+        let average = |seconds: fn(&FrameProfile) -> f32| self.recent.iter().map(seconds).sum::<f32>() / (count as f32);
+        let counted = |amount: fn(&FrameProfile) -> u64| self.recent.iter().map(amount).sum::<u64>() / (count as u64);
+
+        FrameProfile {
+            frame_seconds:  average(|profile| profile.frame_seconds),
+            render_seconds: average(|profile| profile.render_seconds),
+            upload_seconds: average(|profile| profile.upload_seconds),
+            rays:           counted(|profile| profile.rays as u64) as u32,
+            steps:          counted(|profile| profile.steps),
+            max_steps:      self.recent.iter().map(|profile| profile.max_steps).max().unwrap_or(0),
+            escaped_rays:   counted(|profile| profile.escaped_rays as u64) as u32,
+        }
+    }
+
+    // Figures on the left of the screen, a third of the way down, the heatmap at the very top.
+    pub fn draw(&self, raycaster: &mut Raycaster) {
+        if !self.show_overlay {
+            return;
+        }
+
+        let scale   = (raycaster.get_height() / OVERLAY_UNIT_HEIGHT).max(1);
+        let unit    = scale as i32;
+        let average = self.get_average();
+        let style   = TextStyle { shadow: Some(Color::BLACK), ..TextStyle::new(Color::CYAN, scale) };
+
+        let text    = format!(
+            "FRAME   {:6.2} MS\nRENDER  {:6.2} MS\nUPLOAD  {:6.2} MS\nSTEPS   {:6.2} PER RAY, {} AT MOST\nESCAPED {} OF {} RAYS",
+            average.frame_seconds * 1000.0,
+            average.render_seconds * 1000.0,
+            average.upload_seconds * 1000.0,
+            average.get_average_steps(),
+            average.max_steps,
+            average.escaped_rays,
+            average.rays,
+        );
+
+        let most_steps = self.column_steps.iter().copied().max().unwrap_or(0).max(1);
+
+        for (x, steps) in self.column_steps.iter().enumerate().take(raycaster.get_width() as usize) {
+            raycaster.fill_rectangle(x as i32, 0, 1, HEATMAP_HEIGHT * unit, &get_heat_color((*steps as f32) / (most_steps as f32)));
+        }
+
+        self.font.draw(raycaster, &text, OVERLAY_MARGIN * unit, (raycaster.get_height() as i32) / 3, &style);
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::new()
+    }
+}
+
+impl ProfileRecorder {
+    pub fn create(path: &Path) -> io::Result<ProfileRecorder> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writeln!(writer, "frame,frame_ms,render_ms,upload_ms,rays,average_steps,max_steps,escaped_rays")?;
+
+        Ok(ProfileRecorder { writer, frame: 0 })
+    }
+
+    pub fn record(&mut self, profile: &FrameProfile) -> io::Result<()> {
+        self.frame += 1;
+
+        writeln!(
+            self.writer,
+            "{},{:.3},{:.3},{:.3},{},{:.2},{},{}",
+            self.frame,
+            profile.frame_seconds * 1000.0,
+            profile.render_seconds * 1000.0,
+            profile.upload_seconds * 1000.0,
+            profile.rays,
+            profile.get_average_steps(),
+            profile.max_steps,
+            profile.escaped_rays,
+        )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// From blue (0) through green to red (1).
+fn get_heat_color(heat: f32) -> Color {
+    let heat = heat.clamp(0.0, 1.0);
+
+    Color::new((heat * 2.0 - 1.0).max(0.0), 1.0 - (heat * 2.0 - 1.0).abs(), (1.0 - heat * 2.0).max(0.0), 1.0)
+}
@@ -0,0 +1,35 @@
+/*
+* SplitMix64 (see https://prng.di.unimi.it/splitmix64.c): small, fast, and the same on every platform, so that
+* a seed and the inputs of a game are enough to play it again.
+*/
+#[derive(Clone, Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut value = self.state;
+
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+        value ^ (value >> 31)
+    }
+
+    // In [0, 1[, with the 24 bits a float can hold.
+    pub fn next_f32(&mut self) -> f32 {
+        ((self.next_u64() >> 40) as f32) / ((1u64 << 24) as f32)
+    }
+
+    // A generator made with this as its seed goes on exactly like this one.
+    pub fn get_state(&self) -> u64 {
+        self.state
+    }
+}
@@ -0,0 +1,368 @@
+use crate::{math, map::{Map, MapPosition, MapCoordinate, SignedMapPosition, world_position_to_signed_map_position}, material::{Tile, TileShape}, world::{TILE_LENGTH, WorldDirection, WorldVector, WorldLength, WorldPosition}};
+
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    // Cell of the grid the surface was hit in: the tile grid, or the spatial grid of a sector map.
+    pub cell:           MapPosition,
+    pub tile:           Tile,
+    pub point:          WorldPosition,
+    pub normal:         WorldVector,
+    pub distance:       WorldLength,
+    // Where the surface was hit, used as the horizontal texture coordinate, which wraps every world unit.
+    pub surface_offset: f32,
+    /*
+    * Lines between two sectors only have walls where the floor or the ceiling of one side is higher than the
+    * other, there is an opening in between. Tiles never have one.
+    */
+    pub has_opening:    bool,
+}
+
+// A cell of the grid met by `traverse_grid`.
+#[derive(Clone, Copy, Debug)]
+pub struct GridCell {
+    pub position:       SignedMapPosition,
+    // Whether the ray stepped along Y (rather than X) to get in. None for the cell containing the origin.
+    pub vertical_entry: Option<bool>,
+    pub entry_distance: WorldLength,
+    pub exit_distance:  WorldLength,
+}
+
+pub struct RayTrace {
+    pub hit:       Option<RayHit>,
+    pub traversed: Vec<MapPosition>,
+}
+
+// Casts a ray and stops at the first tile accepted by `stops_ray`. See `walk`.
+pub fn trace<F, V>(map: &Map, origin: WorldPosition, ray_direction: WorldDirection, max_distance: WorldLength, stops_ray: F, visit: V) -> Option<RayHit>
+where
+    F: Fn(Tile) -> bool,
+    V: FnMut(MapPosition),
+{
+    let mut first_hit = None;
+
+    walk(map, origin, ray_direction, max_distance, stops_ray, visit, |hit| {
+        first_hit = Some(hit);
+
+        false
+    });
+
+    first_hit
+}
+
+/*
+* Walks the grid from `origin` along a normalized `ray_direction`, one cell at a time. Every tile accepted
+* by `is_hit` that the ray actually meets is reported to `on_hit`, which tells whether the ray goes on
+* (through see-through tiles, for instance). The walk also ends when the travelled distance exceeds
+* `max_distance`, or when the ray leaves the map.
+*
+* The cell containing the origin is visited, but only tested for thin and diagonal walls, so that rays
+* cast from inside a block can get out of it. `visit` is called for every traversed cell, hit cells
+* included. Distances are measured along the ray. Returns how many DDA steps were taken, see `traverse_grid`.
+*
+* Both the renderer and gameplay queries go through this routine, so that what you see is what you hit.
+*/
+pub fn walk<F, V, H>(map: &Map, origin: WorldPosition, ray_direction: WorldDirection, max_distance: WorldLength, is_hit: F, mut visit: V, mut on_hit: H) -> u32
+where
+    F: Fn(Tile) -> bool,
+    V: FnMut(MapPosition),
+    H: FnMut(RayHit) -> bool,
+{
+    let x_pixel_sign = if ray_direction.x < 0.0 { -1 } else { 1 };
+    let y_pixel_sign = if ray_direction.y < 0.0 { -1 } else { 1 };
+
+    traverse_grid(origin, ray_direction, max_distance, |grid_cell| {
+        // The bounds need to be checked before reading the tile, rays cast from outside of the map included.
+        if !map.contains(&grid_cell.position) {
+            return grid_cell.vertical_entry.is_none();
+        }
+
+        let cell = MapPosition { x: grid_cell.position.x as MapCoordinate, y: grid_cell.position.y as MapCoordinate };
+        let tile = map.get_tile(cell.x, cell.y);
+
+        visit(cell);
+
+        if !is_hit(tile) {
+            return true;
+        }
+
+        let hit = match (tile.shape(), grid_cell.vertical_entry) {
+            // Thin walls do not fill their cell, we can be standing next to one.
+            (TileShape::Segment(from, to), _) => hit_segment(origin, ray_direction, max_distance, cell, tile, from, to),
+
+            (TileShape::Block, None) => None,
+
+            (TileShape::Block, Some(is_vertical_step)) => {
+                /*
+                * Blocks are either vertical or horizontal, due to the map setup. To determine if the wall is
+                * horizontal or vertical, we remember on which side the wall was hit. Because of the infinity
+                * case, you can't trust both step values. Only one can be trusted, we'll use the one that
+                * created the ray hit.
+                *
+                * Steps are measured along a normalized direction vector, so the accumulator that created the
+                * hit is exactly the distance between the origin and the wall, along the ray.
+                */
+                let distance = grid_cell.entry_distance.abs();
+
+                // The face we hit looks back towards the ray, on the axis we just stepped on.
+                let normal   = if is_vertical_step {
+                    WorldVector { x: 0.0, y: -y_pixel_sign as f32 }
+                } else {
+                    WorldVector { x: -x_pixel_sign as f32, y: 0.0 }
+                };
+
+                let point          = origin + ray_direction * distance;
+
+                /*
+                * Faces are followed counterclockwise around the block (seen from above), which is from left to
+                * right for someone looking at them, so that textures are not mirrored.
+                */
+                let surface_offset = if is_vertical_step {
+                    if y_pixel_sign > 0 { -point.x } else { point.x }
+                } else {
+                    if x_pixel_sign > 0 { point.y } else { -point.y }
+                }.rem_euclid(TILE_LENGTH);
+
+                Some(RayHit { cell, tile, point, normal, distance, surface_offset, has_opening: false })
+            },
+        };
+
+        match hit {
+            Some(hit) => on_hit(hit),
+            None      => true,
+        }
+    })
+}
+
+/*
+* The DDA behind `walk`: goes through every cell of a grid of unit cells the ray crosses, in order, starting
+* with the cell containing `origin`, until `visit_cell` returns false or the ray goes further than
+* `max_distance`. The grid has no bounds, that is up to the caller. Returns how many cells were visited, which
+* is the cost of the walk.
+*/
+pub fn traverse_grid<C: FnMut(GridCell) -> bool>(origin: WorldPosition, ray_direction: WorldDirection, max_distance: WorldLength, mut visit_cell: C) -> u32 {
+    let     x_pixel_sign  = if ray_direction.x < 0.0 { -1 } else { 1 };
+    let     y_pixel_sign  = if ray_direction.y < 0.0 { -1 } else { 1 };
+    let mut current_tile  = world_position_to_signed_map_position(origin);
+
+    /*
+    * When moving from 1 unit positively along the direction vector on a vector component (x or y),
+    * we move the other by a factor `component B / component A`.
+    *
+    * Using Pythagora's theorem, we can find how much distance we need to travel to go from
+    * one vector component (x or y) to the next. We need that because our algorithm moves
+    * exactly one tile at a time (that allows to never go through any wall), and we need
+    * to know which pixel should be visited next.
+    *
+    * We want to move the tile which is the "most" behind in terms of being ready to be
+    * updated. If X is lagging behind, then we'll move horizontally, otherwise we'll move
+    * vertically.
+    *
+    * In the case of the X component, when moving X 1 pixel to the right, we establish that:
+    * - The distance travelled by X (distance_x) is exactly 1.
+    * - The distance travelled by Y (distance_y) is exactly ray_direction.y / ray_direction.x.
+    * - The distance travelled along the direction vector is sqrt(1² + (ray_direction.y / ray_direction.x)²).
+    *
+    * We can expand the distance along the direction vector as:
+    * sqrt((ray_direction.x² + ray_direction.y²) / ray_direction.x²)
+    *
+    * Then take the denominator out of the square root:
+    * sqrt(ray_direction.x² + ray.direction.y²) / ray_direction.x.
+    *
+    * The sqrt() part is exactly ray_direction's magnitude formula, which we know to be one
+    * because direction vector are supposed to be normalized. That leaves us with:
+    * 1 / ray_direction.x.
+    *
+    * We need to be careful: it is possible that the ray is perfectly horizontal or vertical,
+    * which for the pupose of the DDA algorithm below, can be considered as a step that is,
+    * never lagging behind, therefore always greater than the other one, therefore we can
+    * set it to +∞.
+    *
+    * Also, we use absolute values because steps are for comparison only. We need to perform
+    * comparisons on equal grounds.
+    *
+    * The same logic applies to Y, leaving us with:
+    */
+    let step = WorldVector {
+        x: if ray_direction.x == 0.0 { f32::INFINITY } else { 1.0 / ray_direction.x.abs() },
+        y: if ray_direction.y == 0.0 { f32::INFINITY } else { 1.0 / ray_direction.y.abs() },
+    };
+
+    /*
+    * Our map coordinates are in the top-left corner of the tile, and the origin can be anywhere within its cell.
+    * The accumulators start at the distance travelled along the ray before crossing the first cell border on
+    * each axis, which is the distance to that border on the axis, scaled by the step.
+    *
+    * Going towards negative coordinates, the first border is the one of the current cell, otherwise it is the
+    * one of the next cell. An infinite step never crosses anything, which we keep as is instead of multiplying
+    * it by a potentially null distance.
+    */
+    let cell_corner = WorldPosition { x: current_tile.x as f32, y: current_tile.y as f32 };
+
+    let first_border_distance = |sign: i64, origin: f32, corner: f32, step: f32| -> WorldLength {
+        if step.is_infinite() {
+            step
+        } else if sign < 0 {
+            (origin - corner) * step
+        } else {
+            (corner + TILE_LENGTH - origin) * step
+        }
+    };
+
+    let mut steps_accumulator = WorldVector {
+        x: first_border_distance(x_pixel_sign, origin.x, cell_corner.x, step.x),
+        y: first_border_distance(y_pixel_sign, origin.y, cell_corner.y, step.y),
+    };
+
+    let mut grid_cell = GridCell {
+        position:       current_tile,
+        vertical_entry: None,
+        entry_distance: 0.0,
+        exit_distance:  steps_accumulator.x.min(steps_accumulator.y),
+    };
+
+    let mut visited = 1;
+
+    if !visit_cell(grid_cell) {
+        return visited;
+    }
+
+    loop {
+        let is_vertical_step = steps_accumulator.y < steps_accumulator.x;
+
+        /*
+        * The accumulator of the lagging component is the distance at which the ray crosses the next
+        * cell border. Past the maximum distance, there is nothing left to look at.
+        */
+        let travelled = if is_vertical_step { steps_accumulator.y } else { steps_accumulator.x };
+
+        if travelled > max_distance {
+            return visited;
+        }
+
+        if is_vertical_step {
+            current_tile.y += y_pixel_sign;
+        } else {
+            current_tile.x += x_pixel_sign;
+        }
+
+        // The accumulator now tells where the ray leaves the new cell.
+        if is_vertical_step {
+            steps_accumulator.y += step.y;
+        } else {
+            steps_accumulator.x += step.x;
+        }
+
+        grid_cell = GridCell {
+            position:       current_tile,
+            vertical_entry: Some(is_vertical_step),
+            entry_distance: travelled,
+            exit_distance:  steps_accumulator.x.min(steps_accumulator.y),
+        };
+
+        visited += 1;
+
+        if !visit_cell(grid_cell) {
+            return visited;
+        }
+    }
+}
+
+/*
+* Thin and diagonal walls are segments within their cell, that the ray may or may not meet while crossing
+* the cell. The segment being inside the cell, any intersection is within the cell, so there is no need to
+* check where the ray enters and leaves it.
+*/
+fn hit_segment(origin: WorldPosition, ray_direction: WorldDirection, max_distance: WorldLength, cell: MapPosition, tile: Tile, from: WorldPosition, to: WorldPosition) -> Option<RayHit> {
+    let corner            = WorldPosition { x: cell.x as f32, y: cell.y as f32 };
+    let (distance, ratio) = math::intersect_ray_with_segment(origin, ray_direction, corner + from, corner + to)?;
+
+    if distance > max_distance {
+        return None;
+    }
+
+    Some(RayHit {
+        cell,
+        tile,
+        distance,
+
+        point:          origin + ray_direction * distance,
+        normal:         math::get_facing_normal(to - from, ray_direction),
+        surface_offset: ratio,
+        has_opening:    false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{map::{Map, MapPosition, SignedMapPosition}, material::MaterialRegistry, world::{WorldDirection, WorldLength, WorldPosition}};
+
+    use super::{GridCell, RayHit, traverse_grid, walk};
+
+    const EPSILON: WorldLength = 1e-3;
+
+    fn first_hit(map: &Map, origin: WorldPosition, ray_direction: WorldDirection) -> Option<RayHit> {
+        let mut first_hit = None;
+
+        walk(map, origin, ray_direction, WorldLength::INFINITY, |tile| tile.is_visible(), |_| (), |hit| {
+            first_hit = Some(hit);
+
+            false
+        });
+
+        first_hit
+    }
+
+    #[test]
+    fn first_border_is_the_nearest() {
+        let materials = MaterialRegistry::load(Path::new("assets/materials.toml")).unwrap();
+        let wall      = materials.get("wall").unwrap();
+        let empty     = materials.get("empty").unwrap();
+
+        // 6 × 6, walls all around.
+        let tiles     = (0 .. 36).map(|index| if index % 6 == 0 || index % 6 == 5 || index / 6 == 0 || index / 6 == 5 { wall } else { empty }).collect();
+        let map       = Map::new(6, 6, tiles, MapPosition { x: 1, y: 1 });
+        let diagonal  = std::f32::consts::SQRT_2;
+
+        // Origin, direction, first cell entered, distance to it, distance to the wall.
+        let cases     = [
+            // Fractional origins, along each axis both ways.
+            ((2.25, 3.75), ( 1.0,  0.0), ( 3,  3), 0.75, 2.75),
+            ((2.25, 3.75), (-1.0,  0.0), ( 1,  3), 0.25, 1.25),
+            ((2.25, 3.75), ( 0.0, -1.0), ( 2,  2), 0.75, 2.75),
+            ((2.25, 3.75), ( 0.0,  1.0), ( 2,  4), 0.25, 1.25),
+            // Diagonals, the nearest border being on either axis.
+            ((2.25, 3.75), (-1.0, -1.0), ( 1,  3), 0.25 * diagonal, 1.25 * diagonal),
+            ((2.5,  3.25), ( 1.0, -1.0), ( 2,  2), 0.25 * diagonal, 2.25 * diagonal),
+            ((3.5,  3.5 ), ( 1.0,  0.5), ( 4,  3), 0.5 * 1.25f32.sqrt(), 1.5 * 1.25f32.sqrt()),
+            // Right against a wall, facing it, facing away from it, or getting out of it.
+            ((1.0,  2.5 ), (-1.0,  0.0), ( 0,  2), 0.0, 0.0),
+            ((1.0,  2.5 ), ( 1.0,  0.0), ( 2,  2), 1.0, 4.0),
+            ((2.5,  5.0 ), ( 0.0, -1.0), ( 2,  4), 0.0, 4.0),
+            ((4.5,  1.0 ), ( 0.0, -1.0), ( 4,  0), 0.0, 0.0),
+            // Outside of the map, left of it.
+            ((-0.5, 2.5 ), ( 1.0,  0.0), ( 0,  2), 0.5, 0.5),
+        ];
+
+        for ((x, y), (direction_x, direction_y), (cell_x, cell_y), border_distance, hit_distance) in cases {
+            let     origin        = WorldPosition::new(x, y);
+            let     ray_direction = WorldDirection::new(direction_x, direction_y).normalize();
+            let mut cells         = vec![];
+
+            traverse_grid(origin, ray_direction, WorldLength::INFINITY, |grid_cell: GridCell| {
+                cells.push(grid_cell);
+
+                cells.len() < 2
+            });
+
+            let first_border = cells[1];
+            let hit          = first_hit(&map, origin, ray_direction).unwrap();
+
+            assert_eq!(first_border.position, SignedMapPosition { x: cell_x, y: cell_y }, "The ray from {} towards {} went to the wrong cell", origin, ray_direction);
+            assert!((first_border.entry_distance - border_distance).abs() < EPSILON, "The ray from {} towards {} crossed its first border at {}, instead of {}", origin, ray_direction, first_border.entry_distance, border_distance);
+            assert!((cells[0].exit_distance - border_distance).abs() < EPSILON, "The ray from {} towards {} left its cell at {}, instead of {}", origin, ray_direction, cells[0].exit_distance, border_distance);
+            assert!((hit.distance - hit_distance).abs() < EPSILON, "The ray from {} towards {} hit at {}, instead of {}", origin, ray_direction, hit.distance, hit_distance);
+        }
+    }
+}
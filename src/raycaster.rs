@@ -1,240 +1,1185 @@
+use std::ops::Range;
+
 use ggez::graphics::Color;
 
-use crate::{camera::Camera, map::{Map, MapCoordinate, Tile, world_position_to_signed_map_position}, world::{WorldDirection, WorldVector, WorldLength}};
+use crate::{hud::Hud, math, minimap, profiler::Profiler, sky::Sky, viewmodel::ViewModel, ray::RayHit, sprite::{self, Sprite}, texture::{self, Texture, TextureCoordinate}, camera::Camera, level::{EYE_HEIGHT, HEIGHT_PROBE, Heights, Level}, map::MapPosition, material::Tile, world::{WorldDirection, WorldLength, WorldPosition, WorldVector}};
+
+pub const  DEFAULT_SCREEN_HEIGHT:   u16 = 600;
+pub const  DEFAULT_SCREEN_WIDTH:    u16 = 800;
+pub const  PIXEL_SIZE:              u16 = 4;
+pub const  DEFAULT_MAX_REFLECTIONS: u32 = 4;
+pub const  DEFAULT_MAX_PORTALS:     u32 = 8;
 
-pub const  SCREEN_HEIGHT:    u16 = 600;
-pub const  SCREEN_WIDTH:     u16 = 800;
-pub const  PIXEL_SIZE:       u16 = 4;
+// Reflected rays start a little off the mirror (or the portal exit), so that they do not hit it again.
+const REFLECTION_OFFSET: WorldLength = 1e-4;
+// Pixels are turned into rows by squares of this many pixels a side, which stay in the cache.
+const TRANSPOSE_TILE:    usize       = 32;
 
 pub struct Raycaster {
-    framebuffer: Vec<u8>,
-    width:       f32,
+        // Packed colours (see `pack_color`), column after column: scanlines and overlays are drawn here.
+        pixels:          Vec<u32>,
+        // RGBA8, row after row, made from `pixels` at the end of each frame.
+        framebuffer:     Vec<u8>,
+        // The frame before, to tell which rows changed. Every pixel is drawn again each frame, so the two are swapped.
+        last_frame:      Vec<u8>,
+        width:           u16,
+        height:          u16,
+        column:          Vec<Color>,
+        column_hits:     Vec<RaycastHit>,
+        column_legs:     Vec<RayLeg>,
+        column_bounds:   Vec<WorldLength>,
+        column_layers:   Vec<ColumnLayer>,
+        // Frames of each sprite texture, see `Sprite::get_frame`.
+        sprite_textures: Vec<Vec<Texture>>,
+        ray_stats:       RayStats,
+    pub sky:             Sky,
+    pub limits:          RecursionLimits,
+    pub show_minimap:    bool,
+    // Drawn on the minimap, as lines going through the centre of each cell.
+    pub debug_paths:     Vec<Vec<MapPosition>>,
+    // The weapon of the player, drawn over the 3D view. None without a player (when rendering headless...).
+    pub view_model:      Option<ViewModel>,
+    // Drawn over everything else, the minimap included.
+    pub hud:             Option<Hud>,
+    // Its overlay is drawn over the HUD, when shown.
+    pub profiler:        Option<Profiler>,
 }
 
-pub struct RaycastHit {
-    pub is_vertical: bool,
-    pub tile:        Tile,
-    pub distance:    WorldLength,
+// How the rays of the last frame went, for profiling.
+#[derive(Clone, Debug, Default)]
+pub struct RayStats {
+    // DDA steps of the ray of each column, through every leg of its path.
+    pub column_steps: Vec<u32>,
+    // Rays that left the level without meeting anything opaque.
+    pub escaped_rays: u32,
 }
 
-impl Raycaster {
-    pub fn update_framebuffer(&mut self, map: &Map, camera: &Camera) -> &[u8] {
-        for x in 0 .. SCREEN_WIDTH {
-            self.render_scanline(map, camera, x);
-        }
+/*
+* Mirrors facing each other, or portals looking into each other, would send rays around forever. Past these
+* limits, mirrors are drawn with their own colour, and portals like the wall they are on.
+*/
+#[derive(Clone, Copy, Debug)]
+pub struct RecursionLimits {
+    pub reflections: u32,
+    pub portals:     u32,
+}
 
-        &self.framebuffer
-    }
+#[derive(Clone, Copy, Debug)]
+pub struct RaycastHit {
+    pub tile:           Tile,
+    pub normal:         WorldVector,
+    pub distance:       WorldLength,
+    pub surface_offset: TextureCoordinate,
+    pub has_opening:    bool,
+}
 
-    pub fn new() -> Raycaster {
-        let framebuffer = vec![0; (SCREEN_WIDTH as usize) * (SCREEN_HEIGHT as usize) * (PIXEL_SIZE as usize)];
+/*
+* Straight part of the path followed by a ray, from `start` to `end` (distances along the whole path). Each bounce
+* off a mirror, and each portal crossed, starts a new leg.
+*/
+#[derive(Clone, Copy, Debug)]
+struct RayLeg {
+    origin:    WorldPosition,
+    direction: WorldDirection,
+    start:     WorldLength,
+    end:       WorldLength,
+}
 
-        Raycaster { framebuffer, width: SCREEN_WIDTH as f32 }
-    }
+/*
+* Everything drawn on a column, painted from back to front. Vertical extents are heights in world units, walls
+* going from `bottom` to `top`.
+*/
+#[derive(Clone, Copy, Debug)]
+enum ColumnLayer {
+    // Floor and ceiling between two distances along the path, where heights do not change.
+    Space      { start: WorldLength, end: WorldLength, heights: Heights },
+    Wall       { hit: RaycastHit, bottom: WorldLength, top: WorldLength },
+    SeeThrough { hit: RaycastHit, bottom: WorldLength, top: WorldLength },
+    Mirror     { hit: RaycastHit, bottom: WorldLength, top: WorldLength },
+    Sprite     { sprite: usize, frame: usize, distance: WorldLength, u: TextureCoordinate, floor: WorldLength },
+}
 
-    fn render_scanline(&mut self, map: &Map, camera: &Camera, x: u16) {
-        let ray_direction = camera.get_ray_direction(x as f32, self.width);
+// What a column sees: where its ray goes, and from which height.
+struct ColumnView<'a> {
+    camera:        &'a Camera,
+    ray_direction: WorldDirection,
+    horizon:       f32,
+    screen_height: f32,
+    eye_height:    WorldLength,
+    legs:          &'a [RayLeg],
+}
 
-        let hit           = self.cast_ray(map, camera, ray_direction);
+// Projects heights onto a screen column, for things at a given distance along the ray.
+struct ColumnProjection {
+    horizon:         f32,
+    eye_height:      WorldLength,
+    pixels_per_unit: f32,
+}
 
-        if hit.is_none() {
-            return;
-        }
+/*
+* A column being painted. What is painted stays in `colors` too, for what goes over it to be blended with
+* the exact colours, and not packed ones.
+*/
+struct ColumnPaint<'a> {
+    colors: &'a mut [Color],
+    pixels: &'a mut [u32],
+}
 
-        let hit           = hit.unwrap();
+// Where a wall lands on a screen column, before clipping.
+struct WallProjection {
+    top:             f32,
+    height:          f32,
+    pixels_per_unit: f32,
+}
 
-        let ceiling_color = Color::BLACK;
-        let color         = hit.tile.color();
-        let floor_color   = Color::new(0.5, 0.5, 0.5, 1.0);
+impl From<RayHit> for RaycastHit {
+    fn from(hit: RayHit) -> RaycastHit {
+        RaycastHit { tile: hit.tile, normal: hit.normal, distance: hit.distance, surface_offset: hit.surface_offset, has_opening: hit.has_opening }
+    }
+}
+
+impl<'a> ColumnView<'a> {
+    fn project(&self, distance: WorldLength) -> ColumnProjection {
+        /*
+        * We can't use the distance along the ray to determine the height of the wall because it gives a weird
+        * fishbowl effect. We're not interested in the distance between the eye of the player and the obstacle,
+        * but, rather, by the distance from the obstacle to the camera plane located at the player's position.
+        *
+        * Both the ray direction and the view direction are normalized, so the perpendicular distance is the
+        * projection of the hit distance onto the view direction: distance * cos(angle) = distance * dot product.
+        * Rays leaving an open map do not hit anything, which is the same as hitting a wall infinitely far away.
+        */
+        let perpendicular_distance = distance * self.ray_direction.dot(self.camera.get_view_direction());
 
         /*
         * Using triangle ratios, we determine that:
         *   unclipped_projected_height / distance_to_projection_plane (1 because of our camera setup)
         * = wall_height / distance_from_player
         *
-        * We use 1 as our wall height.
+        * A wall of height 1 is as many pixels high as the screen at a distance of 1, heights at the eye level land on the
+        * horizon. Close walls end up taller than the screen, only the pixels going out of the screen are dropped,
+        * the projection itself is never clamped.
         *
         * See https://www.permadi.com/tutorial/raycast/rayc9.html
         */
-        let as_screen_height_ratio = 1.0 - num::clamp(1.0 / hit.distance, 0.0, 1.0);
+        ColumnProjection { horizon: self.horizon, eye_height: self.eye_height, pixels_per_unit: self.screen_height / perpendicular_distance }
+    }
 
-        let empty_portion_size     = ((SCREEN_HEIGHT as f32) * as_screen_height_ratio / 2.0) as u16;
+    /*
+    * Where the ray is after travelling `distance`, and where it is going. Reflected rays are still on the
+    * screen column they started from, as if they went on straight.
+    */
+    fn get_point(&self, distance: WorldLength) -> (WorldPosition, WorldDirection) {
+        let leg = self.legs.iter().find(|leg| distance < leg.end).or(self.legs.last()).unwrap();
 
-        let low_end                = empty_portion_size;
-        let high_start             = SCREEN_HEIGHT - empty_portion_size;
+        (leg.origin + leg.direction * (distance - leg.start), leg.direction)
+    }
 
-        let mut color = color.unwrap();
+    fn get_heights(&self, level: &dyn Level, distance: WorldLength) -> Heights {
+        level.get_heights(self.get_point(distance).0)
+    }
+}
 
-        // This works because we operate in a grid.
-        if !hit.is_vertical {
-            color.r *= 0.5;
-            color.g *= 0.5;
-            color.b *= 0.5;
+impl ColumnProjection {
+    // Screen space has y going down, heights above the eye are above the horizon.
+    fn get_row(&self, height: WorldLength) -> f32 {
+        // Right at the eye, the projection of the eye level is the horizon whatever the scale, even infinite.
+        if height == self.eye_height {
+            return self.horizon;
         }
 
-        for y in 0 .. SCREEN_HEIGHT {
-            self.set_pixel(x, y, &(
-                if y < low_end {
-                    floor_color
-                } else if y > high_start {
-                    ceiling_color
-                } else {
-                    color
-                }
-            ));
+        self.horizon - (height - self.eye_height) * self.pixels_per_unit
+    }
+
+    fn get_wall(&self, bottom: WorldLength, top: WorldLength) -> WallProjection {
+        let top_row = self.get_row(top);
+
+        WallProjection { top: top_row, height: self.get_row(bottom) - top_row, pixels_per_unit: self.pixels_per_unit }
+    }
+}
+
+impl<'a> ColumnPaint<'a> {
+    fn get(&self, y: u16) -> Color {
+        self.colors[y as usize]
+    }
+
+    fn set(&mut self, y: u16, color: Color) {
+        self.colors[y as usize] = color;
+        self.pixels[y as usize] = pack_color(&color);
+    }
+
+    // The colour is only packed once. Rows may be empty, or even backwards, like when looping over them.
+    fn fill(&mut self, rows: Range<u16>, color: Color) {
+        if rows.is_empty() {
+            return;
+        }
+
+        let rows = (rows.start as usize) .. (rows.end as usize);
+
+        self.colors[rows.clone()].fill(color);
+        self.pixels[rows].fill(pack_color(&color));
+    }
+}
+
+impl WallProjection {
+    fn get_visible_rows(&self, screen_height: f32) -> Range<u16> {
+        get_visible_rows(self.top, self.top + self.height, screen_height)
+    }
+
+    // In world units from the top of the wall, we use the middle of the pixel.
+    fn get_texture_v(&self, y: u16) -> TextureCoordinate {
+        ((y as f32) + 0.5 - self.top) / self.pixels_per_unit
+    }
+}
+
+impl Default for RecursionLimits {
+    fn default() -> RecursionLimits {
+        RecursionLimits { reflections: DEFAULT_MAX_REFLECTIONS, portals: DEFAULT_MAX_PORTALS }
+    }
+}
+
+impl ColumnLayer {
+    fn get_distance(&self) -> WorldLength {
+        match self {
+            ColumnLayer::Space      { end, .. }      => *end,
+            ColumnLayer::Wall       { hit, .. }      => hit.distance,
+            ColumnLayer::SeeThrough { hit, .. }      => hit.distance,
+            ColumnLayer::Mirror     { hit, .. }      => hit.distance,
+            ColumnLayer::Sprite     { distance, .. } => *distance,
+        }
+    }
+}
+
+impl Raycaster {
+    /*
+    * Draws a frame into the framebuffer, and returns the rows that changed since the last one, which are the
+    * only ones that need uploading. They come as a single band, empty when nothing changed.
+    */
+    pub fn update_framebuffer(&mut self, level: &dyn Level, camera: &Camera, sprites: &[Sprite]) -> Range<u16> {
+        self.ray_stats.escaped_rays = 0;
+
+        std::mem::swap(&mut self.framebuffer, &mut self.last_frame);
+
+        for x in 0 .. self.width {
+            self.render_scanline(level, camera, sprites, x);
+        }
+
+        if let Some(view_model) = self.view_model.take() {
+            view_model.draw(self);
+
+            self.view_model = Some(view_model);
+        }
+
+        if self.show_minimap {
+            minimap::draw_minimap(self, level, camera);
+        }
+
+        if let Some(hud) = self.hud.take() {
+            hud.draw(self);
+
+            self.hud = Some(hud);
+        }
+
+        if let Some(profiler) = self.profiler.take() {
+            profiler.draw(self);
+
+            self.profiler = Some(profiler);
+        }
+
+        self.transpose_pixels();
+
+        self.get_dirty_rows()
+    }
+
+    // The framebuffer is `width` × `height` pixels, whatever the size of the window it is shown in.
+    pub fn new(width: u16, height: u16) -> Raycaster {
+        assert!(width > 0 && height > 0, "The screen needs at least one pixel!");
+
+        let pixels      = vec![0; (width as usize) * (height as usize)];
+        let framebuffer = vec![0; (width as usize) * (height as usize) * (PIXEL_SIZE as usize)];
+        let last_frame  = framebuffer.clone();
+
+        Raycaster {
+            pixels,
+            framebuffer,
+            last_frame,
+            width,
+            height,
+
+            sky:             Sky::make_demo_sky(),
+            column:          vec![Color::BLACK; height as usize],
+            column_hits:     vec![],
+            column_legs:     vec![],
+            column_bounds:   vec![],
+            column_layers:   vec![],
+            sprite_textures: sprite::make_sprite_textures(),
+            ray_stats:       RayStats { column_steps: vec![0; width as usize], escaped_rays: 0 },
+            limits:          RecursionLimits::default(),
+            show_minimap:    true,
+            debug_paths:     vec![],
+            view_model:      None,
+            hud:             None,
+            profiler:        None,
+        }
+    }
+
+    pub fn get_width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn get_height(&self) -> u16 {
+        self.height
+    }
+
+    // RGBA8 pixels, row after row.
+    pub fn get_framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    pub fn get_ray_stats(&self) -> &RayStats {
+        &self.ray_stats
+    }
+
+    // From the first row that differs from the last frame to the last one.
+    fn get_dirty_rows(&self) -> Range<u16> {
+        let row_size = (PIXEL_SIZE as usize) * (self.width as usize);
+        let mut rows = self.framebuffer.chunks_exact(row_size).zip(self.last_frame.chunks_exact(row_size));
+        let is_dirty = |(row, last_row): (&[u8], &[u8])| row != last_row;
+
+        match rows.clone().position(is_dirty) {
+            Some(first) => (first as u16) .. (rows.rposition(is_dirty).unwrap() as u16 + 1),
+            None        => 0 .. 0,
         }
     }
 
-    fn cast_ray(&self, map: &Map, camera: &Camera, ray_direction: WorldDirection) -> Option<RaycastHit> {
-        let mut within_bounds = true;
-        let     x_pixel_sign  = if ray_direction.x < 0.0 { -1 } else { 1 };
-        let     y_pixel_sign  = if ray_direction.y < 0.0 { -1 } else { 1 };
-        let mut current_tile  = world_position_to_signed_map_position(camera.position);
+    fn render_scanline(&mut self, level: &dyn Level, camera: &Camera, sprites: &[Sprite], x: u16) {
+        let ray_direction     = camera.get_ray_direction(x as f32, self.width as f32);
+        let floor_color       = Color::new(0.5, 0.5, 0.5, 1.0);
+
+        // The buffers are kept around between scanlines to avoid allocating.
+        let mut column        = std::mem::take(&mut self.column);
+        let mut column_hits   = std::mem::take(&mut self.column_hits);
+        let mut column_legs   = std::mem::take(&mut self.column_legs);
+        let mut column_bounds = std::mem::take(&mut self.column_bounds);
+        let mut column_layers = std::mem::take(&mut self.column_layers);
+        let mut pixels        = std::mem::take(&mut self.pixels);
+
+        column_hits.clear();
+        column_legs.clear();
+        column_bounds.clear();
+        column_layers.clear();
 
         /*
-        * When moving from 1 unit positively along the direction vector on a vector component (x or y),
-        * we move the other by a factor `component B / component A`.
-        *
-        * Using Pythagora's theorem, we can find how much distance we need to travel to go from
-        * one vector component (x or y) to the next. We need that because our algorithm moves
-        * exactly one tile at a time (that allows to never go through any wall), and we need
-        * to know which pixel should be visited next.
-        *
-        * We want to move the tile which is the "most" behind in terms of being ready to be
-        * updated. If X is lagging behind, then we'll move horizontally, otherwise we'll move
-        * vertically.
-        *
-        * In the case of the X component, when moving X 1 pixel to the right, we establish that:
-        * - The distance travelled by X (distance_x) is exactly 1.
-        * - The distance travelled by Y (distance_y) is exactly ray_direction.y / ray_direction.x.
-        * - The distance travelled along the direction vector is sqrt(1² + (ray_direction.y / ray_direction.x)²).
-        *
-        * We can expand the distance along the direction vector as:
-        * sqrt((ray_direction.x² + ray_direction.y²) / ray_direction.x²)
-        *
-        * Then take the denominator out of the square root:
-        * sqrt(ray_direction.x² + ray.direction.y²) / ray_direction.x.
-        *
-        * The sqrt() part is exactly ray_direction's magnitude formula, which we know to be one
-        * because direction vector are supposed to be normalized. That leaves us with:
-        * 1 / ray_direction.x.
-        *
-        * We need to be careful: it is possible that the ray is perfectly horizontal or vertical,
-        * which for the pupose of the DDA algorithm below, can be considered as a step that is,
-        * never lagging behind, therefore always greater than the other one, therefore we can
-        * set it to +∞.
-        *
-        * Also, we use absolute values because steps are for comparison only. We need to perform
-        * comparisons on equal grounds.
-        *
-        * The same logic applies to Y, leaving us with:
+        * See-through tiles and openings between sectors let the ray go on, mirrors and portals send it somewhere
+        * else, so a column can be made of several hits, from front to back. The last one is the opaque wall
+        * behind them all, unless the ray left the map, after a last bounce off a mirror maybe. Mirrors are opaque
+        * when the ray cannot bounce anymore.
         */
-        let step = WorldVector {
-            x: if ray_direction.x == 0.0 { f32::INFINITY } else { 1.0 / ray_direction.x.abs() },
-            y: if ray_direction.y == 0.0 { f32::INFINITY } else { 1.0 / ray_direction.y.abs() },
+        let steps = follow_ray(level, camera.position, ray_direction, self.limits, |leg| column_legs.push(leg), |hit| {
+            column_hits.push(hit);
+
+            true
+        });
+
+        // Like `cast_ray` returning None.
+        let has_escaped = column_hits.iter().all(|hit| hit.has_opening);
+
+        self.ray_stats.column_steps[x as usize]  = steps;
+        self.ray_stats.escaped_rays             += has_escaped as u32;
+
+        let view              = ColumnView {
+            camera,
+            ray_direction,
+
+            horizon:       camera.get_horizon(self.height as f32),
+            screen_height: self.height as f32,
+            eye_height:    level.get_heights(camera.position).floor + EYE_HEIGHT,
+            legs:          &column_legs,
         };
 
+        // The ray bounced off as many mirrors as it met, up to the limit.
+        let reflective_hits   = column_hits.iter().filter(|hit| !hit.has_opening && hit.tile.is_reflective()).count() as u32;
+        let is_reflected      = |hit: &RaycastHit| hit.tile.is_reflective() && reflective_hits <= self.limits.reflections;
+
         /*
-        * Our map coordinates are in the top-left corner of the tile, and our algorithm
-        * needs to know which direction to advance first. Therefore, the initial
-        * travelled distance might need to be adjusted so that the two coordinates
-        * start fair in the "competition".
+        * Distance to the opaque surface seen by the column, along the ray, reflections included: something seen
+        * in a mirror is as far as the mirror plus the distance from the mirror to it. Infinite when the ray left
+        * the map.
         */
-        let mut steps_accumulator = WorldVector {
-            x: if x_pixel_sign < 0 { 0.0 } else { step.x },
-            y: if y_pixel_sign < 0 { 0.0 } else { step.y },
-        };
+        let opaque_hit        = column_hits.last().filter(|hit| !hit.tile.is_transparent() && !hit.has_opening && !is_reflected(hit));
+        let depth             = opaque_hit.map_or(WorldLength::INFINITY, |hit| hit.distance);
+
+        // Heights can only change across openings and from one leg to the next, which splits the path into spaces.
+        column_bounds.push(0.0);
+        column_bounds.extend(column_hits.iter().filter(|hit| hit.has_opening).map(|hit| hit.distance));
+        column_bounds.extend(column_legs.iter().skip(1).map(|leg| leg.start));
+        column_bounds.sort_by(|a, b| a.total_cmp(b));
+        column_bounds.push(depth);
 
-        while within_bounds {
-            let is_vertical_step = steps_accumulator.y < steps_accumulator.x;
+        for bounds in column_bounds.windows(2) {
+            let (start, end) = (bounds[0], bounds[1]);
 
-            if is_vertical_step {
-                current_tile.y += y_pixel_sign;
+            if start < end {
+                column_layers.push(ColumnLayer::Space { start, end, heights: view.get_heights(level, (start + HEIGHT_PROBE).min((start + end) / 2.0)) });
+            }
+        }
+
+        /*
+        * Walls go from the floor to the ceiling of the space in front of them, except for openings, which only
+        * have steps below and lintels above, where the space behind them is lower. Mirrors past the reflection
+        * limit are walls.
+        */
+        let mut reflections = 0;
+
+        for hit in &column_hits {
+            let near = view.get_heights(level, hit.distance - HEIGHT_PROBE);
+            let hit  = *hit;
+
+            if hit.has_opening {
+                let far = view.get_heights(level, hit.distance + HEIGHT_PROBE);
+
+                if far.floor > near.floor {
+                    column_layers.push(ColumnLayer::Wall { hit, bottom: near.floor, top: far.floor });
+                }
+
+                if far.ceiling < near.ceiling {
+                    column_layers.push(ColumnLayer::Wall { hit, bottom: far.ceiling, top: near.ceiling });
+                }
+            } else if hit.tile.is_transparent() {
+                column_layers.push(ColumnLayer::SeeThrough { hit, bottom: near.floor, top: near.ceiling });
+            } else if hit.tile.is_reflective() && reflections < self.limits.reflections {
+                reflections += 1;
+
+                column_layers.push(ColumnLayer::Mirror { hit, bottom: near.floor, top: near.ceiling });
             } else {
-                current_tile.x += x_pixel_sign;
-            }
-
-            let tile = map.get_tile(current_tile.x as MapCoordinate, current_tile.y as MapCoordinate);
-
-            if tile.is_visible() {
-                /*
-                * We're in a convenient situation where walls are either vertical or horizontal, due to the map
-                * setup. To determine if the wall is horizontal or vertical, we remember on which side the wall
-                * was hit. Because of the infinity case, you can't trust both step values. Only one can
-                * be trusted, we'll use the one that created the ray hit.
-                *
-                * We can't use euclidean distance to determine the distance to the wall because it give a weird
-                * fishbowl effect. We're not interested in the distance between the eye of the player and the
-                * obstacle, but, rather, by the distance from the obstacle to the camera plane located at
-                * the player's position that is also perpandicular to the cast ray.
-                *
-                * Using triangle ratios (see https://lodev.org/cgtutor/images/raycastperpwalldist.png),
-                * we can determine that
-                * euclidean = (|ray_direction| * ray_y_distance) / ray_direction.y
-                * in the vertical case.
-                *
-                * With triangle ratios, we also know that:
-                * perpandicular / |player_direction| = euclidean / |ray_direction|.
-                *
-                * Which means that:
-                *      perp = euclidean * |player_direction| / |ray_direction|. Using the value of euclidean:
-                *           = ((|ray_direction| * ray_y_distance) / ray_direction.y) * |player_direction| / |ray_direction|
-                *           = ray_y_distance / ray_direction.y * |player_direction|
-                *
-                * We can assume player_direction to have length 1 if we stop using ray_direction's values in calculations.
-                * Instead, we'll try to factor out ray_direction by replacing it with known values.
-                *
-                * We then get perp = ray_y_distance / ray_direction.y.
-                *
-                * ray_y_distance = number_of_steps + corrective_step (because our grid cell size is 1, and rays are
-                * cast from the outer borders of the cell.
-                *
-                * Steps accumulator measures the same thing, but instead of moving one by one, it moves step.y by step.y.
-                *
-                * We get steps_accumulator = (number_of_steps + corrective_step) * step.y
-                *        so ray_y_distance = steps_accumulator.y / step.y
-                *
-                * Because step.y = 1 / ray_direction.y, perp = steps_accumulator.y / step.y / step.y
-                *                                            = steps.accumulator.y
-                */
-                let euclidean_distance = (if is_vertical_step {
-                    steps_accumulator.y
+                column_layers.push(ColumnLayer::Wall { hit, bottom: near.floor, top: near.ceiling });
+            }
+        }
+
+        /*
+        * Sprites are looked for along every leg, so that they show up in mirrors, and they are hidden by what is
+        * in front of them thanks to the depth of the column.
+        */
+        for leg in view.legs {
+            for (index, sprite) in sprites.iter().enumerate() {
+                if let Some((distance, u)) = sprite.intersect_ray(leg.origin, leg.direction) {
+                    let distance = leg.start + distance;
+
+                    if distance < leg.end && distance < depth {
+                        let frame = sprite.get_frame(leg.origin, self.sprite_textures[sprite.texture].len());
+
+                        column_layers.push(ColumnLayer::Sprite { sprite: index, frame, distance, u, floor: level.get_heights(sprite.position).floor });
+                    }
+                }
+            }
+        }
+
+        /*
+        * Back to front, so that closer layers are drawn over further ones. What a mirror shows is already in the
+        * column when the mirror itself is drawn, since it is further away.
+        */
+        column_layers.sort_by(|a, b| b.get_distance().total_cmp(&a.get_distance()));
+
+        let column_start      = (x as usize) * (self.height as usize);
+        let mut paint         = ColumnPaint { colors: &mut column, pixels: &mut pixels[column_start .. column_start + (self.height as usize)] };
+
+        paint.fill(0 .. self.height, Color::BLACK);
+
+        for layer in &column_layers {
+            match layer {
+                ColumnLayer::Space { start, end, heights } => {
+                    let near = view.project(*start);
+                    let far  = view.project(*end);
+
+                    for y in get_visible_rows(near.get_row(heights.ceiling), far.get_row(heights.ceiling), view.screen_height) {
+                        paint.set(y, self.get_ceiling_color(level, &view, heights.ceiling, y));
+                    }
+
+                    paint.fill(get_visible_rows(far.get_row(heights.floor), near.get_row(heights.floor), view.screen_height), floor_color);
+                },
+
+                // Faces without a texture are the same colour from top to bottom.
+                ColumnLayer::Wall { hit, bottom, top } if hit.tile.get_texture(hit.normal).is_none() => {
+                    let projection = view.project(hit.distance).get_wall(*bottom, *top);
+
+                    paint.fill(projection.get_visible_rows(view.screen_height), self.get_wall_color(hit, 0.0));
+                },
+
+                ColumnLayer::Wall { hit, bottom, top } => {
+                    let projection = view.project(hit.distance).get_wall(*bottom, *top);
+
+                    for y in projection.get_visible_rows(view.screen_height) {
+                        paint.set(y, self.get_wall_color(hit, projection.get_texture_v(y)));
+                    }
+                },
+
+                ColumnLayer::SeeThrough { hit, bottom, top } => {
+                    let projection = view.project(hit.distance).get_wall(*bottom, *top);
+
+                    for y in projection.get_visible_rows(view.screen_height) {
+                        paint.set(y, texture::blend(paint.get(y), self.get_wall_color(hit, projection.get_texture_v(y))));
+                    }
+                },
+
+                ColumnLayer::Mirror { hit, bottom, top } => {
+                    let projection = view.project(hit.distance).get_wall(*bottom, *top);
+                    let reflection = hit.tile.reflection().unwrap();
+
+                    for y in projection.get_visible_rows(view.screen_height) {
+                        let own_color = self.get_wall_color(hit, projection.get_texture_v(y));
+                        let reflected = paint.get(y);
+
+                        paint.set(y, Color::new(
+                            own_color.r + (reflected.r * reflection.tint.r - own_color.r) * reflection.reflectivity,
+                            own_color.g + (reflected.g * reflection.tint.g - own_color.g) * reflection.reflectivity,
+                            own_color.b + (reflected.b * reflection.tint.b - own_color.b) * reflection.reflectivity,
+                            1.0,
+                        ));
+                    }
+                },
+
+                ColumnLayer::Sprite { sprite, frame, distance, u, floor } => {
+                    let sprite     = &sprites[*sprite];
+                    let texture    = &self.sprite_textures[sprite.texture][*frame];
+                    let projection = view.project(*distance).get_wall(*floor, *floor + sprite.height);
+
+                    for y in projection.get_visible_rows(view.screen_height) {
+                        paint.set(y, texture::blend(paint.get(y), texture.sample(*u, projection.get_texture_v(y) / sprite.height)));
+                    }
+                },
+            }
+        }
+
+        self.pixels        = pixels;
+        self.column        = column;
+        self.column_hits   = column_hits;
+        self.column_legs   = column_legs;
+        self.column_bounds = column_bounds;
+        self.column_layers = column_layers;
+    }
+
+    /*
+    * Textured faces are sampled, the others have a flat colour. Transparency is kept for blending. Textures
+    * repeat every world unit, vertically too.
+    */
+    fn get_wall_color(&self, hit: &RaycastHit, v: TextureCoordinate) -> Color {
+        let mut color = match hit.tile.get_texture(hit.normal) {
+            Some(texture) => texture.sample(hit.surface_offset, v.rem_euclid(1.0)),
+            None          => hit.tile.color().unwrap(),
+        };
+
+        /*
+        * Light comes from the top and bottom of the map: faces looking up or down are fully lit, faces
+        * looking left or right are half as bright, and diagonal faces are somewhere in between. Glowing
+        * materials make up for the difference.
+        */
+        let shading    = 0.5 + 0.5 * hit.normal.y.abs();
+        let brightness = shading + (1.0 - shading) * hit.tile.get_light();
+
+        color.r *= brightness;
+        color.g *= brightness;
+        color.b *= brightness;
+
+        color
+    }
+
+    /*
+    * Indoor places have a ceiling, outdoor places let the sky through. To know where a ceiling pixel is, we run
+    * the wall projection backwards: the ceiling being `ceiling_height - eye_height` above the eye, a pixel
+    * located `horizon - y` pixels above the horizon sees the ceiling at a perpendicular distance of:
+    *   screen_height * (ceiling_height - eye_height) / (horizon - y)
+    *
+    * We use the middle of the pixel, which is always above the horizon because the ceiling is above the eye.
+    *
+    * The distance is along the path of the ray, which may have bounced off mirrors: the leg it falls in tells
+    * where the ceiling point is, and in which direction the sky is seen.
+    */
+    fn get_ceiling_color(&self, level: &dyn Level, view: &ColumnView, ceiling_height: WorldLength, y: u16) -> Color {
+        let ceiling_color          = Color::BLACK;
+        let pixels_above_horizon   = view.horizon - (y as f32 + 0.5);
+
+        let perpendicular_distance = view.screen_height * (ceiling_height - view.eye_height) / pixels_above_horizon;
+        let ray_distance           = perpendicular_distance / view.ray_direction.dot(view.camera.get_view_direction());
+        let (position, direction)  = view.get_point(ray_distance);
+
+        if level.is_open_sky(position) {
+            self.sky.sample(direction, pixels_above_horizon / view.screen_height)
+        } else {
+            ceiling_color
+        }
+    }
+
+    /*
+    * Casts a ray from any point of the map, in a normalized direction. The camera is not needed,
+    * so that tests can exercise the DDA on its own. Mirrors reflect the ray and portals take it to their
+    * exit, within `limits`, the hit distance is then the length of the whole path.
+    *
+    * Returns `None` when the ray leaves the map without meeting a visible tile.
+    */
+    #[cfg(test)]
+    pub fn cast_ray(level: &dyn Level, origin: WorldPosition, ray_direction: WorldDirection, limits: RecursionLimits) -> Option<RaycastHit> {
+        let mut last_hit = None;
+
+        // Openings are seen through.
+        follow_ray(level, origin, ray_direction, limits, |_| (), |hit| {
+            if hit.has_opening {
+                return true;
+            }
+
+            last_hit = Some(hit);
+
+            hit.tile.is_reflective()
+        });
+
+        last_hit
+    }
+
+    pub(crate) fn set_pixel(&mut self, x: u16, y: u16, color: &ggez::graphics::Color) {
+        let big_x      = x as usize;
+        let big_y      = y as usize;
+        let big_height = self.height as usize;
+
+        self.pixels[big_x * big_height + big_y] = pack_color(color);
+    }
+
+    // Unlike `set_pixel`, whatever goes past the edges of the screen is cut. Each column of it is filled at once.
+    pub(crate) fn fill_rectangle(&mut self, x: i32, y: i32, width: i32, height: i32, color: &Color) {
+        let rows   = (y.max(0) as usize) .. ((y + height).min(self.height as i32).max(0) as usize);
+        let packed = pack_color(color);
+
+        if rows.is_empty() {
+            return;
+        }
+
+        for pixel_x in x.max(0) .. (x + width).min(self.width as i32) {
+            let column_start = (pixel_x as usize) * (self.height as usize);
+
+            self.pixels[column_start + rows.start .. column_start + rows.end].fill(packed);
+        }
+    }
+
+    /*
+    * Copies the pixels, column after column, into the framebuffer, row after row. Going through squares of
+    * pixels, rather than whole columns, keeps both the columns being read and the rows being written in the
+    * cache.
+    */
+    fn transpose_pixels(&mut self) {
+        let width  = self.width as usize;
+        let height = self.height as usize;
+
+        for tile_y in (0 .. height).step_by(TRANSPOSE_TILE) {
+            for tile_x in (0 .. width).step_by(TRANSPOSE_TILE) {
+                let tile_width = TRANSPOSE_TILE.min(width - tile_x);
+
+                for y in tile_y .. (tile_y + TRANSPOSE_TILE).min(height) {
+                    let row_start = (PIXEL_SIZE as usize) * (y * width + tile_x);
+                    let row       = &mut self.framebuffer[row_start .. row_start + (PIXEL_SIZE as usize) * tile_width];
+
+                    for (x, rgba) in (tile_x ..).zip(row.chunks_exact_mut(PIXEL_SIZE as usize)) {
+                        rgba.copy_from_slice(&self.pixels[x * height + y].to_ne_bytes());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/*
+* Walks the ray through the level like `ray::walk`, bounces it off mirrors and takes it through portals, within
+* `limits`. Every leg of the path is reported once its end is known, and every hit in order, with distances
+* along the whole path. Portals are not hits, they are seen through. `on_hit` tells whether the ray goes on
+* (through see-through tiles and openings, or off a mirror). Returns the DDA steps of all the legs.
+*/
+fn follow_ray<L, H>(level: &dyn Level, origin: WorldPosition, ray_direction: WorldDirection, limits: RecursionLimits, mut on_leg: L, mut on_hit: H) -> u32
+where
+    L: FnMut(RayLeg),
+    H: FnMut(RaycastHit) -> bool,
+{
+    let mut leg         = RayLeg { origin, direction: ray_direction, start: 0.0, end: WorldLength::INFINITY };
+    let mut reflections = 0;
+    let mut portals     = 0;
+    let mut steps       = 0;
+
+    loop {
+        // Where the ray goes after this leg.
+        let mut next_leg = None;
+
+        steps += level.walk_ray(leg.origin, leg.direction, WorldLength::INFINITY, &|tile| tile.is_visible(), &mut |hit| {
+            let mut path_hit = RaycastHit::from(hit);
+
+            path_hit.distance += leg.start;
+
+            // The walk stops on anything that cannot be seen through, so the hit ends the leg.
+            if !hit.tile.is_transparent() && !hit.has_opening {
+                leg.end = path_hit.distance;
+            }
+
+            if let Some(portal) = level.find_portal(&hit).filter(|_| portals < limits.portals) {
+                let transform = portal.get_transform();
+                let exit      = transform.transform_position(hit.point) + portal.exit.side.get_normal() * REFLECTION_OFFSET;
+
+                portals  += 1;
+                next_leg  = Some((exit, transform.transform_direction(leg.direction)));
+
+                return false;
+            }
+
+            let goes_on = on_hit(path_hit);
+
+            if goes_on && hit.tile.is_reflective() && reflections < limits.reflections {
+                reflections += 1;
+                next_leg     = Some((hit.point + hit.normal * REFLECTION_OFFSET, math::reflect(leg.direction, hit.normal)));
+            }
+
+            goes_on && (hit.tile.is_transparent() || hit.has_opening)
+        });
+
+        on_leg(leg);
+
+        let (origin, direction) = match next_leg {
+            Some(next_leg) => next_leg,
+            None           => return steps,
+        };
+
+        leg = RayLeg { origin, direction, start: leg.end, end: WorldLength::INFINITY };
+    }
+}
+
+/*
+* RGBA8 in memory order, so that the bytes of a packed colour are those of the pixel in the framebuffer. Colours
+* are packed as they are painted, or once for a whole span.
+*/
+fn pack_color(color: &Color) -> u32 {
+    let rgba = color.to_rgba();
+
+    u32::from_ne_bytes([rgba.0, rgba.1, rgba.2, rgba.3])
+}
+
+// Rows between two projected heights, `top` being above `bottom` on the screen, clipped to the screen.
+fn get_visible_rows(top: f32, bottom: f32, screen_height: f32) -> Range<u16> {
+    let top    = num::clamp(top,    0.0, screen_height) as u16;
+    let bottom = num::clamp(bottom, 0.0, screen_height) as u16;
+
+    top .. bottom
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{f32::consts::{FRAC_PI_2, FRAC_PI_4, PI, TAU}, path::Path};
+
+    use ggez::graphics::Color;
+
+    use crate::{camera::Camera, entity::{Entities, EntityTemplates, Health}, hud::{Hud, HudStatus}, level::{EYE_HEIGHT, Level}, map::{Map, MapCoordinate, MapPosition}, material::{MaterialRegistry, Tile, TileShape}, portal::{FaceSide, WallFace}, profiler::{FrameProfile, Profiler}, random::Random, sector::SectorMap, sprite::Sprite, viewmodel::ViewModel, weapon, world::{WorldAngle, WorldDirection, WorldLength, WorldPosition, WorldVector}};
+
+    use super::{PIXEL_SIZE, RayStats, RaycastHit, Raycaster, RecursionLimits};
+
+    const WIDTH:  u16 = 64;
+    const HEIGHT: u16 = 120;
+
+    /*
+    * Scenes of the demo maps, sprites and overlays included, with the camera at a position, an angle and a
+    * pitch. Their frames were made by the renderer as it was before pixels were packed column after column,
+    * and kept in tests/golden.
+    */
+    type GoldenScene = (&'static str, bool, (f32, f32), WorldAngle, f32);
+
+    const GOLDEN_SCENES: [GoldenScene; 11] = [
+        ("spawn",          false, (5.5, 9.5),  -0.05,             0.1),
+        ("thin_walls",     false, (5.5, 5.5),  7.0 * FRAC_PI_4,   0.0),
+        ("sky",            false, (3.5, 12.5), 0.7,              -0.4),
+        ("window",         false, (6.5, 4.5),  PI,                0.0),
+        ("grate",          false, (7.5, 7.5),  PI,               -0.1),
+        ("mirror",         false, (8.3, 1.5),  FRAC_PI_2 + 0.15,  0.0),
+        ("portal",         false, (5.5, 9.5),  FRAC_PI_2,         0.0),
+        ("sectors",        true,  (4.0, 8.0),  FRAC_PI_2 - 0.3,   0.1),
+        ("sectors_step",   true,  (10.0, 10.5), 3.6,              0.0),
+        ("sectors_mirror", true,  (8.0, 8.0),  -1.05,             0.0),
+        ("sectors_sky",    true,  (8.5, 8.0),  -2.3,              0.15),
+    ];
+    // Neither is a multiple of the squares pixels are transposed by.
+    const GOLDEN_SIZES:  [(u16, u16); 2] = [(257, 131), (33, 47)];
+
+    // What entities the level starts with look like.
+    fn get_sprites(level: &dyn Level) -> Vec<Sprite> {
+        let     templates = EntityTemplates::load(Path::new("assets/entities.toml")).unwrap();
+        let mut entities  = Entities::new();
+
+        templates.spawn_all(&mut entities, level.get_entity_spawns()).unwrap();
+
+        entities.get_entity_sprites().into_iter().map(|(_, sprite)| sprite).collect()
+    }
+
+    // The minimap on most scenes, and either the HUD and the weapon or the profiler, so that they are seen over everything.
+    fn add_overlays(raycaster: &mut Raycaster, scene: usize) {
+        let shotgun = weapon::find_weapon("shotgun").unwrap();
+
+        raycaster.show_minimap = scene % 3 != 2;
+        raycaster.debug_paths  = vec![vec![MapPosition { x: 2, y: 2 }, MapPosition { x: 5, y: 3 }]];
+
+        if scene % 4 != 1 {
+            let mut view_model = ViewModel::new();
+            let mut hud        = Hud::new();
+
+            view_model.weapon    = Some(shotgun);
+            view_model.recoil    = 0.5;
+            raycaster.view_model = Some(view_model);
+
+            hud.status           = HudStatus { health: Some(Health { current: 20.0, maximum: 100.0 }), weapon: Some((shotgun, 0)) };
+            hud.show_message("Saved to slot quick.");
+            raycaster.hud        = Some(hud);
+        } else {
+            let mut profiler  = Profiler::new();
+            let     ray_stats = RayStats { column_steps: (0 .. raycaster.width as u32).collect(), escaped_rays: 3 };
+
+            profiler.show_overlay = true;
+            profiler.add(FrameProfile::new(0.016, 0.004, 0.001, &ray_stats), &ray_stats);
+            raycaster.profiler    = Some(profiler);
+        }
+    }
+
+    fn get_golden_path(name: &str, width: u16, height: u16) -> String {
+        format!("tests/golden/{}_{}x{}.png", name, width, height)
+    }
+
+    const MAPS:          u32         = 200;
+    const RAYS_PER_MAP:  u32         = 50;
+    const EPSILON:       WorldLength = 1e-3;
+    // What the inside of the maps is made of, empty cells being more likely than any wall.
+    const INSIDE_TILES:  [&str; 12]  = ["empty", "empty", "empty", "empty", "wall", "wall2", "edge_n", "edge_w", "mid_ew", "mid_ns", "diag_ne", "diag_nw"];
+
+    struct TestMap {
+        width:  MapCoordinate,
+        height: MapCoordinate,
+        tiles:  Vec<Tile>,
+    }
+
+    impl TestMap {
+        // Walls all around, random tiles inside.
+        fn new(random: &mut Random, materials: &MaterialRegistry) -> TestMap {
+            let width  = 3 + (random.next_u64() % 14) as MapCoordinate;
+            let height = 3 + (random.next_u64() % 14) as MapCoordinate;
+            let wall   = materials.get("wall").unwrap();
+
+            let tiles  = (0 .. width * height).map(|index| {
+                let (x, y) = (index % width, index / width);
+
+                if x == 0 || y == 0 || x == width - 1 || y == height - 1 {
+                    wall
                 } else {
-                    steps_accumulator.x
-                }).abs();
+                    materials.get(INSIDE_TILES[(random.next_u64() as usize) % INSIDE_TILES.len()]).unwrap()
+                }
+            }).collect();
+
+            TestMap { width, height, tiles }
+        }
+
+        fn to_map(&self) -> Map {
+            Map::new(self.width, self.height, self.tiles.clone(), MapPosition { x: 1, y: 1 })
+        }
+
+        // A random point of the inside, in a cell that is made empty for it.
+        fn make_origin(&mut self, random: &mut Random, materials: &MaterialRegistry) -> WorldPosition {
+            let x = 1 + (random.next_u64() % (self.width as u64 - 2)) as MapCoordinate;
+            let y = 1 + (random.next_u64() % (self.height as u64 - 2)) as MapCoordinate;
 
-                return Some(RaycastHit { tile, is_vertical: is_vertical_step, distance: euclidean_distance });
+            self.tiles[(y * self.width + x) as usize] = materials.get("empty").unwrap();
+
+            WorldPosition { x: x as f32 + random.next_f32(), y: y as f32 + random.next_f32() }
+        }
+
+        // Distance to the nearest visible surface, going through every segment of every tile of the map.
+        fn brute_force_distance(&self, origin: WorldPosition, ray_direction: WorldDirection) -> Option<WorldLength> {
+            let mut nearest = None;
+
+            for (index, tile) in self.tiles.iter().enumerate() {
+                if !tile.is_visible() {
+                    continue;
+                }
+
+                let corner   = WorldPosition { x: (index as MapCoordinate % self.width) as f32, y: (index as MapCoordinate / self.width) as f32 };
+
+                let segments = match tile.shape() {
+                    TileShape::Segment(from, to) => vec![(from, to)],
+                    TileShape::Block             => vec![
+                        (WorldPosition::new(0.0, 0.0), WorldPosition::new(1.0, 0.0)),
+                        (WorldPosition::new(1.0, 0.0), WorldPosition::new(1.0, 1.0)),
+                        (WorldPosition::new(1.0, 1.0), WorldPosition::new(0.0, 1.0)),
+                        (WorldPosition::new(0.0, 1.0), WorldPosition::new(0.0, 0.0)),
+                    ],
+                };
+
+                for (from, to) in segments {
+                    if let Some(distance) = intersect(origin, ray_direction, corner + from, corner + to) {
+                        nearest = Some(WorldLength::min(distance, nearest.unwrap_or(WorldLength::INFINITY)));
+                    }
+                }
             }
 
-            // We increment steps after the iteration because we don't want to count steps into the wall.
-            if is_vertical_step {
-                steps_accumulator.y += step.y;
-            } else {
-                steps_accumulator.x += step.x;
+            nearest
+        }
+    }
+
+    // Distance along the ray to the segment, solved with Cramer's rule.
+    fn intersect(origin: WorldPosition, ray_direction: WorldDirection, from: WorldPosition, to: WorldPosition) -> Option<WorldLength> {
+        let segment     = to - from;
+        let offset      = from - origin;
+        let determinant = segment.x * ray_direction.y - segment.y * ray_direction.x;
+
+        if determinant.abs() < 1e-9 {
+            return None;
+        }
+
+        let distance = (segment.x * offset.y - segment.y * offset.x) / determinant;
+        let ratio    = (ray_direction.x * offset.y - ray_direction.y * offset.x) / determinant;
+
+        (distance >= 0.0 && (0.0 ..= 1.0).contains(&ratio)).then_some(distance)
+    }
+
+    fn cast(map: &Map, origin: WorldPosition, ray_direction: WorldDirection) -> Option<RaycastHit> {
+        Raycaster::cast_ray(map, origin, ray_direction, RecursionLimits::default())
+    }
+
+    fn get_pixel(raycaster: &Raycaster, x: u16, y: u16) -> [u8; 4] {
+        let start = ((y as usize) * (raycaster.get_width() as usize) + (x as usize)) * (PIXEL_SIZE as usize);
+
+        raycaster.get_framebuffer()[start .. start + (PIXEL_SIZE as usize)].try_into().unwrap()
+    }
+
+    fn to_bytes(color: Color) -> [u8; 4] {
+        let rgba = color.to_rgba();
+
+        [rgba.0, rgba.1, rgba.2, rgba.3]
+    }
+
+    // One wall across a corridor, 2.5 in front of the camera, which looks at it from the middle of the corridor.
+    #[test]
+    fn single_wall_between_ceiling_and_floor() {
+        let materials = MaterialRegistry::load(Path::new("assets/materials.toml")).unwrap();
+        let wall      = materials.get("wall").unwrap();
+        let empty     = materials.get("empty").unwrap();
+
+        let tiles     = vec![
+            wall, wall,  wall,
+            wall, empty, wall,
+            wall, empty, wall,
+            wall, empty, wall,
+            wall, wall,  wall,
+        ];
+
+        let map       = Map::new(3, 5, tiles, MapPosition { x: 1, y: 3 });
+        let distance  = 2.5;
+        let heights   = map.get_heights(WorldPosition::new(1.5, 3.5));
+
+        // Indoors, the ceiling is black, the floor grey, and the wall facing us fully lit.
+        let ceiling   = to_bytes(Color::BLACK);
+        let floor     = to_bytes(Color::new(0.5, 0.5, 0.5, 1.0));
+        let red       = to_bytes(wall.color().unwrap());
+
+        for pitch in [0.0, 0.2, -0.15] {
+            let mut raycaster = Raycaster::new(WIDTH, HEIGHT);
+            let mut camera    = Camera::new(WorldPosition::new(1.5, 3.5), 0.0);
+
+            raycaster.show_minimap = false;
+
+            camera.look_up(pitch);
+            raycaster.update_framebuffer(&map, &camera, &[]);
+
+            // The middle column looks straight ahead. The pitch moves the horizon down from the middle of the screen.
+            let x               = WIDTH / 2;
+            let horizon         = (HEIGHT as f32) * (0.5 + pitch);
+            let pixels_per_unit = (HEIGHT as f32) / distance;
+            let top             = (horizon - (heights.ceiling - EYE_HEIGHT) * pixels_per_unit).round() as u16;
+            let bottom          = (horizon + (EYE_HEIGHT - heights.floor) * pixels_per_unit).round() as u16;
+
+            assert!(0 < top && bottom < HEIGHT, "The wall fills the screen with a pitch of {}", pitch);
+
+            for y in 0 .. HEIGHT {
+                let expected = if y + 1 < top { ceiling } else if y > top && y + 1 < bottom { red } else if y > bottom { floor } else { continue };
+
+                assert_eq!(get_pixel(&raycaster, x, y), expected, "Row {} is wrong with a pitch of {} (wall from {} to {})", y, pitch, top, bottom);
+            }
+        }
+    }
+
+    // Packing pixels and filling spans must not change a single byte of any frame.
+    #[test]
+    fn frames_match_the_renderer_before_packed_columns() {
+        let materials  = MaterialRegistry::load(Path::new("assets/materials.toml")).unwrap();
+        let map        = Map::load(Path::new("assets/maps/demo.toml"), &materials).unwrap();
+        let sector_map = SectorMap::make_demo_sector_map(&materials);
+        let sprites    = [get_sprites(&map), get_sprites(&sector_map)];
+
+        for (width, height) in GOLDEN_SIZES {
+            for (scene, &(name, is_sectors, (x, y), angle, pitch)) in GOLDEN_SCENES.iter().enumerate() {
+                let     level: &dyn Level = if is_sectors { &sector_map } else { &map };
+                let mut camera            = Camera::new(WorldPosition::new(x, y), angle);
+                let mut raycaster         = Raycaster::new(width, height);
+                let     path              = get_golden_path(name, width, height);
+                let     golden            = image::open(&path).unwrap_or_else(|error| panic!("Could not read {}: {}", path, error)).to_rgba8();
+
+                camera.look_up(pitch);
+                add_overlays(&mut raycaster, scene);
+                raycaster.update_framebuffer(level, &camera, &sprites[is_sectors as usize]);
+
+                assert!(raycaster.get_framebuffer() == golden.as_raw().as_slice(), "The {} scene is not the same as {}", name, path);
             }
+        }
+    }
+
+    #[test]
+    fn random_rays_hit_the_nearest_wall() {
+        let     materials = MaterialRegistry::load(Path::new("assets/materials.toml")).unwrap();
+        let mut random    = Random::new(0x5eed);
+
+        for _ in 0 .. MAPS {
+            let mut test_map = TestMap::new(&mut random, &materials);
+
+            for _ in 0 .. RAYS_PER_MAP {
+                let origin        = test_map.make_origin(&mut random, &materials);
+                let angle         = random.next_f32() * TAU;
+                let ray_direction = WorldDirection::new(angle.sin(), -angle.cos());
+                let map           = test_map.to_map();
 
-            within_bounds = (
-                   current_tile.x >= 0
-                && current_tile.y >= 0
-                && (current_tile.x as MapCoordinate) < map.width
-                && (current_tile.y as MapCoordinate) < map.height
-            );
+                // The map is closed, every ray ends on a wall.
+                let hit           = cast(&map, origin, ray_direction).unwrap_or_else(|| panic!("The ray from {} towards {} escaped", origin, ray_direction));
+                let expected      = test_map.brute_force_distance(origin, ray_direction).unwrap();
+
+                assert!(hit.tile.is_visible());
+                assert!((hit.distance - expected).abs() < EPSILON, "The ray from {} towards {} hit at {}, instead of {}", origin, ray_direction, hit.distance, expected);
+            }
         }
+    }
+
+    #[test]
+    fn removing_walls_never_brings_hits_closer() {
+        let     materials = MaterialRegistry::load(Path::new("assets/materials.toml")).unwrap();
+        let     empty     = materials.get("empty").unwrap();
+        let mut random    = Random::new(0xd00d);
+
+        for _ in 0 .. MAPS {
+            let mut test_map = TestMap::new(&mut random, &materials);
+            let     origin   = test_map.make_origin(&mut random, &materials);
+            let     before   = test_map.to_map();
 
-        None
+            // Inside walls go away, one in two. The walls around stay, so that the map is still closed.
+            for y in 1 .. test_map.height - 1 {
+                for x in 1 .. test_map.width - 1 {
+                    if random.next_f32() < 0.5 {
+                        test_map.tiles[(y * test_map.width + x) as usize] = empty;
+                    }
+                }
+            }
+
+            let after = test_map.to_map();
+
+            for _ in 0 .. RAYS_PER_MAP {
+                let angle         = random.next_f32() * TAU;
+                let ray_direction = WorldDirection::new(angle.sin(), -angle.cos());
+                let hit_before    = cast(&before, origin, ray_direction).unwrap();
+                let hit_after     = cast(&after, origin, ray_direction).unwrap();
+
+                assert!(hit_after.distance >= hit_before.distance - EPSILON, "The ray from {} towards {} hit at {}, then at {}", origin, ray_direction, hit_before.distance, hit_after.distance);
+            }
+        }
     }
 
-    fn set_pixel(&mut self, x: u16, y: u16, color: &ggez::graphics::Color) {
-        let big_x     = x as usize;
-        let big_y     = y as usize;
-        let big_width = self.width as usize;
+    // Mirrors and portals on the walls around and mirrors inside: rays bounce and go through, but never get out.
+    #[test]
+    fn rays_never_escape_enclosed_maps() {
+        let     materials = MaterialRegistry::load(Path::new("assets/materials.toml")).unwrap();
+        let     mirror    = materials.get("mirror").unwrap();
+        let mut random    = Random::new(0xe5c4);
+
+        for _ in 0 .. MAPS {
+            let mut test_map = TestMap::new(&mut random, &materials);
+            let     width    = test_map.width;
+            let     height   = test_map.height;
+
+            for tile in test_map.tiles.iter_mut() {
+                if random.next_f32() < 0.1 {
+                    *tile = mirror;
+                }
+            }
+
+            // The west wall leads to the north wall, through the middle of both.
+            let west  = WallFace::new(0, height / 2, FaceSide::East);
+            let north = WallFace::new(width / 2, 0, FaceSide::South);
+
+            for face in [west, north] {
+                test_map.tiles[(face.cell.y * width + face.cell.x) as usize] = materials.get("wall").unwrap();
+            }
+
+            let     origin = test_map.make_origin(&mut random, &materials);
+            let mut map    = test_map.to_map();
+
+            map.link_faces(west, north);
+
+            for _ in 0 .. RAYS_PER_MAP {
+                let angle         = random.next_f32() * TAU;
+                let ray_direction = WorldDirection::new(angle.sin(), -angle.cos());
+                let hit           = cast(&map, origin, ray_direction).unwrap_or_else(|| panic!("The ray from {} towards {} escaped", origin, ray_direction));
+                let first_surface = test_map.brute_force_distance(origin, ray_direction).unwrap();
+
+                // Bouncing and going through portals only ever make the path longer.
+                assert!(hit.tile.is_visible());
+                assert!(hit.distance.is_finite() && hit.distance >= first_surface - EPSILON, "The ray from {} towards {} hit at {}, before the first surface at {}", origin, ray_direction, hit.distance, first_surface);
+            }
+        }
+    }
+
+    // A corridor going east, with a mirror at its end.
+    #[test]
+    fn mirrors_send_rays_back() {
+        let materials = MaterialRegistry::load(Path::new("assets/materials.toml")).unwrap();
+        let wall      = materials.get("wall").unwrap();
+        let mirror    = materials.get("mirror").unwrap();
+        let empty     = materials.get("empty").unwrap();
+        let tiles     = (0 .. 18).map(|index| if index == 11 { mirror } else if index / 6 == 1 && index % 6 != 0 { empty } else { wall }).collect();
+        let map       = Map::new(6, 3, tiles, MapPosition { x: 1, y: 1 });
+        let origin    = WorldPosition::new(2.5, 1.5);
+        let east      = WorldDirection::new(1.0, 0.0);
+
+        // Back from the mirror, 2.5 away, to the west wall, 4 further.
+        let reflected = Raycaster::cast_ray(&map, origin, east, RecursionLimits { reflections: 1, portals: 0 }).unwrap();
+
+        assert!(reflected.tile == wall);
+        assert!((reflected.distance - 6.5).abs() < EPSILON, "The ray came back to the wall at {}, instead of 6.5", reflected.distance);
+        assert!(reflected.normal == WorldVector::new(1.0, 0.0));
+
+        // Past the limit, the mirror itself is seen.
+        let seen      = Raycaster::cast_ray(&map, origin, east, RecursionLimits { reflections: 0, portals: 0 }).unwrap();
+
+        assert!(seen.tile == mirror);
+        assert!((seen.distance - 2.5).abs() < EPSILON, "The ray met the mirror at {}, instead of 2.5", seen.distance);
+    }
+
+    // Two corridors, one going east and one going south, the end of the first leading into the second.
+    #[test]
+    fn portals_take_rays_to_their_exit() {
+        let     materials = MaterialRegistry::load(Path::new("assets/materials.toml")).unwrap();
+        let     wall      = materials.get("wall").unwrap();
+        let     empty     = materials.get("empty").unwrap();
+
+        // The first corridor is on row 1 from column 1 to 3, the second on column 6 from row 1 to 5.
+        let     tiles     = (0 .. 56).map(|index| if (index / 8 == 1 && (1 ..= 3).contains(&(index % 8))) || (index % 8 == 6 && (1 ..= 5).contains(&(index / 8))) { empty } else { wall }).collect();
+        let mut map       = Map::new(8, 7, tiles, MapPosition { x: 1, y: 1 });
+        let     entrance  = WallFace::new(4, 1, FaceSide::West);
+        let     exit      = WallFace::new(6, 0, FaceSide::South);
+        let     origin    = WorldPosition::new(1.5, 1.25);
+
+        map.link_faces(entrance, exit);
+
+        // 2.5 to the portal, then down the second corridor, coming out a quarter to the east of its middle.
+        let through = Raycaster::cast_ray(&map, origin, WorldDirection::new(1.0, 0.0), RecursionLimits { reflections: 0, portals: 1 }).unwrap();
+
+        assert!((through.distance - 7.5).abs() < EPSILON, "The ray hit at {}, instead of 7.5", through.distance);
+        assert!(through.normal == WorldVector::new(0.0, -1.0));
+        assert!((through.surface_offset - 0.25).abs() < EPSILON, "The ray hit {} along the wall, instead of 0.25", through.surface_offset);
 
-        let start = (PIXEL_SIZE as usize) * (big_y * big_width + big_x);
-        let rgba  = color.to_rgba();
+        // Past the limit, the wall the portal is on is seen.
+        let blocked = Raycaster::cast_ray(&map, origin, WorldDirection::new(1.0, 0.0), RecursionLimits { reflections: 0, portals: 0 }).unwrap();
 
-        self.framebuffer[start + 0] = rgba.0;
-        self.framebuffer[start + 1] = rgba.1;
-        self.framebuffer[start + 2] = rgba.2;
-        self.framebuffer[start + 3] = rgba.3;
+        assert!((blocked.distance - 2.5).abs() < EPSILON, "The ray hit at {}, instead of 2.5", blocked.distance);
+        assert!(blocked.normal == WorldVector::new(-1.0, 0.0));
     }
 }
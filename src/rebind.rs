@@ -0,0 +1,123 @@
+use ggez::{event::{Button, KeyCode}, graphics::{Color, DrawParam, Drawable, Text}};
+
+use crate::input::{Action, Binding, Bindings};
+
+const MARGIN:        f32 = 24.0;
+const LINE_HEIGHT:   f32 = 24.0;
+const LABEL_WIDTH:   f32 = 200.0;
+
+/*
+* Lists every action with its bindings. Up and down pick an action, Return waits for the next thing pressed
+* that binding files can name and adds it to the bindings of the action, Back clears them and Delete puts the
+* defaults back. Escape leaves (or stops waiting). On gamepads, the D-pad picks, South adds, West clears, North
+* resets and East leaves.
+*
+* These are not bindings, so that the screen works however badly things were bound.
+*/
+#[derive(Default)]
+pub struct RebindScreen {
+    selected:   usize,
+    is_waiting: bool,
+}
+
+impl RebindScreen {
+    pub fn new() -> RebindScreen {
+        RebindScreen::default()
+    }
+
+    // Whether the screen stays open.
+    pub fn press(&mut self, binding: Binding, bindings: &mut Bindings) -> bool {
+        let action = Action::ALL[self.selected];
+        let count  = Action::ALL.len();
+
+        // What could not be written to the bindings file is not bound, the screen waits for something else.
+        if self.is_waiting {
+            if !binding.has_name() {
+                return true;
+            }
+
+            self.is_waiting = false;
+
+            if binding != Binding::Key(KeyCode::Escape) {
+                bindings.add(action, binding);
+            }
+
+            return true;
+        }
+
+        match binding {
+            Binding::Key(KeyCode::Up)     | Binding::Button(Button::DPadUp)   => self.selected = (self.selected + count - 1) % count,
+            Binding::Key(KeyCode::Down)   | Binding::Button(Button::DPadDown) => self.selected = (self.selected + 1) % count,
+            Binding::Key(KeyCode::Return) | Binding::Button(Button::South)    => self.is_waiting = true,
+            Binding::Key(KeyCode::Back)   | Binding::Button(Button::West)     => bindings.clear(action),
+            Binding::Key(KeyCode::Delete) | Binding::Button(Button::North)    => bindings.reset(action),
+            Binding::Key(KeyCode::Escape) | Binding::Button(Button::East)     => return false,
+            _                                                                 => (),
+        }
+
+        true
+    }
+
+    pub fn draw(&self, context: &mut ggez::Context, bindings: &Bindings) -> ggez::GameResult {
+        let help = "Up and down pick, Return adds, Back clears, Delete resets, Escape leaves.";
+
+        Text::new(help).draw(context, DrawParam::new().dest([MARGIN, MARGIN]).color(Color::WHITE))?;
+
+        // Actions that do not fit below the help come up as they are picked.
+        let lines = ((ggez::graphics::screen_coordinates(context).h - MARGIN) / LINE_HEIGHT) as usize;
+        let shown = lines.saturating_sub(2).max(1);
+        let first = (self.selected + 1).saturating_sub(shown);
+
+        for (index, action) in Action::ALL.iter().enumerate().skip(first).take(shown) {
+            let y     = MARGIN + LINE_HEIGHT * ((index - first + 2) as f32);
+            let color = if index == self.selected { Color::YELLOW } else { Color::WHITE };
+
+            let names = if index == self.selected && self.is_waiting {
+                "Press something to bind, or Escape...".to_owned()
+            } else {
+                bindings.get(*action).iter().map(Binding::to_string).collect::<Vec<_>>().join(", ")
+            };
+
+            Text::new(action.get_label()).draw(context, DrawParam::new().dest([MARGIN, y]).color(color))?;
+            Text::new(names).draw(context, DrawParam::new().dest([MARGIN + LABEL_WIDTH, y]).color(color))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use ggez::event::KeyCode;
+
+    use crate::input::{Action, Binding, Bindings};
+
+    use super::RebindScreen;
+
+    // A key binding files have no name for is passed over, so that the bindings written can be loaded back.
+    #[test]
+    fn keys_without_a_name_are_not_bound() {
+        let mut screen   = RebindScreen::new();
+        let mut bindings = Bindings::default();
+        let     path     = env::temp_dir().join("rebind-keys-without-a-name.toml");
+        let     action   = Action::ALL[0];
+
+        for key_code in [KeyCode::Return, KeyCode::Numpad5, KeyCode::G] {
+            assert!(screen.press(Binding::Key(key_code), &mut bindings));
+        }
+
+        assert!(!Binding::Key(KeyCode::Numpad5).has_name());
+        assert!(bindings.get(action).contains(&Binding::Key(KeyCode::G)));
+        assert!(!bindings.get(action).contains(&Binding::Key(KeyCode::Numpad5)));
+
+        bindings.write(&path).unwrap();
+
+        let loaded = Bindings::load(&path);
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap().get(action), bindings.get(action));
+    }
+}
@@ -0,0 +1,315 @@
+use std::{fmt, fs::{self, File}, io::{self, BufWriter, Write}, path::{Path, PathBuf}};
+
+use crate::{asset::{self, LoadError}, level::{self, LevelSource}, simulation::{Simulation, TickInput}};
+
+/*
+* Replays are text files: a header telling how to start the simulation again, then one line per tick with
+* the input of that tick and the checksum of the state it led to:
+*
+*   raycaster-replay 3
+*   level map assets/maps/demo.toml
+*   level-data 7d3a09c1e4b2f865
+*   seed 1234
+*   ticks-per-second 60
+*   1 1 0 0 0 0 0 4f1c6d2a9b3e8f07
+*   2 1 0 0.5 0 1 0 90a2b4c6d8e0f213
+*
+* The level data is a hash of the files the level is made from, see `hash_level_data`: replays of other files
+* would go another way, they are refused. Tick lines give the tick number, then forward, sideways, turn and
+* look up, then whether the player fires (0 or 1) and the weapons they switch by, then the checksum in
+* hexadecimal. Floats are written in their shortest form that reads back to the same value.
+*
+* Older versions cannot be played: version 1 comes from before weapons, and version 2 does not tell which
+* files it was recorded with.
+*/
+const REPLAY_MAGIC:   &str = "raycaster-replay";
+const REPLAY_VERSION: u32  = 3;
+
+// How to start the simulation again.
+#[derive(PartialEq, Clone, Debug)]
+pub struct ReplayHeader {
+    pub level:            LevelSource,
+    pub level_data:       u64,
+    pub seed:             u64,
+    pub ticks_per_second: u32,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct RecordedTick {
+    pub input:    TickInput,
+    pub checksum: u64,
+}
+
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub ticks:  Vec<RecordedTick>,
+}
+
+// Writes ticks as they are played. Lines are written as they come, so that a crash still leaves a replay.
+pub struct ReplayRecorder {
+    writer: BufWriter<File>,
+    tick:   u64,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ReplayOutcome {
+    Matched  { ticks: u64 },
+    // The first tick whose state differs from the recorded one.
+    Diverged { tick: u64, expected: u64, actual: u64 },
+}
+
+impl ReplayRecorder {
+    pub fn create(path: &Path, header: &ReplayHeader) -> io::Result<ReplayRecorder> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writeln!(writer, "{} {}", REPLAY_MAGIC, REPLAY_VERSION)?;
+
+        match &header.level {
+            LevelSource::Map(path)   => writeln!(writer, "level map {}", path.display())?,
+            LevelSource::DemoSectors => writeln!(writer, "level sectors")?,
+        }
+
+        writeln!(writer, "level-data {:016x}", header.level_data)?;
+
+        writeln!(writer, "seed {}", header.seed)?;
+        writeln!(writer, "ticks-per-second {}", header.ticks_per_second)?;
+
+        Ok(ReplayRecorder { writer, tick: 0 })
+    }
+
+    // `checksum` is that of the state after the tick.
+    pub fn record(&mut self, input: &TickInput, checksum: u64) -> io::Result<()> {
+        self.tick += 1;
+
+        writeln!(
+            self.writer,
+            "{} {} {} {} {} {} {} {:016x}",
+            self.tick, input.forward, input.sideways, input.turn, input.look_up, input.fire as u8, input.switch_weapon, checksum
+        )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Replay, LoadError> {
+        let text  = fs::read_to_string(path).map_err(|error| LoadError::Io { path: path.to_owned(), error })?;
+        let error = |line: usize, message: &str| LoadError::Parse { path: path.to_owned(), message: format!("line {}: {}", line + 1, message) };
+
+        let mut lines = text.lines().enumerate();
+        let mut next  = |expected: &str| lines.next().ok_or_else(|| error(0, &format!("the replay ends before its {}", expected)));
+
+        let (index, line) = next("version")?;
+
+        match line.strip_prefix(REPLAY_MAGIC).and_then(|rest| rest.strip_prefix(' ')).and_then(|version| version.parse::<u32>().ok()) {
+            Some(REPLAY_VERSION) => (),
+            Some(1)              => return Err(error(index, "replays of version 1 come from before weapons, the game cannot go the same way again")),
+            Some(2)              => return Err(error(index, "replays of version 2 do not tell which level data they were recorded with, they cannot be checked")),
+            _                    => {
+                return Err(error(index, &format!("expected \"{} {}\", this is not a replay or it comes from another version", REPLAY_MAGIC, REPLAY_VERSION)));
+            },
+        }
+
+        let (index, line) = next("level")?;
+
+        let level = match line.strip_prefix("level ") {
+            Some("sectors")                          => LevelSource::DemoSectors,
+            Some(level) if level.starts_with("map ") => LevelSource::Map(PathBuf::from(&level["map ".len() ..])),
+            _                                        => return Err(error(index, "expected \"level map <path>\" or \"level sectors\"")),
+        };
+
+        let (index, line) = next("level data")?;
+        let level_data    = line.strip_prefix("level-data ").and_then(|hash| u64::from_str_radix(hash, 16).ok()).ok_or_else(|| error(index, "expected \"level-data <hexadecimal hash>\""))?;
+
+        let (index, line) = next("seed")?;
+        let seed          = line.strip_prefix("seed ").and_then(|seed| seed.parse().ok()).ok_or_else(|| error(index, "expected \"seed <number>\""))?;
+
+        let (index, line) = next("tick rate")?;
+        let ticks_per_second = line
+        .strip_prefix("ticks-per-second ")
+        .and_then(|rate| rate.parse().ok())
+        .filter(|rate| *rate > 0)
+        .ok_or_else(|| error(index, "expected \"ticks-per-second <number>\""))?;
+
+        let mut ticks = vec![];
+
+        for (index, line) in lines {
+            let fields: Vec<_> = line.split_whitespace().collect();
+
+            let tick = match fields.as_slice() {
+                [tick, forward, sideways, turn, look_up, fire, switch, checksum] if tick.parse() == Ok(ticks.len() + 1) => {
+                    let axis   = |field: &str| field.parse::<f32>().map_err(|_| error(index, &format!("{:?} is not a number", field)));
+                    let fire   = match *fire {
+                        "0"   => false,
+                        "1"   => true,
+                        field => return Err(error(index, &format!("{:?} is neither 0 nor 1", field))),
+                    };
+                    let switch = switch.parse::<i32>().map_err(|_| error(index, &format!("{:?} is not a whole number", switch)))?;
+
+                    RecordedTick {
+                        input:    TickInput { forward: axis(forward)?, sideways: axis(sideways)?, turn: axis(turn)?, look_up: axis(look_up)?, fire, switch_weapon: switch },
+                        checksum: u64::from_str_radix(checksum, 16).map_err(|_| error(index, &format!("{:?} is not a checksum", checksum)))?,
+                    }
+                },
+                [] => continue,
+                _  => return Err(error(index, &format!("expected tick {} with four axes, firing, a weapon switch and a checksum", ticks.len() + 1))),
+            };
+
+            ticks.push(tick);
+        }
+
+        let current = hash_level_data(&level)?;
+
+        if current != level_data {
+            return Err(LoadError::invalid(path, format!("it was recorded with other level data ({:016x}, the files now give {:016x}), it would not go the same way", level_data, current)));
+        }
+
+        Ok(Replay { header: ReplayHeader { level, level_data, seed, ticks_per_second }, ticks })
+    }
+
+    // The simulation as it was when recording started.
+    pub fn make_simulation(&self) -> Result<Simulation, LoadError> {
+        let loaded = level::load_level(&self.header.level)?;
+
+        Ok(Simulation::new(self.header.level.clone(), loaded.level, loaded.entities, self.header.ticks_per_second, self.header.seed))
+    }
+
+    // Steps `simulation` through the recorded inputs, stopping at the first state that differs.
+    pub fn play(&self, simulation: &mut Simulation) -> ReplayOutcome {
+        for recorded in &self.ticks {
+            simulation.step(&recorded.input);
+
+            let checksum = simulation.get_checksum();
+
+            if checksum != recorded.checksum {
+                return ReplayOutcome::Diverged { tick: simulation.get_tick(), expected: recorded.checksum, actual: checksum };
+            }
+        }
+
+        ReplayOutcome::Matched { ticks: self.ticks.len() as u64 }
+    }
+}
+
+/*
+* FNV-1a hash of the files the level is made from: the map, for tile maps, the materials and the entity
+* templates. The demo sector map is made by code.
+*/
+pub fn hash_level_data(level: &LevelSource) -> Result<u64, LoadError> {
+    let mut paths = vec![asset::get_asset_path("materials.toml"), asset::get_asset_path("entities.toml")];
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    if let LevelSource::Map(path) = level {
+        paths.push(path.clone());
+    }
+
+    for path in paths {
+        let bytes = fs::read(&path).map_err(|error| LoadError::Io { path, error })?;
+
+        for byte in bytes {
+            hash = (hash ^ (byte as u64)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    Ok(hash)
+}
+
+impl fmt::Display for ReplayOutcome {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayOutcome::Matched  { ticks }                  => write!(formatter, "Replay matched the recording for all {} ticks.", ticks),
+            ReplayOutcome::Diverged { tick, expected, actual } => write!(formatter, "Replay diverged at tick {}: expected state {:016x}, got {:016x}.", tick, expected, actual),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::{Path, PathBuf}};
+
+    use crate::{asset::LoadError, level::{self, LevelSource}, simulation::{Simulation, TickInput}};
+
+    use super::{Replay, ReplayHeader, ReplayOutcome, ReplayRecorder, hash_level_data};
+
+    const TICKS_PER_SECOND: u32 = 60;
+
+    // A few seconds of walking, turning and firing on the demo map, written to `path`.
+    fn record(path: &Path) {
+        let level  = LevelSource::Map(PathBuf::from("assets/maps/demo.toml"));
+        let loaded = level::load_level(&level).unwrap();
+        let header = ReplayHeader { level: level.clone(), level_data: hash_level_data(&level).unwrap(), seed: 42, ticks_per_second: TICKS_PER_SECOND };
+
+        let mut simulation = Simulation::new(level, loaded.level, loaded.entities, TICKS_PER_SECOND, 42);
+        let mut recorder   = ReplayRecorder::create(path, &header).unwrap();
+
+        for tick in 0 .. 3 * TICKS_PER_SECOND {
+            let input = TickInput { forward: 1.0, sideways: 0.0, turn: if tick % 60 < 20 { 0.5 } else { 0.0 }, look_up: 0.0, fire: tick % 45 < 5, switch_weapon: (tick == 90) as i32 };
+
+            simulation.step(&input);
+            recorder.record(&input, simulation.get_checksum()).unwrap();
+        }
+
+        recorder.flush().unwrap();
+    }
+
+    // Replaces the line starting with `prefix`.
+    fn rewrite_line(path: &Path, prefix: &str, line: &str) {
+        let text = fs::read_to_string(path).unwrap();
+        let text = text.lines().map(|old| if old.starts_with(prefix) { line } else { old }).collect::<Vec<_>>().join("\n");
+
+        fs::write(path, text).unwrap();
+    }
+
+    fn get_message(result: Result<Replay, LoadError>) -> String {
+        match result {
+            Ok(_)      => panic!("The replay was loaded"),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn replays_go_the_same_way_again() {
+        let path = env::temp_dir().join("replays-go-the-same-way-again.replay");
+
+        record(&path);
+
+        let replay         = Replay::load(&path).unwrap();
+        let mut simulation = replay.make_simulation().unwrap();
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(replay.play(&mut simulation), ReplayOutcome::Matched { ticks: 3 * TICKS_PER_SECOND as u64 });
+    }
+
+    #[test]
+    fn replays_of_other_level_data_are_refused() {
+        let path = env::temp_dir().join("replays-of-other-level-data-are-refused.replay");
+
+        record(&path);
+        rewrite_line(&path, "level-data ", "level-data 0123456789abcdef");
+
+        let message = get_message(Replay::load(&path));
+
+        fs::remove_file(&path).unwrap();
+
+        assert!(message.contains("other level data"), "{}", message);
+    }
+
+    #[test]
+    fn older_versions_are_refused() {
+        let path = env::temp_dir().join("older-versions-are-refused.replay");
+
+        record(&path);
+
+        for (version, reason) in [(1, "before weapons"), (2, "which level data")] {
+            rewrite_line(&path, "raycaster-replay ", &format!("raycaster-replay {}", version));
+
+            let message = get_message(Replay::load(&path));
+
+            assert!(message.contains(reason), "{}", message);
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+}
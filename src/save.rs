@@ -0,0 +1,332 @@
+use std::{fs, path::{Path, PathBuf}, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{asset::LoadError, camera::Camera, entity::{Ai, AiState, Arsenal, Billboard, Collider, Entities, Health, Patrol, Pickup, Projectile, Transform}, level::{self, LevelSource}, map::MapPosition, random::Random, simulation::Simulation, weapon::WEAPONS, world::{WorldAngle, WorldPosition}};
+
+/*
+* Saves are TOML files holding everything needed to put the simulation back where it was: which level, what
+* changed in it, the camera, every entity with its components, and the random generator.
+*
+* Every save starts with its version. Saves from older versions are migrated one version at a time (see
+* `migrate`) before being read, saves from newer versions are refused.
+*/
+pub const SAVE_VERSION:   u32  = 3;
+pub const SAVE_DIRECTORY: &str = "saves";
+pub const QUICK_SAVE:     &str = "quick";
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SaveGame {
+    pub version:  u32,
+    pub tick:     u64,
+    // TOML integers are signed, so 64-bit values are written in hexadecimal.
+    #[serde(with = "hexadecimal")]
+    pub seed:     u64,
+    #[serde(with = "hexadecimal")]
+    pub random:   u64,
+    pub level:    LevelSource,
+    pub camera:   SavedCamera,
+    // Left out when empty: TOML cannot write an empty list after the tables above.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tiles:    Vec<SavedTile>,
+    #[serde(default)]
+    pub entities: Vec<SavedEntity>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SavedCamera {
+    pub position: WorldPosition,
+    pub rotation: WorldAngle,
+    pub pitch:    f32,
+}
+
+// A tile of the level that changed, by material id.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SavedTile {
+    pub x:        u32,
+    pub y:        u32,
+    pub material: String,
+}
+
+// Components of one entity. Entities are saved in slot order, and get new slots when loaded.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SavedEntity {
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_player:  bool,
+    pub transform:  Option<Transform>,
+    pub billboard:  Option<Billboard>,
+    pub collider:   Option<Collider>,
+    pub health:     Option<Health>,
+    pub ai:         Option<Ai>,
+    pub ai_state:   Option<AiState>,
+    pub patrol:     Option<Patrol>,
+    pub pickup:     Option<Pickup>,
+    pub arsenal:    Option<Arsenal>,
+    pub projectile: Option<Projectile>,
+}
+
+// What the slot list shows of a save, without reading all of it.
+#[derive(Debug)]
+pub struct SaveSlotInfo {
+    pub name:     String,
+    pub modified: Option<SystemTime>,
+}
+
+/*
+* Saves live in a directory, one file per slot, named after the slot. Quick saves are a slot like any other.
+*/
+pub struct SaveSlots {
+    directory: PathBuf,
+}
+
+impl SaveGame {
+    pub fn capture(simulation: &Simulation) -> SaveGame {
+        let entities = &simulation.entities;
+
+        SaveGame {
+            version:  SAVE_VERSION,
+            tick:     simulation.get_tick(),
+            seed:     simulation.get_seed(),
+            random:   simulation.random.get_state(),
+            level:    simulation.get_level_source().clone(),
+            camera:   SavedCamera {
+                position: simulation.camera.position,
+                rotation: simulation.camera.get_rotation(),
+                pitch:    simulation.camera.get_pitch(),
+            },
+            tiles:    simulation.level.get_tile_changes().iter().map(|change| {
+                SavedTile { x: change.position.x, y: change.position.y, material: change.tile.get_id().to_owned() }
+            }).collect(),
+            entities: entities.iter().map(|entity| SavedEntity {
+                is_player:  entity == simulation.player,
+                transform:  entities.transforms.get(entity).copied(),
+                billboard:  entities.billboards.get(entity).copied(),
+                collider:   entities.colliders.get(entity).copied(),
+                health:     entities.healths.get(entity).copied(),
+                ai:         entities.ais.get(entity).copied(),
+                ai_state:   entities.ai_states.get(entity).copied(),
+                patrol:     entities.patrols.get(entity).cloned(),
+                pickup:     entities.pickups.get(entity).copied(),
+                arsenal:    entities.arsenals.get(entity).cloned(),
+                projectile: entities.projectiles.get(entity).copied(),
+            }).collect(),
+        }
+    }
+
+    /*
+    * Loads the level again, then puts back what the save remembers. `path` is only used in error messages.
+    * The entities the level starts with are replaced by the saved ones.
+    */
+    pub fn restore(&self, path: &Path, ticks_per_second: u32) -> Result<Simulation, LoadError> {
+        let mut loaded = level::load_level(&self.level)?;
+
+        for saved in &self.tiles {
+            let tile     = loaded.materials.get(&saved.material).ok_or_else(|| LoadError::invalid(path, format!("there is no material {:?}", saved.material)))?;
+            let position = MapPosition { x: saved.x, y: saved.y };
+
+            if !loaded.level.set_tile(position, tile) {
+                return Err(LoadError::invalid(path, format!("the level has no tile at ({}, {})", saved.x, saved.y)));
+            }
+        }
+
+        let mut entities = Entities::new();
+        let mut player   = None;
+
+        for saved in &self.entities {
+            let entity = entities.spawn();
+
+            if let Some(transform)  = saved.transform  { entities.transforms.insert(entity, transform); }
+            if let Some(billboard)  = saved.billboard  { entities.billboards.insert(entity, billboard); }
+            if let Some(collider)   = saved.collider   { entities.colliders.insert(entity, collider); }
+            if let Some(health)     = saved.health     { entities.healths.insert(entity, health); }
+            if let Some(ai)         = saved.ai         { entities.ais.insert(entity, ai); }
+            if let Some(ai_state)   = saved.ai_state   { entities.ai_states.insert(entity, ai_state); }
+            if let Some(patrol)     = &saved.patrol    { entities.patrols.insert(entity, patrol.clone()); }
+            if let Some(pickup)     = saved.pickup     { entities.pickups.insert(entity, pickup); }
+            if let Some(projectile) = saved.projectile { entities.projectiles.insert(entity, projectile); }
+
+            // Weapons added since the save was made are carried without ammo.
+            if let Some(mut arsenal) = saved.arsenal.clone() {
+                arsenal.ammo.resize(WEAPONS.len(), 0);
+                entities.arsenals.insert(entity, arsenal);
+            }
+
+            if saved.is_player {
+                player = Some(entity);
+            }
+        }
+
+        let player     = player.ok_or_else(|| LoadError::invalid(path, "there is no player"))?;
+        let mut camera = Camera::new(self.camera.position, self.camera.rotation);
+
+        camera.look_up(self.camera.pitch);
+
+        let mut simulation = Simulation::new(self.level.clone(), loaded.level, Entities::new(), ticks_per_second, self.seed);
+
+        simulation.camera   = camera;
+        simulation.entities = entities;
+        simulation.player   = player;
+        simulation.random   = Random::new(self.random);
+        simulation.restore_tick(self.tick);
+
+        Ok(simulation)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), LoadError> {
+        let text = toml::to_string(self).map_err(|error| LoadError::invalid(path, error.to_string()))?;
+
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).map_err(|error| LoadError::Io { path: directory.to_owned(), error })?;
+        }
+
+        fs::write(path, text).map_err(|error| LoadError::Io { path: path.to_owned(), error })
+    }
+
+    pub fn read(path: &Path) -> Result<SaveGame, LoadError> {
+        let text  = fs::read_to_string(path).map_err(|error| LoadError::Io { path: path.to_owned(), error })?;
+        let parse = |message: String| LoadError::Parse { path: path.to_owned(), message };
+        let value = text.parse::<toml::Value>().map_err(|error| parse(error.to_string()))?;
+
+        let version = match value.get("version").and_then(toml::Value::as_integer) {
+            Some(version) if version >= 0 => version as u32,
+            _                             => return Err(parse("there is no version, this is not a save".to_owned())),
+        };
+
+        if version > SAVE_VERSION {
+            return Err(LoadError::invalid(path, format!("this save comes from a newer version of the game (save version {}, this game reads up to {})", version, SAVE_VERSION)));
+        }
+
+        let value = migrate(value, version).map_err(|message| LoadError::invalid(path, message))?;
+
+        value.try_into().map_err(|error: toml::de::Error| parse(error.to_string()))
+    }
+}
+
+/*
+* Brings a save of an older version up to date, one version at a time: each step takes the save as the
+* previous version wrote it, and changes what the next version changed (renamed fields, new fields with their
+* default value...), then bumps the version.
+*/
+fn migrate(mut value: toml::Value, version: u32) -> Result<toml::Value, String> {
+    match version {
+        // Enemies got a state machine, they start it from the beginning.
+        1 => {
+            let ai_state = toml::Value::try_from(AiState::default()).map_err(|error| error.to_string())?;
+
+            for entity in value.get_mut("entities").and_then(toml::Value::as_array_mut).into_iter().flatten() {
+                if let Some(entity) = entity.as_table_mut().filter(|entity| entity.contains_key("ai")) {
+                    entity.insert("ai_state".to_owned(), ai_state.clone());
+                }
+            }
+
+            value["version"] = toml::Value::Integer(2);
+
+            migrate(value, 2)
+        },
+
+        // The player got weapons, with what they start with.
+        2 => {
+            let arsenal = toml::Value::try_from(Arsenal::new()).map_err(|error| error.to_string())?;
+
+            for entity in value.get_mut("entities").and_then(toml::Value::as_array_mut).into_iter().flatten() {
+                let is_player = entity.get("is_player").and_then(toml::Value::as_bool) == Some(true);
+
+                if let Some(entity) = entity.as_table_mut().filter(|_| is_player) {
+                    entity.insert("arsenal".to_owned(), arsenal.clone());
+                }
+            }
+
+            value["version"] = toml::Value::Integer(3);
+
+            migrate(value, 3)
+        },
+
+        SAVE_VERSION => Ok(value),
+        _            => Err(format!("save version {} is too old to be migrated", version)),
+    }
+}
+
+impl SaveSlotInfo {
+    // When the file system tells.
+    pub fn get_minutes_old(&self) -> Option<u64> {
+        self.modified.and_then(|modified| modified.elapsed().ok()).map(|age| age.as_secs() / 60)
+    }
+}
+
+impl SaveSlots {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> SaveSlots {
+        SaveSlots { directory: directory.into() }
+    }
+
+    // Slot names end up in file names, so they are kept simple.
+    pub fn get_path(&self, slot: &str) -> Result<PathBuf, LoadError> {
+        let is_valid = !slot.is_empty() && slot.chars().all(|character| character.is_ascii_alphanumeric() || character == '-' || character == '_');
+
+        if !is_valid {
+            return Err(LoadError::invalid(&self.directory, format!("{:?} is not a slot name, use letters, digits, '-' and '_'", slot)));
+        }
+
+        Ok(self.directory.join(format!("{}.toml", slot)))
+    }
+
+    pub fn save(&self, slot: &str, simulation: &Simulation) -> Result<PathBuf, LoadError> {
+        let path = self.get_path(slot)?;
+
+        SaveGame::capture(simulation).write(&path)?;
+
+        Ok(path)
+    }
+
+    pub fn load(&self, slot: &str, ticks_per_second: u32) -> Result<Simulation, LoadError> {
+        let path = self.get_path(slot)?;
+
+        SaveGame::read(&path)?.restore(&path, ticks_per_second)
+    }
+
+    pub fn delete(&self, slot: &str) -> Result<(), LoadError> {
+        let path = self.get_path(slot)?;
+
+        fs::remove_file(&path).map_err(|error| LoadError::Io { path, error })
+    }
+
+    // Most recent first. A missing directory means there are no saves yet.
+    pub fn list(&self) -> Result<Vec<SaveSlotInfo>, LoadError> {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries)                                                => entries,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(error)                                                 => return Err(LoadError::Io { path: self.directory.clone(), error }),
+        };
+
+        let mut slots: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let path = entry.path();
+            let name = path.file_stem()?.to_str()?.to_owned();
+
+            (path.extension()? == "toml").then(|| SaveSlotInfo { name, modified: entry.metadata().and_then(|metadata| metadata.modified()).ok() })
+        })
+        .collect();
+
+        slots.sort_by_key(|slot| std::cmp::Reverse(slot.modified));
+
+        Ok(slots)
+    }
+}
+
+mod hexadecimal {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:016x}", value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        let text = String::deserialize(deserializer)?;
+
+        u64::from_str_radix(&text, 16).map_err(|_| serde::de::Error::custom(format!("{:?} is not a hexadecimal number", text)))
+    }
+}
@@ -0,0 +1,254 @@
+use std::{f32::consts::TAU, fmt};
+
+use crate::{math, minimap, entity::EntitySpawn, level::{Heights, Level}, map::{MapCoordinate, MapPosition, SignedMapPosition}, material::{MaterialRegistry, Tile}, ray::{self, RayHit}, raycaster::Raycaster, world::{WorldDirection, WorldLength, WorldPosition}};
+
+pub type SectorId = usize;
+
+/*
+* Polygonal area of the world, with its own floor and ceiling heights, like in Doom. Sectors may lie within
+* other sectors (a platform in the middle of a room): the last one containing a position is the one there.
+*/
+pub struct Sector {
+    pub outline:     Vec<WorldPosition>,
+    pub heights:     Heights,
+    pub is_open_sky: bool,
+}
+
+/*
+* Wall going from `from` to `to`. One-sided lines are walls from the floor to the ceiling of their sector,
+* two-sided lines separate two sectors, and only have walls where heights differ (steps, lintels).
+*/
+pub struct Linedef {
+    pub from:  WorldPosition,
+    pub to:    WorldPosition,
+    pub tile:  Tile,
+    pub front: SectorId,
+    pub back:  Option<SectorId>,
+}
+
+/*
+* World made of sectors. Lines are looked up through a uniform grid of unit cells covering the world, each
+* cell listing the lines going through it, so that rays only test the lines of the cells they cross, in the
+* order they cross them, like with tiles.
+*/
+pub struct SectorMap {
+        sectors:       Vec<Sector>,
+        linedefs:      Vec<Linedef>,
+        spawn:         WorldPosition,
+        entity_spawns: Vec<EntitySpawn>,
+        grid_origin:   WorldPosition,
+        grid_width:    MapCoordinate,
+        grid_height:   MapCoordinate,
+        grid_cells:    Vec<Vec<usize>>,
+}
+
+// Why sectors and lines do not make a sector map.
+#[derive(Debug)]
+pub enum SectorMapError {
+    // Without lines, there is no grid to look them up in.
+    NoLines,
+    // Index of the first line bordering a sector that does not exist.
+    UnknownSector { linedef: usize },
+}
+
+impl fmt::Display for SectorMapError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SectorMapError::NoLines                   => write!(formatter, "A sector map needs at least one line."),
+            SectorMapError::UnknownSector { linedef } => write!(formatter, "Line {} borders a sector that does not exist.", linedef),
+        }
+    }
+}
+
+impl std::error::Error for SectorMapError {}
+
+impl SectorMap {
+    pub fn new(sectors: Vec<Sector>, linedefs: Vec<Linedef>, spawn: WorldPosition) -> Result<SectorMap, SectorMapError> {
+        if linedefs.is_empty() {
+            return Err(SectorMapError::NoLines);
+        }
+
+        if let Some(linedef) = linedefs.iter().position(|linedef| linedef.front >= sectors.len() || linedef.back.filter(|back| *back >= sectors.len()).is_some()) {
+            return Err(SectorMapError::UnknownSector { linedef });
+        }
+
+        let vertices    = || linedefs.iter().flat_map(|linedef| [linedef.from, linedef.to]);
+        let grid_origin = vertices().fold(WorldPosition::splat(f32::INFINITY), |corner, vertex| corner.min(vertex)).floor();
+        let grid_end    = vertices().fold(WorldPosition::splat(f32::NEG_INFINITY), |corner, vertex| corner.max(vertex)).floor();
+
+        let mut sector_map = SectorMap {
+            sectors,
+            spawn,
+            grid_origin,
+
+            grid_width:    (grid_end.x - grid_origin.x) as MapCoordinate + 1,
+            grid_height:   (grid_end.y - grid_origin.y) as MapCoordinate + 1,
+            grid_cells:    vec![],
+            linedefs:      vec![],
+            entity_spawns: vec![],
+        };
+
+        sector_map.grid_cells = vec![vec![]; (sector_map.grid_width * sector_map.grid_height) as usize];
+
+        // Lines are walked like rays, from one end to the other.
+        for (index, linedef) in linedefs.iter().enumerate() {
+            let from = linedef.from - grid_origin;
+
+            ray::traverse_grid(from, (linedef.to - linedef.from).normalize(), linedef.from.distance(linedef.to), |grid_cell| {
+                if let Some(cell) = sector_map.get_grid_cell(grid_cell.position) {
+                    sector_map.grid_cells[(cell.y * sector_map.grid_width + cell.x) as usize].push(index);
+                }
+
+                true
+            });
+        }
+
+        sector_map.linedefs = linedefs;
+
+        Ok(sector_map)
+    }
+
+    /*
+    * A hexagonal hall with a raised platform, a sunken courtyard open to the sky, a pillar and a mirror, none
+    * of which follow the grid. Walls use the materials of the demo tile map.
+    */
+    pub fn make_demo_sector_map(materials: &MaterialRegistry) -> SectorMap {
+        let material = |id: &str| materials.get(id).unwrap_or_else(|| panic!("The demo sector map needs the {:?} material!", id));
+
+        let polygon = |center: (f32, f32), radius: f32, sides: u32, angle: f32| -> Vec<WorldPosition> {
+            (0 .. sides)
+            .map(|side| angle + TAU * (side as f32) / (sides as f32))
+            .map(|angle| WorldPosition { x: center.0 + radius * angle.cos(), y: center.1 + radius * angle.sin() })
+            .collect()
+        };
+
+        let hall      = polygon((8.0, 8.0),  7.0, 6, 0.0);
+        let platform  = polygon((10.0, 6.0), 1.5, 5, 0.3);
+        let courtyard = polygon((5.5, 11.0), 1.8, 4, 0.2);
+        let pillar    = polygon((6.0, 5.0),  0.5, 4, 0.4);
+
+        let mut linedefs = vec![];
+
+        let mut add_loop = |outline: &[WorldPosition], tile: Tile, front: SectorId, back: Option<SectorId>| {
+            for (index, from) in outline.iter().enumerate() {
+                linedefs.push(Linedef { tile, front, back, from: *from, to: outline[(index + 1) % outline.len()] });
+            }
+        };
+
+        add_loop(&hall,      material("wall"),    0, None);
+        add_loop(&platform,  material("wall2"),   0, Some(1));
+        add_loop(&courtyard, material("wall2"),   0, Some(2));
+        add_loop(&pillar,    material("diag_ne"), 0, None);
+
+        // One of the walls of the hall is a mirror.
+        linedefs[3].tile = material("mirror");
+
+        let sectors = vec![
+            Sector { outline: hall,      heights: Heights { floor:  0.0,  ceiling: 1.5 }, is_open_sky: false },
+            Sector { outline: platform,  heights: Heights { floor:  0.25, ceiling: 1.5 }, is_open_sky: false },
+            Sector { outline: courtyard, heights: Heights { floor: -0.25, ceiling: 1.5 }, is_open_sky: true  },
+        ];
+
+        let mut sector_map = SectorMap::new(sectors, linedefs, WorldPosition { x: 4.0, y: 8.0 }).unwrap_or_else(|error| panic!("The demo sector map is broken: {}", error));
+
+        for (template, x, y) in [("barrel", 10.0, 6.0), ("medkit", 5.5, 11.0), ("grunt", 12.0, 10.0)] {
+            sector_map.add_entity_spawn(EntitySpawn { template: template.to_owned(), position: WorldPosition { x, y }, angle: 0.0, patrol: vec![] });
+        }
+
+        sector_map
+    }
+
+    pub fn add_entity_spawn(&mut self, spawn: EntitySpawn) {
+        self.entity_spawns.push(spawn);
+    }
+
+    pub fn get_linedefs(&self) -> &[Linedef] {
+        &self.linedefs
+    }
+
+    pub fn find_sector(&self, position: WorldPosition) -> Option<&Sector> {
+        self.sectors.iter().rev().find(|sector| math::is_point_in_polygon(position, &sector.outline))
+    }
+
+    fn get_grid_cell(&self, position: SignedMapPosition) -> Option<MapPosition> {
+        let is_inside = position.x >= 0
+            && position.y >= 0
+            && (position.x as MapCoordinate) < self.grid_width
+            && (position.y as MapCoordinate) < self.grid_height;
+
+        if is_inside { Some(MapPosition { x: position.x as MapCoordinate, y: position.y as MapCoordinate }) } else { None }
+    }
+}
+
+impl Level for SectorMap {
+    /*
+    * Lines crossing several cells are listed in each of them, so a line only counts as hit in the cell where
+    * the ray meets it, which also keeps hits in order. Within a cell, hits are sorted by distance.
+    */
+    fn walk_ray(&self, origin: WorldPosition, ray_direction: WorldDirection, max_distance: WorldLength, is_hit: &dyn Fn(Tile) -> bool, on_hit: &mut dyn FnMut(RayHit) -> bool) -> u32 {
+        let mut cell_hits = vec![];
+
+        ray::traverse_grid(origin - self.grid_origin, ray_direction, max_distance, |grid_cell| {
+            let cell = match self.get_grid_cell(grid_cell.position) {
+                Some(cell) => cell,
+                None       => return grid_cell.vertical_entry.is_none(),
+            };
+
+            cell_hits.clear();
+
+            for &index in &self.grid_cells[(cell.y * self.grid_width + cell.x) as usize] {
+                let linedef           = &self.linedefs[index];
+
+                if !is_hit(linedef.tile) {
+                    continue;
+                }
+
+                let (distance, ratio) = match math::intersect_ray_with_segment(origin, ray_direction, linedef.from, linedef.to) {
+                    Some(intersection) => intersection,
+                    None               => continue,
+                };
+
+                if distance < grid_cell.entry_distance || distance >= grid_cell.exit_distance || distance > max_distance {
+                    continue;
+                }
+
+                cell_hits.push(RayHit {
+                    cell,
+                    distance,
+
+                    tile:           linedef.tile,
+                    point:          origin + ray_direction * distance,
+                    normal:         math::get_facing_normal(linedef.to - linedef.from, ray_direction),
+                    surface_offset: ratio * linedef.from.distance(linedef.to),
+                    has_opening:    linedef.back.is_some(),
+                });
+            }
+
+            cell_hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+
+            cell_hits.iter().all(|hit| on_hit(*hit))
+        })
+    }
+
+    // Outside of any sector, there is nothing to stand on anyway.
+    fn get_heights(&self, position: WorldPosition) -> Heights {
+        self.find_sector(position).map_or(Heights { floor: 0.0, ceiling: 1.0 }, |sector| sector.heights)
+    }
+
+    fn is_open_sky(&self, position: WorldPosition) -> bool {
+        self.find_sector(position).filter(|sector| !sector.is_open_sky).is_none()
+    }
+
+    fn find_spawn(&self) -> WorldPosition {
+        self.spawn
+    }
+
+    fn get_entity_spawns(&self) -> &[EntitySpawn] {
+        &self.entity_spawns
+    }
+
+    fn draw_on_minimap(&self, raycaster: &mut Raycaster) {
+        minimap::draw_sector_lines(raycaster, self);
+    }
+}
+
@@ -0,0 +1,362 @@
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use serde::Deserialize;
+
+use crate::{asset::{self, LoadError}, camera, raycaster, simulation};
+
+// Read when it exists and no other settings file is given.
+pub const DEFAULT_SETTINGS_FILE:     &str = "settings.toml";
+// Written by the rebinding screen, defaults are used when there is none.
+pub const DEFAULT_BINDINGS_FILE:     &str = "bindings.toml";
+
+// In degrees turned per pixel the mouse moves.
+pub const DEFAULT_MOUSE_SENSITIVITY: f32  = 0.15;
+// Gamepad axes are centred when within this of the centre, worn sticks never quite going back to it.
+pub const DEFAULT_DEADZONE:          f32  = 0.2;
+// Larger windows are most likely a typo.
+const MAX_RESOLUTION:                u32  = 16384;
+const MAX_TICKS_PER_SECOND:          u32  = 1000;
+// Every bounce and every portal walks the ray again, deeper limits would only cost frames.
+const MAX_REFLECTIONS:               u32  = 16;
+const MAX_PORTALS:                   u32  = 32;
+
+/*
+* Everything the player may want to change without rebuilding the game. Every setting has a default, so that a
+* settings file only needs the ones that differ, and command-line options override the file (see
+* `CommandLine`). Files are checked when read (see `Settings::validate`), options when parsed.
+*
+*   [window]
+*   width      = 1024
+*   fullscreen = true
+*
+* Bindings have their own file, see input.rs.
+*/
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub window:   WindowSettings,
+    pub game:     GameSettings,
+    pub controls: ControlSettings,
+    pub renderer: RendererSettings,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowSettings {
+    pub title:      String,
+    pub width:      u32,
+    pub height:     u32,
+    pub fullscreen: bool,
+    pub vsync:      bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameSettings {
+    pub map:              PathBuf,
+    pub ticks_per_second: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlSettings {
+    pub bindings:          PathBuf,
+    // No mouse look at 0, the mouse is then left alone.
+    pub mouse_sensitivity: f32,
+    pub deadzone:          f32,
+}
+
+/*
+* The framebuffer is `render_scale` times the size of the window, and stretched to fill it: lower scales
+* trade sharpness for speed. The field of view is horizontal, in degrees.
+*/
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RendererSettings {
+    pub render_scale:    f32,
+    pub field_of_view:   f32,
+    pub minimap:         bool,
+    // Health, ammo, crosshair and messages, over the 3D view.
+    pub hud:             bool,
+    // Frames per second, on the HUD.
+    pub fps_counter:     bool,
+    // Font sheet of the HUD, see font.rs. The built-in font is used when there is none.
+    pub font:            Option<PathBuf>,
+    // Paths enemies follow, drawn on the minimap.
+    pub debug_paths:     bool,
+    pub max_reflections: u32,
+    pub max_portals:     u32,
+}
+
+/*
+* Command-line options override the settings file, which is only read when there is one. Flags that turn
+* something on can be turned off too: `--vsync=false`.
+*/
+#[derive(Debug, Parser)]
+#[command(name = "raycaster", version, about = "A raycasting engine, walking around maps of tiles or sectors.")]
+pub struct CommandLine {
+    #[arg(long, value_name = "FILE", help = "Settings file, settings.toml when there is one")]
+    pub config:            Option<PathBuf>,
+
+    #[arg(long, value_name = "FILE", conflicts_with = "sectors", help = "Tile map to play")]
+    pub map:               Option<PathBuf>,
+    #[arg(long, help = "Play the demo sector map instead of a tile map")]
+    pub sectors:           bool,
+    #[arg(long, value_name = "TICKS", value_parser = parse_ticks_per_second, help = "Simulation steps per second")]
+    pub ticks_per_second:  Option<u32>,
+
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_resolution, help = "Size of the window, 800x600 for instance")]
+    pub resolution:        Option<(u32, u32)>,
+    #[arg(long, value_name = "BOOL", num_args = 0 ..= 1, default_missing_value = "true", help = "Fill the screen")]
+    pub fullscreen:        Option<bool>,
+    #[arg(long, value_name = "BOOL", num_args = 0 ..= 1, default_missing_value = "true", help = "Wait for the screen between frames")]
+    pub vsync:             Option<bool>,
+
+    #[arg(long, value_name = "SCALE", value_parser = parse_render_scale, help = "Size of the framebuffer relative to the window, from 0 to 1")]
+    pub render_scale:      Option<f32>,
+    #[arg(long, value_name = "DEGREES", value_parser = parse_field_of_view, help = "Horizontal field of view")]
+    pub field_of_view:     Option<f32>,
+    #[arg(long, value_name = "BOOL", num_args = 0 ..= 1, default_missing_value = "true", help = "Show the minimap")]
+    pub minimap:           Option<bool>,
+    #[arg(long, value_name = "BOOL", num_args = 0 ..= 1, default_missing_value = "true", help = "Show health, ammo, a crosshair and messages")]
+    pub hud:               Option<bool>,
+    #[arg(long, value_name = "BOOL", num_args = 0 ..= 1, default_missing_value = "true", help = "Show the frame rate on the HUD")]
+    pub fps_counter:       Option<bool>,
+    #[arg(long, value_name = "FILE", help = "Font sheet of the HUD, the built-in font otherwise")]
+    pub font:              Option<PathBuf>,
+    #[arg(long, value_name = "BOOL", num_args = 0 ..= 1, default_missing_value = "true", help = "Draw the paths enemies follow on the minimap")]
+    pub debug_paths:       Option<bool>,
+    #[arg(long, value_name = "COUNT", value_parser = parse_max_reflections, help = "Bounces off mirrors a ray can take, mirrors then show their own colour")]
+    pub max_reflections:   Option<u32>,
+    #[arg(long, value_name = "COUNT", value_parser = parse_max_portals, help = "Portals a ray can go through, portals then show the wall they are on")]
+    pub max_portals:       Option<u32>,
+    #[arg(long, value_name = "FILE", help = "Bindings file, written by the rebinding screen")]
+    pub bindings:          Option<PathBuf>,
+    #[arg(long, value_name = "DEGREES", value_parser = parse_mouse_sensitivity, help = "Degrees turned per pixel the mouse moves, 0 to leave the mouse alone")]
+    pub mouse_sensitivity: Option<f32>,
+
+    #[arg(long, value_name = "FILE", conflicts_with = "load", help = "Record the game to a replay file")]
+    pub record:            Option<PathBuf>,
+    #[arg(long, value_name = "FILE", help = "Write how long each frame took to render to a CSV file")]
+    pub profile:           Option<PathBuf>,
+    #[arg(long, value_name = "FILE", help = "Play a replay without a window, and check that it goes the same way")]
+    pub replay:            Option<PathBuf>,
+    #[arg(long, value_name = "SLOT", help = "Start from a save")]
+    pub load:              Option<String>,
+    #[arg(long, help = "List the saves, most recent first")]
+    pub list_saves:        bool,
+    #[arg(long, help = "Time uploading frames to the GPU, new images against a streaming one, at 800x600 and 1920x1080")]
+    pub benchmark_upload:  bool,
+}
+
+impl Settings {
+    // The default settings file is optional, others are not.
+    pub fn load(command_line: &CommandLine) -> Result<Settings, LoadError> {
+        let mut settings = match &command_line.config {
+            Some(path)                                        => Settings::read(path)?,
+            None if Path::new(DEFAULT_SETTINGS_FILE).exists() => Settings::read(Path::new(DEFAULT_SETTINGS_FILE))?,
+            None                                              => Settings::default(),
+        };
+
+        settings.apply(command_line);
+
+        Ok(settings)
+    }
+
+    pub fn read(path: &Path) -> Result<Settings, LoadError> {
+        let settings: Settings = asset::read_toml(path)?;
+
+        settings.validate().map_err(|message| LoadError::invalid(path, message))?;
+
+        Ok(settings)
+    }
+
+    // Options were checked by the command-line parser already.
+    pub fn apply(&mut self, command_line: &CommandLine) {
+        if let Some(map) = &command_line.map {
+            self.game.map = map.clone();
+        }
+
+        if let Some(font) = &command_line.font {
+            self.renderer.font = Some(font.clone());
+        }
+
+        if let Some(bindings) = &command_line.bindings {
+            self.controls.bindings = bindings.clone();
+        }
+
+        if let Some((width, height)) = command_line.resolution {
+            self.window.width  = width;
+            self.window.height = height;
+        }
+
+        self.game.ticks_per_second      = command_line.ticks_per_second.unwrap_or(self.game.ticks_per_second);
+        self.window.fullscreen          = command_line.fullscreen.unwrap_or(self.window.fullscreen);
+        self.window.vsync               = command_line.vsync.unwrap_or(self.window.vsync);
+        self.renderer.render_scale      = command_line.render_scale.unwrap_or(self.renderer.render_scale);
+        self.renderer.field_of_view     = command_line.field_of_view.unwrap_or(self.renderer.field_of_view);
+        self.renderer.minimap           = command_line.minimap.unwrap_or(self.renderer.minimap);
+        self.renderer.hud               = command_line.hud.unwrap_or(self.renderer.hud);
+        self.renderer.fps_counter       = command_line.fps_counter.unwrap_or(self.renderer.fps_counter);
+        self.renderer.debug_paths       = command_line.debug_paths.unwrap_or(self.renderer.debug_paths);
+        self.renderer.max_reflections   = command_line.max_reflections.unwrap_or(self.renderer.max_reflections);
+        self.renderer.max_portals       = command_line.max_portals.unwrap_or(self.renderer.max_portals);
+        self.controls.mouse_sensitivity = command_line.mouse_sensitivity.unwrap_or(self.controls.mouse_sensitivity);
+    }
+
+    // Messages name the setting at fault, as written in the file.
+    pub fn validate(&self) -> Result<(), String> {
+        check_resolution(self.window.width, self.window.height).map_err(|message| format!("window.width and window.height: {}", message))?;
+        check_ticks_per_second(self.game.ticks_per_second).map_err(|message| format!("game.ticks_per_second: {}", message))?;
+        check_render_scale(self.renderer.render_scale).map_err(|message| format!("renderer.render_scale: {}", message))?;
+        check_field_of_view(self.renderer.field_of_view).map_err(|message| format!("renderer.field_of_view: {}", message))?;
+        check_max_reflections(self.renderer.max_reflections).map_err(|message| format!("renderer.max_reflections: {}", message))?;
+        check_max_portals(self.renderer.max_portals).map_err(|message| format!("renderer.max_portals: {}", message))?;
+        check_mouse_sensitivity(self.controls.mouse_sensitivity).map_err(|message| format!("controls.mouse_sensitivity: {}", message))?;
+        check_deadzone(self.controls.deadzone).map_err(|message| format!("controls.deadzone: {}", message))?;
+
+        Ok(())
+    }
+
+    // Size of the framebuffer, at least a pixel each way.
+    pub fn get_render_size(&self) -> (u16, u16) {
+        let scale = |length: u32| ((length as f32) * self.renderer.render_scale).round().max(1.0) as u16;
+
+        (scale(self.window.width), scale(self.window.height))
+    }
+}
+
+impl Default for WindowSettings {
+    fn default() -> WindowSettings {
+        WindowSettings {
+            title:      "Raycaster".to_owned(),
+            width:      raycaster::DEFAULT_SCREEN_WIDTH as u32,
+            height:     raycaster::DEFAULT_SCREEN_HEIGHT as u32,
+            fullscreen: false,
+            vsync:      true,
+        }
+    }
+}
+
+impl Default for GameSettings {
+    fn default() -> GameSettings {
+        GameSettings { map: asset::get_asset_path("maps/demo.toml"), ticks_per_second: simulation::DEFAULT_TICKS_PER_SECOND }
+    }
+}
+
+impl Default for ControlSettings {
+    fn default() -> ControlSettings {
+        ControlSettings { bindings: PathBuf::from(DEFAULT_BINDINGS_FILE), mouse_sensitivity: DEFAULT_MOUSE_SENSITIVITY, deadzone: DEFAULT_DEADZONE }
+    }
+}
+
+impl Default for RendererSettings {
+    fn default() -> RendererSettings {
+        RendererSettings {
+            render_scale:    1.0,
+            field_of_view:   camera::DEFAULT_FIELD_OF_VIEW.to_degrees(),
+            minimap:         true,
+            hud:             true,
+            fps_counter:     false,
+            font:            None,
+            debug_paths:     false,
+            max_reflections: raycaster::DEFAULT_MAX_REFLECTIONS,
+            max_portals:     raycaster::DEFAULT_MAX_PORTALS,
+        }
+    }
+}
+
+/*
+* Checks shared by the settings file and the command line, the parsers below giving clap values it can
+* report errors about.
+*/
+fn check_resolution(width: u32, height: u32) -> Result<(u32, u32), String> {
+    match (width, height) {
+        (1 ..= MAX_RESOLUTION, 1 ..= MAX_RESOLUTION) => Ok((width, height)),
+        _                                            => Err(format!("{}x{} is not a resolution, both sides go from 1 to {}", width, height, MAX_RESOLUTION)),
+    }
+}
+
+fn check_ticks_per_second(ticks: u32) -> Result<u32, String> {
+    match ticks {
+        1 ..= MAX_TICKS_PER_SECOND => Ok(ticks),
+        _                          => Err(format!("{} ticks per second is not possible, go from 1 to {}", ticks, MAX_TICKS_PER_SECOND)),
+    }
+}
+
+fn check_render_scale(scale: f32) -> Result<f32, String> {
+    match scale {
+        _ if scale > 0.0 && scale <= 1.0 => Ok(scale),
+        _                                => Err(format!("{} is not a render scale, go from 0 (excluded) to 1", scale)),
+    }
+}
+
+fn check_field_of_view(degrees: f32) -> Result<f32, String> {
+    match degrees {
+        _ if degrees > 0.0 && degrees < 180.0 => Ok(degrees),
+        _                                     => Err(format!("{} degrees is not a field of view, go from 0 to 180 (both excluded)", degrees)),
+    }
+}
+
+fn check_max_reflections(reflections: u32) -> Result<u32, String> {
+    match reflections {
+        0 ..= MAX_REFLECTIONS => Ok(reflections),
+        _                     => Err(format!("{} reflections is too many, go from 0 to {}", reflections, MAX_REFLECTIONS)),
+    }
+}
+
+fn check_max_portals(portals: u32) -> Result<u32, String> {
+    match portals {
+        0 ..= MAX_PORTALS => Ok(portals),
+        _                 => Err(format!("{} portals is too many, go from 0 to {}", portals, MAX_PORTALS)),
+    }
+}
+
+fn check_mouse_sensitivity(degrees: f32) -> Result<f32, String> {
+    match degrees {
+        _ if degrees >= 0.0 && degrees.is_finite() => Ok(degrees),
+        _                                          => Err(format!("{} is not a mouse sensitivity, use 0 or more", degrees)),
+    }
+}
+
+fn check_deadzone(deadzone: f32) -> Result<f32, String> {
+    match deadzone {
+        _ if (0.0 .. 1.0).contains(&deadzone) => Ok(deadzone),
+        _                                     => Err(format!("{} is not a deadzone, go from 0 to 1 (excluded)", deadzone)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("{:?} is not a number", text))
+}
+
+fn parse_resolution(text: &str) -> Result<(u32, u32), String> {
+    let (width, height) = text.split_once('x').ok_or_else(|| format!("{:?} is not a resolution, write it like 800x600", text))?;
+
+    check_resolution(parse_number(width)?, parse_number(height)?)
+}
+
+fn parse_ticks_per_second(text: &str) -> Result<u32, String> {
+    check_ticks_per_second(parse_number(text)?)
+}
+
+fn parse_render_scale(text: &str) -> Result<f32, String> {
+    check_render_scale(parse_number(text)?)
+}
+
+fn parse_field_of_view(text: &str) -> Result<f32, String> {
+    check_field_of_view(parse_number(text)?)
+}
+
+fn parse_max_reflections(text: &str) -> Result<u32, String> {
+    check_max_reflections(parse_number(text)?)
+}
+
+fn parse_max_portals(text: &str) -> Result<u32, String> {
+    check_max_portals(parse_number(text)?)
+}
+
+fn parse_mouse_sensitivity(text: &str) -> Result<f32, String> {
+    check_mouse_sensitivity(parse_number(text)?)
+}
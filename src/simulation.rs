@@ -0,0 +1,289 @@
+use std::{collections::HashMap, f32::consts::{PI, TAU}};
+
+use crate::{systems, camera::Camera, random::Random, weapon::{self, Trigger}, entity::{Arsenal, Collider, Entities, Entity, Health, Transform}, level::{Level, LevelSource}, path::Pathfinder, sprite::Sprite, world::{self, WorldAngle, WorldLength, WorldPosition, WorldVector}};
+
+pub const DEFAULT_TICKS_PER_SECOND:  u32 = 60;
+// Past this many ticks in a single frame, the simulation gives up catching up and runs slower than real time.
+pub const DEFAULT_MAX_CATCH_UP_TICKS: u32 = 5;
+
+// In tiles per second.
+const MOVE_SPEED:                f32         = 3.0;
+// In radians per second.
+const TURN_SPEED:                f32         = 2.5;
+// In screen heights per second.
+const LOOK_SPEED:                f32         = 1.0;
+const PLAYER_RADIUS:             f32         = 0.2;
+const PLAYER_HEALTH:             f32         = 100.0;
+// Anything moving further than this in one tick went through a portal: it is not interpolated, it jumps.
+const MAX_INTERPOLATED_DISTANCE: WorldLength = 1.0;
+
+/*
+* What the player asks for during one tick. Every axis goes from -1 to 1: forward (or backward), to the
+* right (or left), turning clockwise (or counterclockwise), looking up (or down). The mouse can turn faster
+* than keys, going past 1. Weapons fire for as long as `fire` is held, see weapon.rs.
+*/
+#[derive(PartialEq, Clone, Copy, Default, Debug)]
+pub struct TickInput {
+    pub forward:       f32,
+    pub sideways:      f32,
+    pub turn:          f32,
+    pub look_up:       f32,
+    pub fire:          bool,
+    // Weapons to go forward (or backward) by.
+    pub switch_weapon: i32,
+}
+
+/*
+* Everything that happens in the world, advanced one fixed-length tick at a time. Nothing here depends on
+* ggez or on the frame rate, so that the game can be stepped from tests, replays or a server alike.
+*
+* Anything random must come from `random`: a simulation started from the same level with the same seed, and
+* stepped with the same inputs, goes through the exact same states (see replay.rs).
+*
+* The state of the previous tick is kept, so that frames falling between two ticks can be drawn in between.
+*/
+pub struct Simulation {
+    pub level:              Box<dyn Level>,
+    pub camera:             Camera,
+    pub entities:           Entities,
+    pub player:             Entity,
+    pub random:             Random,
+    // Paths are kept between ticks, not saved: they are found again as needed.
+    pub pathfinder:         Pathfinder,
+        level_source:       LevelSource,
+        seed:               u64,
+        tick_seconds:       f32,
+        tick:               u64,
+        previous_camera:    Camera,
+        previous_positions: HashMap<Entity, WorldPosition>,
+}
+
+/*
+* Turns the irregular time between frames into a whole number of fixed-length ticks, the remainder being
+* carried over to the next frame. When frames take too long (or the game was paused by the window being
+* dragged around), catching up would make frames longer still: ticks are capped, and the time they would
+* have covered is dropped.
+*/
+pub struct FixedTimestep {
+    tick_seconds:       f32,
+    max_catch_up_ticks: u32,
+    accumulated:        f32,
+}
+
+impl Simulation {
+    /*
+    * `entities` are those of the level, the player is added. The camera starts where the level says. The
+    * source of the level is kept for saves.
+    */
+    pub fn new(level_source: LevelSource, level: Box<dyn Level>, mut entities: Entities, ticks_per_second: u32, seed: u64) -> Simulation {
+        assert!(ticks_per_second > 0, "The simulation needs at least one tick per second!");
+
+        let camera = Camera::new(level.find_spawn(), PI / 4.0);
+        let player = entities.spawn();
+
+        entities.transforms.insert(player, Transform { position: camera.position, angle: camera.get_rotation() });
+        entities.colliders.insert(player, Collider { radius: PLAYER_RADIUS, is_solid: true });
+        entities.healths.insert(player, Health::new(PLAYER_HEALTH));
+        entities.arsenals.insert(player, Arsenal::new());
+
+        Simulation {
+            level,
+            player,
+            entities,
+            level_source,
+            seed,
+
+            random:             Random::new(seed),
+            pathfinder:         Pathfinder::new(),
+            tick_seconds:       1.0 / (ticks_per_second as f32),
+            tick:               0,
+            previous_camera:    camera.clone(),
+            previous_positions: HashMap::new(),
+            camera,
+        }
+    }
+
+    pub fn step(&mut self, input: &TickInput) {
+        let seconds = self.tick_seconds;
+
+        self.previous_camera    = self.camera.clone();
+        self.previous_positions = self.entities.transforms.iter().map(|(entity, transform)| (entity, transform.position)).collect();
+
+        self.pathfinder.advance();
+
+        self.camera.rotate_clockwise(input.turn * TURN_SPEED * seconds);
+        self.camera.look_up(input.look_up * LOOK_SPEED * seconds);
+        self.move_player(input.forward * MOVE_SPEED * seconds, input.sideways * MOVE_SPEED * seconds);
+        self.update_entities(Trigger { is_firing: input.fire, switch: input.switch_weapon }, seconds);
+
+        self.tick += 1;
+    }
+
+    // Ticks stepped since the start.
+    pub fn get_tick(&self) -> u64 {
+        self.tick
+    }
+
+    // How far a turn of 1 turns in a tick.
+    pub fn get_turn_per_tick(&self) -> WorldAngle {
+        TURN_SPEED * self.tick_seconds
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn get_level_source(&self) -> &LevelSource {
+        &self.level_source
+    }
+
+    // Puts the simulation at `tick`, when loading a save for instance. Nothing is interpolated from before.
+    pub fn restore_tick(&mut self, tick: u64) {
+        self.tick               = tick;
+        self.previous_camera    = self.camera.clone();
+        self.previous_positions = HashMap::new();
+    }
+
+    /*
+    * FNV-1a hash of what the player can notice: the tick, the camera, the random generator, and where every
+    * entity is and how healthy. Floats are hashed bit for bit, so that the smallest divergence shows.
+    */
+    pub fn get_checksum(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+        let mut add = |value: u64| {
+            for byte in value.to_le_bytes() {
+                hash = (hash ^ (byte as u64)).wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        };
+
+        add(self.tick);
+        add(self.random.get_state());
+        add(self.camera.position.x.to_bits() as u64);
+        add(self.camera.position.y.to_bits() as u64);
+        add(self.camera.get_rotation().to_bits() as u64);
+        add(self.camera.get_pitch().to_bits() as u64);
+
+        for (entity, transform) in self.entities.transforms.iter() {
+            add(entity.get_index() as u64);
+            add(transform.position.x.to_bits() as u64);
+            add(transform.position.y.to_bits() as u64);
+            add(transform.angle.to_bits() as u64);
+        }
+
+        for (entity, health) in self.entities.healths.iter() {
+            add(entity.get_index() as u64);
+            add(health.current.to_bits() as u64);
+        }
+
+        hash
+    }
+
+    /*
+    * The camera `alpha` of the way from the previous tick to the current one. Angles go the short way round.
+    * The pitch is not interpolated, it is cheap to change and looks fine either way.
+    */
+    pub fn get_interpolated_camera(&self, alpha: f32) -> Camera {
+        let mut camera = self.camera.clone();
+
+        if !should_interpolate(self.previous_camera.position, self.camera.position) {
+            return camera;
+        }
+
+        let turn = get_angle_difference(self.previous_camera.get_rotation(), self.camera.get_rotation());
+
+        camera.position = self.previous_camera.position.lerp(self.camera.position, alpha);
+        camera.rotate_clockwise(-turn * (1.0 - alpha));
+
+        camera
+    }
+
+    // What the sprite pass draws, with entities `alpha` of the way from the previous tick to the current one.
+    pub fn get_interpolated_sprites(&self, alpha: f32) -> Vec<Sprite> {
+        let mut sprites = self.entities.get_entity_sprites();
+
+        for (entity, sprite) in sprites.iter_mut() {
+            if let Some(previous) = self.previous_positions.get(entity) {
+                if should_interpolate(*previous, sprite.position) {
+                    sprite.position = previous.lerp(sprite.position, alpha);
+                }
+            }
+        }
+
+        sprites.into_iter().map(|(_, sprite)| sprite).collect()
+    }
+
+    // Forward and sideways (towards the right) are relative to where the player looks.
+    fn move_player(&mut self, forward: f32, sideways: f32) {
+        let view_direction  = self.camera.get_view_direction();
+        let right_direction = world::rotate_clockwise(view_direction, std::f32::consts::FRAC_PI_2);
+        let motion          = view_direction * forward + right_direction * sideways;
+
+        if motion == WorldVector::ZERO {
+            return;
+        }
+
+        // Portals can turn the player around.
+        let movement        = self.level.move_through(self.camera.position, motion, PLAYER_RADIUS);
+
+        self.camera.position = movement.position;
+        self.camera.rotate_clockwise(movement.rotation);
+    }
+
+    /*
+    * The camera is where the player is: systems see where it went, and may push it around. The player shoots
+    * where the camera looks, before anything else moves.
+    */
+    fn update_entities(&mut self, trigger: Trigger, seconds: f32) {
+        if let Some(transform) = self.entities.transforms.get_mut(self.player) {
+            transform.position = self.camera.position;
+            transform.angle    = self.camera.get_rotation();
+        }
+
+        weapon::run_arsenal(&mut self.entities, self.level.as_ref(), &mut self.random, self.player, trigger, seconds);
+        systems::run_systems(&mut self.entities, self.level.as_ref(), &mut self.pathfinder, self.player, seconds);
+
+        if let Some(transform) = self.entities.transforms.get_mut(self.player) {
+            self.camera.position = transform.position;
+            transform.angle      = self.camera.get_rotation();
+        }
+    }
+}
+
+impl FixedTimestep {
+    pub fn new(ticks_per_second: u32, max_catch_up_ticks: u32) -> FixedTimestep {
+        FixedTimestep { tick_seconds: 1.0 / (ticks_per_second as f32), max_catch_up_ticks, accumulated: 0.0 }
+    }
+
+    // How many ticks to run for a frame that came `elapsed_seconds` after the previous one.
+    pub fn advance(&mut self, elapsed_seconds: f32) -> u32 {
+        self.accumulated += elapsed_seconds.max(0.0);
+
+        let ticks = (self.accumulated / self.tick_seconds) as u32;
+
+        if ticks > self.max_catch_up_ticks {
+            self.accumulated = 0.0;
+
+            return self.max_catch_up_ticks;
+        }
+
+        self.accumulated -= (ticks as f32) * self.tick_seconds;
+
+        ticks
+    }
+
+    // How far the frame is between the last tick and the next one, from 0 to 1.
+    pub fn get_alpha(&self) -> f32 {
+        num::clamp(self.accumulated / self.tick_seconds, 0.0, 1.0)
+    }
+}
+
+// Anything that moved further went through a portal.
+pub fn should_interpolate(previous: WorldPosition, current: WorldPosition) -> bool {
+    previous.distance(current) <= MAX_INTERPOLATED_DISTANCE
+}
+
+// Clockwise turn from `from` to `to`, between -π and π.
+fn get_angle_difference(from: WorldAngle, to: WorldAngle) -> WorldAngle {
+    (to - from + PI).rem_euclid(TAU) - PI
+}
@@ -0,0 +1,107 @@
+use std::f32::consts::TAU;
+
+use ggez::graphics::Color;
+
+use crate::{texture::{self, Texture, TextureCoordinate}, world::WorldDirection};
+
+const SKY_TEXTURE_WIDTH:    u32 = 1024;
+const SKY_TEXTURE_HEIGHT:   u32 = 256;
+const CLOUD_NOISE_PERIOD:   u32 = 16;
+
+/*
+* A layer of the panoramic sky. The whole texture covers one full turn (or `horizontal_repeats` turns),
+* from the horizon at its bottom to one screen height above the horizon at its top.
+*
+* Parallax comes from layers moving at different speeds: closer clouds repeat more often around
+* the player, move further when looking up or down, and drift with the wind.
+*/
+pub struct SkyLayer {
+    pub texture:            Texture,
+    pub horizontal_repeats: f32,
+    pub elevation_scale:    f32,
+    pub drift_speed:        f32,
+        drift:              TextureCoordinate,
+}
+
+// Layers are composited back to front, the first layer should be opaque.
+pub struct Sky {
+    pub layers: Vec<SkyLayer>,
+}
+
+impl SkyLayer {
+    // Keep `horizontal_repeats` a whole number, otherwise a seam appears where the angle wraps around.
+    pub fn new(texture: Texture, horizontal_repeats: f32, elevation_scale: f32, drift_speed: f32) -> SkyLayer {
+        SkyLayer { texture, horizontal_repeats, elevation_scale, drift_speed, drift: 0.0 }
+    }
+}
+
+impl Sky {
+    pub fn make_demo_sky() -> Sky {
+        Sky {
+            layers: vec![
+                SkyLayer::new(make_gradient_texture(), 1.0, 1.0, 0.0),
+                SkyLayer::new(make_cloud_texture(1, 0.45, 0.8), 2.0, 1.2, 0.004),
+                SkyLayer::new(make_cloud_texture(2, 0.5,  0.9), 3.0, 1.5, 0.01),
+            ],
+        }
+    }
+
+    // Makes clouds drift, in seconds.
+    pub fn update(&mut self, elapsed_seconds: f32) {
+        for layer in self.layers.iter_mut() {
+            layer.drift = (layer.drift + layer.drift_speed * elapsed_seconds).rem_euclid(1.0);
+        }
+    }
+
+    /*
+    * `ray_direction` gives the horizontal position in the panorama, and `height_above_horizon`
+    * (in screen heights, so that it does not depend on the resolution) the vertical one. The horizon
+    * moves with the camera pitch, and the sky with it.
+    */
+    pub fn sample(&self, ray_direction: WorldDirection, height_above_horizon: f32) -> Color {
+        // Clockwise angle from the top of the map, see world.rs. It wraps at 2π by construction.
+        let angle = ray_direction.x.atan2(-ray_direction.y).rem_euclid(TAU);
+
+        self.layers.iter().fold(Color::BLACK, |color, layer| {
+            let u = angle / TAU * layer.horizontal_repeats + layer.drift;
+            let v = 1.0 - height_above_horizon * layer.elevation_scale;
+
+            texture::blend(color, layer.texture.sample(u, v))
+        })
+    }
+}
+
+fn make_gradient_texture() -> Texture {
+    let zenith  = Color::new(0.10, 0.25, 0.60, 1.0);
+    let horizon = Color::new(0.65, 0.80, 0.95, 1.0);
+
+    Texture::from_fn(1, SKY_TEXTURE_HEIGHT, |_, y| {
+        let ratio = (y as f32) / ((SKY_TEXTURE_HEIGHT - 1) as f32);
+
+        Color::new(
+            zenith.r + (horizon.r - zenith.r) * ratio,
+            zenith.g + (horizon.g - zenith.g) * ratio,
+            zenith.b + (horizon.b - zenith.b) * ratio,
+            1.0,
+        )
+    })
+}
+
+/*
+* White clouds whose opacity is given by two octaves of noise. Only the densest parts of the noise
+* (above `coverage`) become clouds, and they fade out right above the horizon.
+*/
+fn make_cloud_texture(seed: u32, coverage: f32, max_opacity: f32) -> Texture {
+    Texture::from_fn(SKY_TEXTURE_WIDTH, SKY_TEXTURE_HEIGHT, |x, y| {
+        let noise_x = (x as f32) / (SKY_TEXTURE_WIDTH  as f32) * (CLOUD_NOISE_PERIOD as f32);
+        let noise_y = (y as f32) / (SKY_TEXTURE_HEIGHT as f32) * (CLOUD_NOISE_PERIOD as f32) / 4.0;
+
+        let density = 0.65 * texture::periodic_value_noise(noise_x,       noise_y,       CLOUD_NOISE_PERIOD,     seed)
+                    + 0.35 * texture::periodic_value_noise(noise_x * 2.0, noise_y * 2.0, CLOUD_NOISE_PERIOD * 2, seed);
+
+        let opacity = num::clamp((density - coverage) / (1.0 - coverage), 0.0, 1.0);
+        let fade    = num::clamp((1.0 - (y as f32) / (SKY_TEXTURE_HEIGHT as f32)) * 4.0, 0.0, 1.0);
+
+        Color::new(1.0, 1.0, 1.0, opacity * fade * max_opacity)
+    })
+}
@@ -0,0 +1,147 @@
+use ggez::{event::{Button, KeyCode}, graphics::{Color, DrawParam, Drawable, Text}};
+
+use crate::{input::Binding, save::{SaveSlotInfo, SaveSlots}};
+
+const MARGIN:      f32 = 24.0;
+const LINE_HEIGHT: f32 = 24.0;
+const NAME_WIDTH:  f32 = 200.0;
+
+/*
+* Lists the save slots, most recent first. Up and down pick a slot, Return loads it, Delete deletes it and
+* Escape leaves. On gamepads, the D-pad picks, South loads, West deletes and East leaves.
+*
+* Like on the rebinding screen, these are not bindings.
+*/
+pub struct SlotScreen {
+    slots:    Vec<SaveSlotInfo>,
+    selected: usize,
+    // Why the list is wrong, or a slot is still there.
+    error:    Option<String>,
+}
+
+// What a press asks the game for.
+pub enum SlotScreenOutcome {
+    Stay,
+    Leave,
+    Load(String),
+}
+
+impl SlotScreen {
+    pub fn new(saves: &SaveSlots) -> SlotScreen {
+        let mut screen = SlotScreen { slots: vec![], selected: 0, error: None };
+
+        screen.refresh(saves);
+
+        screen
+    }
+
+    pub fn press(&mut self, binding: Binding, saves: &SaveSlots) -> SlotScreenOutcome {
+        let count = self.slots.len().max(1);
+
+        match binding {
+            Binding::Key(KeyCode::Up)     | Binding::Button(Button::DPadUp)   => self.selected = (self.selected + count - 1) % count,
+            Binding::Key(KeyCode::Down)   | Binding::Button(Button::DPadDown) => self.selected = (self.selected + 1) % count,
+            Binding::Key(KeyCode::Delete) | Binding::Button(Button::West)     => self.delete_selected(saves),
+            Binding::Key(KeyCode::Escape) | Binding::Button(Button::East)     => return SlotScreenOutcome::Leave,
+            Binding::Key(KeyCode::Return) | Binding::Button(Button::South)    => {
+                if let Some(slot) = self.slots.get(self.selected) {
+                    return SlotScreenOutcome::Load(slot.name.clone());
+                }
+            },
+            _                                                                 => (),
+        }
+
+        SlotScreenOutcome::Stay
+    }
+
+    fn delete_selected(&mut self, saves: &SaveSlots) {
+        let result = match self.slots.get(self.selected) {
+            Some(slot) => saves.delete(&slot.name),
+            None       => return,
+        };
+
+        self.refresh(saves);
+
+        if let Err(error) = result {
+            self.error = Some(format!("Could not delete: {}", error));
+        }
+    }
+
+    // The picked line stays where it was, or goes up to the last one.
+    fn refresh(&mut self, saves: &SaveSlots) {
+        match saves.list() {
+            Ok(slots)  => {
+                self.slots = slots;
+                self.error = None;
+            },
+            Err(error) => {
+                self.slots = vec![];
+                self.error = Some(format!("Could not list the saves: {}", error));
+            },
+        }
+
+        self.selected = self.selected.min(self.slots.len().saturating_sub(1));
+    }
+
+    pub fn draw(&self, context: &mut ggez::Context) -> ggez::GameResult {
+        let help = "Up and down pick, Return loads, Delete deletes, Escape leaves.";
+
+        Text::new(help).draw(context, DrawParam::new().dest([MARGIN, MARGIN]).color(Color::WHITE))?;
+
+        if let Some(error) = &self.error {
+            Text::new(error.as_str()).draw(context, DrawParam::new().dest([MARGIN, MARGIN + LINE_HEIGHT]).color(Color::RED))?;
+        } else if self.slots.is_empty() {
+            Text::new("There are no saves.").draw(context, DrawParam::new().dest([MARGIN, MARGIN + LINE_HEIGHT]).color(Color::WHITE))?;
+        }
+
+        // Like actions on the rebinding screen, slots that do not fit come up as they are picked.
+        let lines = ((ggez::graphics::screen_coordinates(context).h - MARGIN) / LINE_HEIGHT) as usize;
+        let shown = lines.saturating_sub(3).max(1);
+        let first = (self.selected + 1).saturating_sub(shown);
+
+        for (index, slot) in self.slots.iter().enumerate().skip(first).take(shown) {
+            let y     = MARGIN + LINE_HEIGHT * ((index - first + 3) as f32);
+            let color = if index == self.selected { Color::YELLOW } else { Color::WHITE };
+            let age   = slot.get_minutes_old().map_or("?".to_owned(), |minutes| minutes.to_string());
+
+            Text::new(slot.name.as_str()).draw(context, DrawParam::new().dest([MARGIN, y]).color(color))?;
+            Text::new(format!("saved {} minutes ago", age)).draw(context, DrawParam::new().dest([MARGIN + NAME_WIDTH, y]).color(color))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use ggez::event::KeyCode;
+
+    use crate::{input::Binding, save::SaveSlots};
+
+    use super::{SlotScreen, SlotScreenOutcome};
+
+    // Slots are only listed, what is in the files does not matter.
+    #[test]
+    fn deleted_slots_leave_the_list() {
+        let directory = env::temp_dir().join("slot-screen-deleted-slots");
+        let saves     = SaveSlots::new(&directory);
+
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("only.toml"), "").unwrap();
+
+        let mut screen = SlotScreen::new(&saves);
+
+        assert!(matches!(screen.press(Binding::Key(KeyCode::Return), &saves), SlotScreenOutcome::Load(slot) if slot == "only"));
+        assert!(matches!(screen.press(Binding::Key(KeyCode::Delete), &saves), SlotScreenOutcome::Stay));
+
+        let is_deleted = !directory.join("only.toml").exists();
+
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(is_deleted);
+        assert!(screen.error.is_none());
+        assert!(matches!(screen.press(Binding::Key(KeyCode::Return), &saves), SlotScreenOutcome::Stay));
+    }
+}
@@ -0,0 +1,114 @@
+use std::f32::consts::TAU;
+
+use crate::{texture::{self, Texture, TextureCoordinate}, world::{WorldAngle, WorldDirection, WorldLength, WorldPosition}};
+
+// Index into the sprite textures of the raycaster.
+pub type SpriteTextureId = usize;
+
+pub const BARREL_TEXTURE:     SpriteTextureId = 0;
+pub const GRUNT_TEXTURE:      SpriteTextureId = 1;
+pub const MEDKIT_TEXTURE:     SpriteTextureId = 2;
+pub const GRUNT_DEAD_TEXTURE: SpriteTextureId = 3;
+pub const PLASMA_TEXTURE:     SpriteTextureId = 4;
+pub const SHELLS_TEXTURE:     SpriteTextureId = 5;
+
+// Frames of the grunt, all the way round.
+const GRUNT_DIRECTIONS: u32 = 8;
+
+/*
+* Billboard standing on the floor: a flat picture that always faces the ray looking at it. Its size is in
+* world units, 1 being the size of a tile (and the height of a wall).
+*
+* Textures with several frames look different depending on where they are seen from, see `get_frame`. The
+* angle is where the sprite faces, clockwise from the top of the map.
+*/
+#[derive(Clone, Copy, Debug)]
+pub struct Sprite {
+    pub position: WorldPosition,
+    pub texture:  SpriteTextureId,
+    pub width:    WorldLength,
+    pub height:   WorldLength,
+    pub angle:    WorldAngle,
+}
+
+impl Sprite {
+    pub fn new(position: WorldPosition, texture: SpriteTextureId, width: WorldLength, height: WorldLength, angle: WorldAngle) -> Sprite {
+        Sprite { position, texture, width, height, angle }
+    }
+
+    /*
+    * Frames go clockwise around the sprite, seen from above, frame 0 showing its front. The frame shown is the
+    * one closest to where `viewer` stands. Reflections and portals see the sprite from where their ray comes
+    * from, so that they show its other sides.
+    */
+    pub fn get_frame(&self, viewer: WorldPosition, frame_count: usize) -> usize {
+        if frame_count <= 1 {
+            return 0;
+        }
+
+        let to_viewer = viewer - self.position;
+        let around    = (to_viewer.x.atan2(-to_viewer.y) - self.angle).rem_euclid(TAU);
+
+        ((around / TAU * (frame_count as f32)).round() as usize) % frame_count
+    }
+
+    /*
+    * The billboard is perpendicular to the ray and goes through the sprite position, so the ray meets its
+    * plane at the projection of the sprite position onto the (normalized) direction:
+    *   distance = (position - origin) · direction
+    *
+    * How far from the middle of the sprite the ray goes through is the distance between the sprite position
+    * and the ray, which the 2D cross product gives, signed: positive to the right of the ray.
+    *
+    * Returns the distance along the ray and the horizontal texture coordinate. In a mirror, consecutive rays
+    * sweep the other way round, which mirrors the sprite, as it should.
+    */
+    pub fn intersect_ray(&self, origin: WorldPosition, direction: WorldDirection) -> Option<(WorldLength, TextureCoordinate)> {
+        let to_sprite = self.position - origin;
+        let distance  = to_sprite.dot(direction);
+        let offset    = direction.perp_dot(to_sprite);
+
+        if distance <= 0.0 || offset.abs() > self.width / 2.0 {
+            return None;
+        }
+
+        Some((distance, 0.5 - offset / self.width))
+    }
+}
+
+// Frames of every texture, ordered by texture ID.
+pub fn make_sprite_textures() -> Vec<Vec<Texture>> {
+    vec![
+        vec![texture::make_barrel_texture()],
+        (0 .. GRUNT_DIRECTIONS).map(|frame| texture::make_grunt_texture((frame as f32) / (GRUNT_DIRECTIONS as f32) * TAU)).collect(),
+        vec![texture::make_medkit_texture()],
+        vec![texture::make_dead_grunt_texture()],
+        vec![texture::make_plasma_texture()],
+        vec![texture::make_shells_texture()],
+    ]
+}
+
+// Names used by data files.
+pub fn get_sprite_texture_name(texture: SpriteTextureId) -> &'static str {
+    match texture {
+        BARREL_TEXTURE     => "barrel",
+        GRUNT_TEXTURE      => "grunt",
+        MEDKIT_TEXTURE     => "medkit",
+        GRUNT_DEAD_TEXTURE => "grunt_dead",
+        PLASMA_TEXTURE     => "plasma",
+        SHELLS_TEXTURE     => "shells",
+        _                  => panic!("Unknown sprite texture {}!", texture),
+    }
+}
+
+pub fn find_sprite_texture(name: &str) -> Option<SpriteTextureId> {
+    match name {
+        "barrel"     => Some(BARREL_TEXTURE),
+        "grunt"      => Some(GRUNT_TEXTURE),
+        "medkit"     => Some(MEDKIT_TEXTURE),
+        "grunt_dead" => Some(GRUNT_DEAD_TEXTURE),
+        "plasma"     => Some(PLASMA_TEXTURE),
+        "shells"     => Some(SHELLS_TEXTURE),
+        _            => None,
+    }
+}
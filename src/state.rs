@@ -1,54 +1,380 @@
-const TARGET_ROTATIONS_PER_SECOND: u32 = 10;
+use std::time::Instant;
 
-use ggez::graphics::{Image, DrawParam, Drawable};
+use ggez::{event::{Axis, Button, GamepadId, MouseButton}, input::keyboard::{KeyCode, KeyMods}, graphics::{Color, DrawParam, Drawable}};
 
-use crate::{raycaster, world::map_point_to_world_position, camera::Camera};
-use crate::map::Map;
+use crate::{font::Font, hud::{Hud, HudStatus}, profiler::{FrameProfile, ProfileRecorder, Profiler}, raycaster::{self, RecursionLimits}, rebind::RebindScreen, replay::ReplayRecorder, save::{self, SaveSlots}, settings::Settings, slots::{SlotScreen, SlotScreenOutcome}, upload::StreamingImage, input::{Action, Binding, Bindings, Input}, simulation::{self, FixedTimestep, Simulation, TickInput}, viewmodel::ViewModel, world::WorldAngle};
 
 pub struct State {
-    pub map:       Map,
-    pub camera:    Camera,
-    pub raycaster: raycaster::Raycaster,
+    pub simulation:       Simulation,
+    pub raycaster:        raycaster::Raycaster,
+        settings:         Settings,
+        input:            Input,
+        timestep:         FixedTimestep,
+        recorder:         Option<ReplayRecorder>,
+        profile_recorder: Option<ProfileRecorder>,
+        // The framebuffer on the GPU, made on the first frame.
+        screen:           Option<StreamingImage>,
+        saves:            SaveSlots,
+        // The game is paused while bindings are changed, or saves looked at.
+        rebind:           Option<RebindScreen>,
+        slot_screen:      Option<SlotScreen>,
+        // Turned by the mouse since the last tick.
+        mouse_turn:       WorldAngle,
+        // Weapons switched by since the last tick.
+        weapon_switch:    i32,
 }
 
 impl State {
-    pub fn new() -> Self {
-        let map   = Map::make_demo_map();
+    /*
+    * Tile maps and sector maps are played the same way. Ticks are written to `recorder`, if any, and what
+    * frames cost to `profile_recorder`.
+    */
+    pub fn new(simulation: Simulation, settings: &Settings, bindings: Bindings, font: Font, recorder: Option<ReplayRecorder>, profile_recorder: Option<ProfileRecorder>, saves: SaveSlots) -> Self {
+        let (width, height) = settings.get_render_size();
+        let mut raycaster   = raycaster::Raycaster::new(width, height);
 
-        let spawn = map.find_first_spawn();
+        raycaster.limits       = RecursionLimits { reflections: settings.renderer.max_reflections, portals: settings.renderer.max_portals };
+        raycaster.show_minimap = settings.renderer.minimap;
+        raycaster.view_model   = Some(ViewModel::new());
+        raycaster.profiler     = Some(Profiler::new());
+
+        if settings.renderer.hud {
+            let mut hud = Hud::new();
+
+            hud.show_fps  = settings.renderer.fps_counter;
+            hud.font      = font;
+            raycaster.hud = Some(hud);
+        }
 
         State {
-            map,
+            simulation,
+            raycaster,
+            recorder,
+            profile_recorder,
+            saves,
+
+            settings:         settings.clone(),
+            input:            Input::new(bindings, settings.controls.deadzone),
+            timestep:         FixedTimestep::new(settings.game.ticks_per_second, simulation::DEFAULT_MAX_CATCH_UP_TICKS),
+            screen:           None,
+            rebind:           None,
+            slot_screen:      None,
+            mouse_turn:       0.0,
+            weapon_switch:    0,
+        }
+    }
+
+    fn save(&mut self, slot: &str) {
+        match self.saves.save(slot, &self.simulation) {
+            Ok(_)      => self.show_message(&format!("Saved to slot {}.", slot)),
+            Err(error) => self.show_message(&format!("Could not save: {}", error)),
+        }
+    }
+
+    // A replay cannot go on from a loaded save, the recording stops there.
+    fn load(&mut self, slot: &str) {
+        match self.saves.load(slot, self.settings.game.ticks_per_second) {
+            Ok(simulation) => {
+                self.simulation = simulation;
+
+                if self.recorder.take().is_some() {
+                    self.show_message(&format!("Loaded slot {}, the replay stops before it.", slot));
+                } else {
+                    self.show_message(&format!("Loaded slot {}.", slot));
+                }
+            },
+            Err(error)     => self.show_message(&format!("Could not load: {}", error)),
+        }
+    }
+
+    // Messages wrap, so that errors can be told in full.
+    fn show_message(&mut self, message: &str) {
+        if let Some(hud) = self.raycaster.hud.as_mut() {
+            hud.show_message(message);
+        }
+    }
+
+    // A recording that cannot be written is given up on, the game goes on.
+    fn record(&mut self, input: &TickInput) {
+        let checksum = self.simulation.get_checksum();
+
+        if let Some(Err(error)) = self.recorder.as_mut().map(|recorder| recorder.record(input, checksum)) {
+            self.show_message(&format!("Could not write the replay, recording stopped: {}", error));
+
+            self.recorder = None;
+        }
+    }
+
+    fn read_input(&self) -> TickInput {
+        TickInput {
+            forward:       self.input.get_axis(Action::MoveForward, Action::MoveBackward),
+            sideways:      self.input.get_axis(Action::StrafeRight, Action::StrafeLeft),
+            turn:          self.input.get_axis(Action::TurnRight,   Action::TurnLeft),
+            look_up:       self.input.get_axis(Action::LookUp,      Action::LookDown),
+            fire:          self.input.get_amount(Action::Fire) > 0.0,
+            // Switches are counted as they are pressed, see `update`.
+            switch_weapon: 0,
+        }
+    }
+
+    // Something was just pressed: the open screen gets it, if any, otherwise the actions it is bound to happen.
+    fn on_press(&mut self, context: &mut ggez::Context, binding: Binding) {
+        if let Some(rebind) = self.rebind.as_mut() {
+            if !rebind.press(binding, &mut self.input.bindings) {
+                self.close_rebind_screen();
+            }
+
+            return;
+        }
 
-            raycaster: raycaster::Raycaster::new(),
-            camera:    Camera::new(map_point_to_world_position(spawn), std::f32::consts::PI / 4.0),
+        if let Some(slot_screen) = self.slot_screen.as_mut() {
+            match slot_screen.press(binding, &self.saves) {
+                SlotScreenOutcome::Stay       => (),
+                SlotScreenOutcome::Leave      => self.slot_screen = None,
+                SlotScreenOutcome::Load(slot) => {
+                    self.slot_screen = None;
+
+                    self.load(&slot);
+                },
+            }
+
+            return;
+        }
+
+        for action in self.input.bindings.get_actions(binding) {
+            self.trigger(context, action);
+        }
+    }
+
+    // Movement and firing are read continuously instead, see `read_input`, like `SaveToSlot`. Nothing can be used yet.
+    fn trigger(&mut self, context: &mut ggez::Context, action: Action) {
+        match action {
+            Action::NextWeapon     => self.weapon_switch += 1,
+            Action::PreviousWeapon => self.weapon_switch -= 1,
+            Action::QuickSave      => self.save(save::QUICK_SAVE),
+            Action::QuickLoad      => self.load(save::QUICK_SAVE),
+            Action::Slot1          => self.use_slot("1"),
+            Action::Slot2          => self.use_slot("2"),
+            Action::Slot3          => self.use_slot("3"),
+            Action::Slot4          => self.use_slot("4"),
+            Action::Saves          => self.slot_screen = Some(SlotScreen::new(&self.saves)),
+            Action::ToggleMap      => self.raycaster.show_minimap = !self.raycaster.show_minimap,
+            Action::ToggleProfiler => self.toggle_profiler(),
+            Action::Rebind         => self.rebind = Some(RebindScreen::new()),
+            Action::Quit           => ggez::event::quit(context),
+            _                      => (),
+        }
+    }
+
+    fn use_slot(&mut self, slot: &str) {
+        if self.input.get_amount(Action::SaveToSlot) > 0.0 {
+            self.save(slot);
+        } else {
+            self.load(slot);
+        }
+    }
+
+    fn toggle_profiler(&mut self) {
+        if let Some(profiler) = self.raycaster.profiler.as_mut() {
+            profiler.show_overlay = !profiler.show_overlay;
+        }
+    }
+
+    // Frames are profiled whether the overlay is shown or not. Lines are written as they come, like replays.
+    fn profile(&mut self, profile: FrameProfile) {
+        if let Some(mut profiler) = self.raycaster.profiler.take() {
+            profiler.add(profile, self.raycaster.get_ray_stats());
+
+            self.raycaster.profiler = Some(profiler);
+        }
+
+        if let Some(Err(error)) = self.profile_recorder.as_mut().map(|recorder| recorder.record(&profile).and_then(|_| recorder.flush())) {
+            self.show_message(&format!("Could not write the profile, profiling stopped: {}", error));
+
+            self.profile_recorder = None;
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.rebind.is_some() || self.slot_screen.is_some()
+    }
+
+    // Bindings are kept for the next games.
+    fn close_rebind_screen(&mut self) {
+        self.rebind = None;
+
+        if let Err(error) = self.input.bindings.write(&self.settings.controls.bindings) {
+            self.show_message(&format!("Could not save the bindings: {}", error));
         }
     }
 }
 
 impl ggez::event::EventHandler<ggez::GameError> for State {
+    // Quitting is an action like the others, rather than the default Escape.
+    fn key_down_event(&mut self, context: &mut ggez::Context, keycode: KeyCode, _keymods: KeyMods, _repeat: bool) {
+        if self.input.press(Binding::Key(keycode)) {
+            self.on_press(context, Binding::Key(keycode));
+        }
+    }
+
+    fn key_up_event(&mut self, _context: &mut ggez::Context, keycode: KeyCode, _keymods: KeyMods) {
+        self.input.release(Binding::Key(keycode));
+    }
+
+    fn mouse_button_down_event(&mut self, context: &mut ggez::Context, button: MouseButton, _x: f32, _y: f32) {
+        if self.input.press(Binding::Mouse(button)) {
+            self.on_press(context, Binding::Mouse(button));
+        }
+    }
+
+    fn mouse_button_up_event(&mut self, _context: &mut ggez::Context, button: MouseButton, _x: f32, _y: f32) {
+        self.input.release(Binding::Mouse(button));
+    }
+
+    // Moving the mouse to the right turns clockwise.
+    fn mouse_motion_event(&mut self, _context: &mut ggez::Context, _x: f32, _y: f32, dx: f32, _dy: f32) {
+        if !self.is_paused() {
+            self.mouse_turn += dx * self.settings.controls.mouse_sensitivity.to_radians();
+        }
+    }
+
+    fn gamepad_button_down_event(&mut self, context: &mut ggez::Context, button: Button, _id: GamepadId) {
+        if self.input.press(Binding::Button(button)) {
+            self.on_press(context, Binding::Button(button));
+        }
+    }
+
+    fn gamepad_button_up_event(&mut self, _context: &mut ggez::Context, button: Button, _id: GamepadId) {
+        self.input.release(Binding::Button(button));
+    }
+
+    fn gamepad_axis_event(&mut self, context: &mut ggez::Context, axis: Axis, value: f32, _id: GamepadId) {
+        if let Some(binding) = self.input.move_axis(axis, value) {
+            self.on_press(context, binding);
+        }
+    }
+
+    /*
+    * Input is read once per frame, every tick of the frame seeing the same. The mouse turn is shared out
+    * between the ticks, and kept for later when there are none, like weapon switches, which go to the first
+    * tick. The weapon bobs as fast as the player went.
+    */
     fn update(&mut self, context: &mut ggez::Context) -> ggez::GameResult {
-        while ggez::timer::check_update_time(context, TARGET_ROTATIONS_PER_SECOND) {
-            self.camera.rotate_clockwise(0.05 * 0.5);
+        let elapsed   = ggez::timer::delta(context).as_secs_f32();
+
+        if self.is_paused() {
+            return Ok(());
+        }
+
+        let ticks     = self.timestep.advance(elapsed);
+        let mut input = self.read_input();
+        let position  = self.simulation.camera.position;
+
+        if ticks > 0 {
+            input.turn          += self.mouse_turn / ((ticks as f32) * self.simulation.get_turn_per_tick());
+            input.switch_weapon  = self.weapon_switch;
+            self.mouse_turn      = 0.0;
+            self.weapon_switch   = 0;
+        }
+
+        for _ in 0 .. ticks {
+            self.simulation.step(&input);
+            self.record(&input);
+
+            input.switch_weapon = 0;
         }
 
-        std::thread::yield_now();
+        if let Some(Err(error)) = self.recorder.as_mut().map(ReplayRecorder::flush) {
+            self.show_message(&format!("Could not write the replay, recording stopped: {}", error));
+
+            self.recorder = None;
+        }
+
+        self.raycaster.sky.update(elapsed);
+
+        let walked  = self.simulation.camera.position.distance(position);
+        let arsenal = self.simulation.entities.arsenals.get(self.simulation.player);
+
+        if let Some(view_model) = self.raycaster.view_model.as_mut() {
+            // Going through a portal is not walking.
+            view_model.update(if simulation::should_interpolate(position, self.simulation.camera.position) { walked } else { 0.0 }, elapsed);
+            view_model.weapon = arsenal.map(|arsenal| arsenal.selected);
+            view_model.recoil = arsenal.map_or(0.0, |arsenal| arsenal.get_recoil());
+        }
+
+        if let Some(hud) = self.raycaster.hud.as_mut() {
+            hud.update(elapsed);
+
+            hud.status = HudStatus {
+                health: self.simulation.entities.healths.get(self.simulation.player).copied(),
+                weapon: arsenal.map(|arsenal| (arsenal.selected, arsenal.ammo[arsenal.selected])),
+            };
+        }
 
         Ok(())
     }
 
+    /*
+    * Frames usually fall between two ticks, what moves is drawn in between. The framebuffer is stretched to the
+    * window, pixels staying square-edged. The profiler overlay shows what the frames before this one cost.
+    */
     fn draw(&mut self, context: &mut ggez::Context) -> ggez::GameResult {
-        let image = Image::from_rgba8(
-            context,
-            raycaster::SCREEN_WIDTH,
-            raycaster::SCREEN_HEIGHT,
-            self.raycaster.update_framebuffer(&self.map, &self.camera)
-        )?;
+        if let Some(rebind) = &self.rebind {
+            ggez::graphics::clear(context, Color::BLACK);
+
+            rebind.draw(context, &self.input.bindings)?;
+
+            return ggez::graphics::present(context);
+        }
+
+        if let Some(slot_screen) = &self.slot_screen {
+            ggez::graphics::clear(context, Color::BLACK);
 
-        image.draw(context, DrawParam::new())?;
+            slot_screen.draw(context)?;
+
+            return ggez::graphics::present(context);
+        }
+
+        let alpha      = self.timestep.get_alpha();
+        let mut camera = self.simulation.get_interpolated_camera(alpha);
+        let sprites    = self.simulation.get_interpolated_sprites(alpha);
+        let width      = self.raycaster.get_width();
+        let height     = self.raycaster.get_height();
+
+        camera.set_field_of_view(self.settings.renderer.field_of_view.to_radians());
+
+        if self.settings.renderer.debug_paths {
+            self.raycaster.debug_paths = self.simulation.pathfinder.get_current_paths().map(<[_]>::to_vec).collect();
+        }
+
+        let render_start = Instant::now();
+        let dirty_rows   = self.raycaster.update_framebuffer(self.simulation.level.as_ref(), &camera, &sprites);
+        let render_time  = render_start.elapsed();
+
+        // Only the rows that changed are uploaded.
+        let upload_start = Instant::now();
+        let framebuffer  = self.raycaster.get_framebuffer();
+        let image        = match self.screen.take() {
+            Some(mut image) => {
+                image.update(context, framebuffer, dirty_rows)?;
+
+                image
+            },
+            None            => StreamingImage::new(context, width, height, framebuffer)?,
+        };
+        let upload_time  = upload_start.elapsed();
+
+        let screen       = ggez::graphics::screen_coordinates(context);
+        let scale        = [screen.w / (width as f32), screen.h / (height as f32)];
+
+        image.get_image().draw(context, DrawParam::new().scale(scale))?;
+
+        self.screen = Some(image);
 
         ggez::graphics::present(context)?;
 
+        self.profile(FrameProfile::new(ggez::timer::delta(context).as_secs_f32(), render_time.as_secs_f32(), upload_time.as_secs_f32(), self.raycaster.get_ray_stats()));
+
         Ok(())
     }
 }
@@ -0,0 +1,149 @@
+use crate::{ai, weapon::{self, WEAPONS}, entity::{AiMode, Entities, Entity}, level::Level, path::Pathfinder, world::{WorldLength, WorldVector}};
+
+// Colliders closer than this to something are touching it.
+pub const TOUCH_MARGIN: WorldLength = 0.05;
+// How close the player must get to a pickup without a collider.
+const PICKUP_DISTANCE:  WorldLength = 0.5;
+
+/*
+* One fixed-length step of the simulation, `seconds` long. The player is an entity like the others, whose
+* transform the caller keeps in sync with the camera.
+*/
+pub fn run_systems(entities: &mut Entities, level: &dyn Level, pathfinder: &mut Pathfinder, player: Entity, seconds: f32) {
+    ai::run_ai(entities, level, pathfinder, player, seconds);
+    weapon::run_projectiles(entities, level, seconds);
+    run_collisions(entities, level, player);
+    run_touch_damage(entities, level, seconds);
+    run_pickups(entities, player);
+    run_deaths(entities, player);
+}
+
+/*
+* Solid colliders push the player and enemies out of them. Walls are not pushed through: the push is a move
+* like any other.
+*/
+fn run_collisions(entities: &mut Entities, level: &dyn Level, player: Entity) {
+    let solids: Vec<_> = entities
+    .colliders
+    .iter()
+    .filter(|(_, collider)| collider.is_solid)
+    .filter_map(|(entity, collider)| entities.transforms.get(entity).map(|transform| (entity, transform.position, collider.radius)))
+    .collect();
+
+    let movers: Vec<_> = std::iter::once(player).chain(entities.ais.iter().map(|(entity, _)| entity)).collect();
+
+    for mover in movers {
+        let radius = match entities.colliders.get(mover) {
+            Some(collider) => collider.radius,
+            None           => continue,
+        };
+
+        for (solid, solid_position, solid_radius) in &solids {
+            let transform = match entities.transforms.get_mut(mover) {
+                Some(transform) if *solid != mover => transform,
+                _                                  => continue,
+            };
+
+            let away    = transform.position - *solid_position;
+            let overlap = radius + solid_radius - away.length();
+
+            if overlap <= 0.0 {
+                continue;
+            }
+
+            // Right on top of each other, any way out will do.
+            let direction = if away == WorldVector::ZERO { WorldVector::X } else { away.normalize() };
+            let movement  = level.move_through(transform.position, direction * overlap, radius);
+
+            transform.position  = movement.position;
+            transform.angle    += movement.rotation;
+        }
+    }
+}
+
+// Materials with `damage_on_touch` hurt whatever touches them, looking around in the four main directions.
+fn run_touch_damage(entities: &mut Entities, level: &dyn Level, seconds: f32) {
+    for (entity, health) in entities.healths.iter_mut() {
+        let (position, radius) = match (entities.transforms.get(entity), entities.colliders.get(entity)) {
+            (Some(transform), Some(collider)) => (transform.position, collider.radius),
+            _                                 => continue,
+        };
+
+        let damage = [WorldVector::X, WorldVector::Y, -WorldVector::X, -WorldVector::Y]
+        .iter()
+        .filter_map(|direction| level.trace_ray(position, *direction, radius + TOUCH_MARGIN, &|tile| tile.get_damage_on_touch() > 0.0))
+        .map(|hit| hit.tile.get_damage_on_touch())
+        .fold(0.0, f32::max);
+
+        health.current -= damage * seconds;
+    }
+}
+
+// Pickups are only taken when they are of use: health when hurt, ammo when not carrying as much as can be.
+fn run_pickups(entities: &mut Entities, player: Entity) {
+    let (position, radius) = match (entities.transforms.get(player), entities.colliders.get(player)) {
+        (Some(transform), collider) => (transform.position, collider.map_or(0.0, |collider| collider.radius)),
+        (None, _)                   => return,
+    };
+
+    let mut taken = vec![];
+
+    for (entity, pickup) in entities.pickups.iter() {
+        let reach    = radius + entities.colliders.get(entity).map_or(PICKUP_DISTANCE, |collider| collider.radius);
+        let in_reach = entities.transforms.get(entity).is_some_and(|transform| transform.position.distance(position) <= reach);
+
+        let heals    = pickup.heal > 0.0 && entities.healths.get(player).is_some_and(|health| health.current < health.maximum);
+        let reloads  = pickup.ammo.is_some_and(|ammo| {
+            entities.arsenals.get(player).and_then(|arsenal| arsenal.ammo.get(ammo.weapon)).is_some_and(|carried| *carried < WEAPONS[ammo.weapon].max_ammo)
+        });
+
+        if !in_reach || !(heals || reloads) {
+            continue;
+        }
+
+        if let Some(health) = entities.healths.get_mut(player) {
+            health.current = (health.current + pickup.heal).min(health.maximum);
+        }
+
+        if let (Some(ammo), Some(arsenal)) = (pickup.ammo, entities.arsenals.get_mut(player)) {
+            if let Some(carried) = arsenal.ammo.get_mut(ammo.weapon) {
+                *carried = (*carried + ammo.amount).min(WEAPONS[ammo.weapon].max_ammo);
+            }
+        }
+
+        taken.push(entity);
+    }
+
+    for entity in taken {
+        entities.despawn(entity);
+    }
+}
+
+/*
+* The player staying around when out of health is up to the caller. Enemies with a corpse leave it behind, it
+* is in their way no more.
+*/
+fn run_deaths(entities: &mut Entities, player: Entity) {
+    let dead: Vec<_> = entities
+    .healths
+    .iter()
+    .filter(|(entity, health)| *entity != player && health.is_dead())
+    .map(|(entity, _)| entity)
+    .collect();
+
+    for entity in dead {
+        let corpse = entities.ais.get(entity).and_then(|ai| ai.corpse);
+
+        match (corpse, entities.ai_states.get_mut(entity), entities.billboards.get_mut(entity)) {
+            (Some(corpse), Some(state), Some(billboard)) => {
+                state.mode        = AiMode::Dead;
+                billboard.texture = corpse;
+
+                entities.healths.remove(entity);
+                entities.colliders.remove(entity);
+            },
+
+            _ => entities.despawn(entity),
+        }
+    }
+}
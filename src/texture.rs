@@ -0,0 +1,365 @@
+use std::path::Path;
+
+use ggez::graphics::Color;
+
+use crate::asset::LoadError;
+
+pub type TextureCoordinate = f32;
+
+// CPU-side texture, sampled by the software renderer.
+pub struct Texture {
+    pub width:  u32,
+    pub height: u32,
+        pixels: Vec<Color>,
+}
+
+impl Texture {
+    // Builds a texture pixel by pixel, `generator` receives pixel coordinates.
+    pub fn from_fn<F: Fn(u32, u32) -> Color>(width: u32, height: u32, generator: F) -> Texture {
+        let pixels = (0 .. height)
+        .flat_map(|y| (0 .. width).map(move |x| (x, y)))
+        .map(|(x, y)| generator(x, y))
+        .collect();
+
+        Texture { width, height, pixels }
+    }
+
+    // Any image format the `image` crate was built with, transparency included.
+    pub fn load(path: &Path) -> Result<Texture, LoadError> {
+        let image = image::open(path).map_err(|error| LoadError::Parse { path: path.to_owned(), message: error.to_string() })?.to_rgba8();
+
+        let pixels = image.pixels().map(|pixel| Color::from_rgba(pixel[0], pixel[1], pixel[2], pixel[3])).collect();
+
+        Ok(Texture { width: image.width(), height: image.height(), pixels })
+    }
+
+    /*
+    * Nearest-neighbour sampling. Coordinates are normalized, U wraps around so that textures can be
+    * tiled horizontally, V is clamped.
+    */
+    pub fn sample(&self, u: TextureCoordinate, v: TextureCoordinate) -> Color {
+        let x = ((u.rem_euclid(1.0) * (self.width as f32)) as u32).min(self.width - 1);
+        let y = ((num::clamp(v, 0.0, 1.0) * (self.height as f32)) as u32).min(self.height - 1);
+
+        self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+// Source over destination.
+pub fn blend(destination: Color, source: Color) -> Color {
+    let inverse = 1.0 - source.a;
+
+    Color::new(
+        source.r * source.a + destination.r * inverse,
+        source.g * source.a + destination.g * inverse,
+        source.b * source.a + destination.b * inverse,
+        source.a + destination.a * inverse,
+    )
+}
+
+/*
+* Value noise on a lattice of `period` × `period` cells, wrapping around on both axes so that the
+* result tiles seamlessly. Coordinates are in lattice cells, output is in [0, 1].
+*/
+pub fn periodic_value_noise(x: f32, y: f32, period: u32, seed: u32) -> f32 {
+    let lattice = |x: i64, y: i64| -> f32 {
+        let x = x.rem_euclid(period as i64) as u32;
+        let y = y.rem_euclid(period as i64) as u32;
+
+        // Integer hash, see https://nullprogram.com/blog/2018/07/31/
+        let mut hash = x.wrapping_mul(0x27d4_eb2d) ^ y.wrapping_mul(0x1656_67b1) ^ seed.wrapping_mul(0x9e37_79b9);
+
+        hash ^= hash >> 16;
+        hash  = hash.wrapping_mul(0x7feb_352d);
+        hash ^= hash >> 15;
+        hash  = hash.wrapping_mul(0x846c_a68b);
+        hash ^= hash >> 16;
+
+        (hash as f32) / (u32::MAX as f32)
+    };
+
+    let cell_x = x.floor();
+    let cell_y = y.floor();
+
+    // Smoothstep, so that the lattice does not show.
+    let fade   = |t: f32| t * t * (3.0 - 2.0 * t);
+    let tx     = fade(x - cell_x);
+    let ty     = fade(y - cell_y);
+
+    let (cell_x, cell_y) = (cell_x as i64, cell_y as i64);
+
+    let top    = lattice(cell_x, cell_y)     + (lattice(cell_x + 1, cell_y)     - lattice(cell_x, cell_y))     * tx;
+    let bottom = lattice(cell_x, cell_y + 1) + (lattice(cell_x + 1, cell_y + 1) - lattice(cell_x, cell_y + 1)) * tx;
+
+    top + (bottom - top) * ty
+}
+
+const TILE_TEXTURE_SIZE:       u32 = 64;
+const VIEW_MODEL_TEXTURE_SIZE: u32 = 64;
+
+// Procedural textures materials can use instead of image files, by name.
+pub fn make_builtin_texture(name: &str) -> Option<Texture> {
+    match name {
+        "window" => Some(make_window_texture()),
+        "grate"  => Some(make_grate_texture()),
+        "fence"  => Some(make_fence_texture()),
+        "barrel" => Some(make_barrel_texture()),
+        _        => None,
+    }
+}
+
+// Tinted glass, in a thick opaque frame.
+pub fn make_window_texture() -> Texture {
+    let frame = Color::new(0.35, 0.25, 0.15, 1.0);
+    let glass = Color::new(0.6,  0.8,  1.0,  0.25);
+
+    Texture::from_fn(TILE_TEXTURE_SIZE, TILE_TEXTURE_SIZE, |x, y| {
+        let border = TILE_TEXTURE_SIZE / 16;
+
+        let is_frame = x < border || y < border || x >= TILE_TEXTURE_SIZE - border || y >= TILE_TEXTURE_SIZE - border;
+
+        if is_frame { frame } else { glass }
+    })
+}
+
+// Vertical bars held by two horizontal ones. The holes are fully transparent.
+pub fn make_grate_texture() -> Texture {
+    let metal = Color::new(0.4, 0.4, 0.45, 1.0);
+    let hole  = Color::new(0.0, 0.0, 0.0,  0.0);
+
+    Texture::from_fn(TILE_TEXTURE_SIZE, TILE_TEXTURE_SIZE, |x, y| {
+        let is_vertical_bar   = x % 16 < 3;
+        let is_horizontal_bar = y % 32 >= 12 && y % 32 < 15;
+
+        if is_vertical_bar || is_horizontal_bar { metal } else { hole }
+    })
+}
+
+// Chain-link mesh (two families of diagonal wires) on wooden posts.
+pub fn make_fence_texture() -> Texture {
+    let wood = Color::new(0.5,  0.35, 0.2, 1.0);
+    let wire = Color::new(0.75, 0.75, 0.7, 1.0);
+    let hole = Color::new(0.0,  0.0,  0.0, 0.0);
+
+    Texture::from_fn(TILE_TEXTURE_SIZE, TILE_TEXTURE_SIZE, |x, y| {
+        let diagonal      = (x + y) % 8;
+        let anti_diagonal = (x + TILE_TEXTURE_SIZE - y) % 8;
+
+        let is_post       = !(4 .. TILE_TEXTURE_SIZE - 4).contains(&x);
+        let is_wire       = diagonal == 0 || anti_diagonal == 0;
+
+        if is_post { wood } else if is_wire { wire } else { hole }
+    })
+}
+
+// Wooden barrel seen from the side, with two iron hoops. Staves get darker towards the edges to look round.
+pub fn make_barrel_texture() -> Texture {
+    let wood  = Color::new(0.55, 0.35, 0.15, 1.0);
+    let iron  = Color::new(0.3,  0.3,  0.3,  1.0);
+    let empty = Color::new(0.0,  0.0,  0.0,  0.0);
+
+    Texture::from_fn(TILE_TEXTURE_SIZE, TILE_TEXTURE_SIZE, |x, y| {
+        // From -1 (left edge of the barrel) to 1 (right edge), the barrel being 3/4 of the texture wide.
+        let across   = ((x as f32) + 0.5 - (TILE_TEXTURE_SIZE as f32) / 2.0) / ((TILE_TEXTURE_SIZE as f32) * 3.0 / 8.0);
+
+        if across.abs() > 1.0 {
+            return empty;
+        }
+
+        let is_hoop  = (10 .. 14).contains(&y) || (50 .. 54).contains(&y);
+        let is_seam  = x % 8 == 0;
+        let shading  = (1.0 - across * across).sqrt() * 0.6 + 0.4;
+
+        let base     = if is_hoop { iron } else if is_seam { Color::new(wood.r * 0.7, wood.g * 0.7, wood.b * 0.7, 1.0) } else { wood };
+
+        Color::new(base.r * shading, base.g * shading, base.b * shading, 1.0)
+    })
+}
+
+/*
+* Hunched green creature with red eyes, standing on two stubby legs, seen from `around` radians clockwise from its
+* front (see `Sprite::get_frame`). Its eyes follow where it looks, and cannot be seen from behind.
+*/
+pub fn make_grunt_texture(around: f32) -> Texture {
+    let skin  = Color::new(0.3, 0.55, 0.25, 1.0);
+    let eye   = Color::new(1.0, 0.1,  0.1,  1.0);
+    let empty = Color::new(0.0, 0.0,  0.0,  0.0);
+
+    Texture::from_fn(TILE_TEXTURE_SIZE, TILE_TEXTURE_SIZE, |x, y| {
+        let (x, y)  = (x as f32 + 0.5, y as f32 + 0.5);
+        let size    = TILE_TEXTURE_SIZE as f32;
+
+        /*
+        * The face turns towards the side of the texture the creature looks at, eyes getting closer. Seen from
+        * right beside, both eyes are in the same place (the cosine being a hair under 0 there).
+        */
+        let face    = size * (0.5 + 0.15 * around.sin());
+        let spacing = size * 0.1 * around.cos().max(0.0);
+        let at_eye  = |centre: f32| (centre - size * 0.04 .. centre + size * 0.04).contains(&x);
+
+        // Ellipse for the body, two rectangles for the legs.
+        let body_x  = (x - size / 2.0) / (size * 0.4);
+        let body_y  = (y - size * 0.45) / (size * 0.42);
+        let is_body = body_x * body_x + body_y * body_y <= 1.0;
+        let is_leg  = y > size * 0.8 && ((size * 0.3 .. size * 0.42).contains(&x) || (size * 0.58 .. size * 0.7).contains(&x));
+        let is_eye  = around.cos() > -1e-3 && (size * 0.25 .. size * 0.32).contains(&y) && (at_eye(face - spacing) || at_eye(face + spacing));
+
+        if is_eye {
+            eye
+        } else if is_body || is_leg {
+            // Darker towards the bottom, as if lit from above.
+            let shading = 1.0 - 0.4 * y / size;
+
+            Color::new(skin.r * shading, skin.g * shading, skin.b * shading, 1.0)
+        } else {
+            empty
+        }
+    })
+}
+
+// The grunt lying on the floor, in a red puddle.
+pub fn make_dead_grunt_texture() -> Texture {
+    let skin  = Color::new(0.2, 0.4, 0.18, 1.0);
+    let blood = Color::new(0.5, 0.05, 0.05, 1.0);
+    let empty = Color::new(0.0, 0.0, 0.0, 0.0);
+
+    Texture::from_fn(TILE_TEXTURE_SIZE, TILE_TEXTURE_SIZE, |x, y| {
+        let (x, y)    = (x as f32 + 0.5, y as f32 + 0.5);
+        let size      = TILE_TEXTURE_SIZE as f32;
+
+        let body_x    = (x - size / 2.0) / (size * 0.4);
+        let body_y    = (y - size * 0.88) / (size * 0.1);
+        let puddle_x  = (x - size / 2.0) / (size * 0.48);
+        let puddle_y  = (y - size * 0.95) / (size * 0.05);
+
+        if body_x * body_x + body_y * body_y <= 1.0 {
+            skin
+        } else if puddle_x * puddle_x + puddle_y * puddle_y <= 1.0 {
+            blood
+        } else {
+            empty
+        }
+    })
+}
+
+// White box with a red cross, in the bottom half of the texture.
+pub fn make_medkit_texture() -> Texture {
+    let white = Color::new(0.9, 0.9, 0.9, 1.0);
+    let red   = Color::new(0.8, 0.1, 0.1, 1.0);
+    let empty = Color::new(0.0, 0.0, 0.0, 0.0);
+
+    Texture::from_fn(TILE_TEXTURE_SIZE, TILE_TEXTURE_SIZE, |x, y| {
+        let is_box   = (8 .. 56).contains(&x) && (16 .. 64).contains(&y);
+        let is_cross = ((28 .. 36).contains(&x) && (24 .. 56).contains(&y)) || ((16 .. 48).contains(&x) && (36 .. 44).contains(&y));
+
+        if !is_box { empty } else if is_cross { red } else { white }
+    })
+}
+
+// Glowing blue ball, up at the height of the eye when the sprite is 0.6 high.
+pub fn make_plasma_texture() -> Texture {
+    let size = TILE_TEXTURE_SIZE as f32;
+
+    Texture::from_fn(TILE_TEXTURE_SIZE, TILE_TEXTURE_SIZE, |x, y| {
+        let (x, y) = (x as f32 + 0.5, y as f32 + 0.5);
+        let from   = ((x - size / 2.0).powi(2) + (y - size * 0.2).powi(2)).sqrt() / (size * 0.12);
+
+        // White at the core, blue towards the edge.
+        if from > 1.0 { Color::new(0.0, 0.0, 0.0, 0.0) } else { Color::new(1.0 - from * 0.8, 1.0 - from * 0.5, 1.0, 1.0) }
+    })
+}
+
+// Red box of shotgun shells, lying on the floor.
+pub fn make_shells_texture() -> Texture {
+    let red   = Color::new(0.7, 0.15, 0.1, 1.0);
+    let brass = Color::new(0.8, 0.65, 0.2, 1.0);
+    let empty = Color::new(0.0, 0.0,  0.0, 0.0);
+
+    Texture::from_fn(TILE_TEXTURE_SIZE, TILE_TEXTURE_SIZE, |x, y| {
+        let is_box   = (12 .. 52).contains(&x) && (40 .. 64).contains(&y);
+        let is_shell = (32 .. 40).contains(&y) && (14 .. 50).contains(&x) && x % 6 < 4;
+
+        if is_box { red } else if is_shell { brass } else { empty }
+    })
+}
+
+/*
+* Weapons held by the player, seen from behind, the muzzle up and the hand at the bottom of the texture. When
+* firing, a flash comes out of the muzzle. See viewmodel.rs.
+*/
+pub fn make_pistol_texture(is_firing: bool) -> Texture {
+    let metal = Color::new(0.25, 0.25, 0.28, 1.0);
+    let flash = Color::new(1.0,  0.85, 0.3,  1.0);
+
+    Texture::from_fn(VIEW_MODEL_TEXTURE_SIZE, VIEW_MODEL_TEXTURE_SIZE, |x, y| {
+        let is_gun = (27 .. 37).contains(&x) && (22 .. 52).contains(&y);
+
+        if is_firing && is_in_flash(x, y, 32.0, 14.0, 8.0) {
+            flash
+        } else if is_gun {
+            shade_sides(metal, x, 27, 37)
+        } else {
+            get_hand_color(x, y, 44)
+        }
+    })
+}
+
+pub fn make_shotgun_texture(is_firing: bool) -> Texture {
+    let metal = Color::new(0.2,  0.2,  0.22, 1.0);
+    let wood  = Color::new(0.45, 0.28, 0.12, 1.0);
+    let flash = Color::new(1.0,  0.75, 0.25, 1.0);
+
+    Texture::from_fn(VIEW_MODEL_TEXTURE_SIZE, VIEW_MODEL_TEXTURE_SIZE, |x, y| {
+        let is_barrel = (16 .. 50).contains(&y) && ((24 .. 31).contains(&x) || (33 .. 40).contains(&x));
+        let is_pump   = (34 .. 44).contains(&y) && (21 .. 43).contains(&x);
+
+        if is_firing && is_in_flash(x, y, 32.0, 9.0, 11.0) {
+            flash
+        } else if is_pump {
+            shade_sides(wood, x, 21, 43)
+        } else if is_barrel {
+            shade_sides(metal, x, 24, 40)
+        } else {
+            get_hand_color(x, y, 46)
+        }
+    })
+}
+
+pub fn make_plasma_gun_texture(is_firing: bool) -> Texture {
+    let metal = Color::new(0.35, 0.4, 0.5, 1.0);
+    let glow  = Color::new(0.4,  0.9, 1.0, 1.0);
+
+    Texture::from_fn(VIEW_MODEL_TEXTURE_SIZE, VIEW_MODEL_TEXTURE_SIZE, |x, y| {
+        // Wider towards the bottom.
+        let half_width = 6 + (y.saturating_sub(24)) / 4;
+        let is_body    = y >= 24 && x + half_width >= 32 && x < 32 + half_width;
+        let is_core    = (30 .. 34).contains(&x) && (28 .. 58).contains(&y);
+
+        if (is_firing && is_in_flash(x, y, 32.0, 16.0, 8.0)) || is_core {
+            glow
+        } else if is_body {
+            shade_sides(metal, x, 32 - half_width, 32 + half_width)
+        } else {
+            Color::new(0.0, 0.0, 0.0, 0.0)
+        }
+    })
+}
+
+fn is_in_flash(x: u32, y: u32, centre_x: f32, centre_y: f32, radius: f32) -> bool {
+    ((x as f32) + 0.5 - centre_x).powi(2) + ((y as f32) + 0.5 - centre_y).powi(2) <= radius * radius
+}
+
+// Darker towards the left and right edges, from `left` to `right`, to look round.
+fn shade_sides(color: Color, x: u32, left: u32, right: u32) -> Color {
+    let across  = ((x as f32) + 0.5 - (left + right) as f32 / 2.0) / ((right - left) as f32 / 2.0);
+    let shading = 1.0 - 0.4 * across.abs();
+
+    Color::new(color.r * shading, color.g * shading, color.b * shading, 1.0)
+}
+
+// A hand holding the weapon from below, from the row `top` down to the bottom of the texture.
+fn get_hand_color(x: u32, y: u32, top: u32) -> Color {
+    let skin = Color::new(0.85, 0.65, 0.5, 1.0);
+
+    if y >= top && (22 .. 42).contains(&x) { shade_sides(skin, x, 22, 42) } else { Color::new(0.0, 0.0, 0.0, 0.0) }
+}
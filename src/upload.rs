@@ -0,0 +1,149 @@
+use std::{fmt, ops::Range, time::{Duration, Instant}};
+
+use gfx::memory::Typed;
+use ggez::{Context, GameError, GameResult, graphics::{self, DrawParam, Drawable, FilterMode, Image}};
+
+use crate::raycaster::PIXEL_SIZE;
+
+// Frames uploaded for each way of uploading, by the benchmark.
+const BENCHMARK_FRAMES: u32 = 200;
+// Rows changed by each frame when only part of the framebuffer changes, like the HUD over a still view.
+const PARTIAL_ROWS:     u16 = 16;
+
+type TextureFormat  = gfx::format::Srgba8;
+type TextureSurface = <TextureFormat as gfx::format::Formatted>::Surface;
+
+/*
+* The framebuffer on the GPU. The texture is made once, then updated in place every frame: only the rows that
+* changed are uploaded.
+*/
+pub struct StreamingImage {
+    image: Image,
+}
+
+// How long uploading and drawing a frame took on average, the old way and the streaming way.
+#[derive(Clone, Copy, Debug)]
+pub struct UploadBenchmark {
+    pub width:          u16,
+    pub height:         u16,
+    // A new image every frame, like before.
+    pub new_image:      Duration,
+    // The whole framebuffer changing every frame, when turning around.
+    pub streaming_all:  Duration,
+    // A few rows changing every frame.
+    pub streaming_some: Duration,
+}
+
+impl StreamingImage {
+    // The texture starts with `rgba`, RGBA8 pixels row after row.
+    pub fn new(context: &mut Context, width: u16, height: u16, rgba: &[u8]) -> GameResult<StreamingImage> {
+        let mut image = Image::from_rgba8(context, width, height, rgba)?;
+
+        image.set_filter(FilterMode::Nearest);
+
+        Ok(StreamingImage { image })
+    }
+
+    pub fn get_image(&self) -> &Image {
+        &self.image
+    }
+
+    // Uploads `rows` of `rgba`, which is the whole framebuffer. The upload goes out with the next draw.
+    pub fn update(&mut self, context: &mut Context, rgba: &[u8], rows: Range<u16>) -> GameResult {
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let width    = self.image.width();
+        let row_size = (width as usize) * (PIXEL_SIZE as usize);
+        let pixels   = &rgba[(rows.start as usize) * row_size .. (rows.end as usize) * row_size];
+
+        let texture  = gfx::handle::Texture::<_, TextureSurface>::new(self.image.get_raw_texture_handle());
+        let info     = gfx::texture::ImageInfoCommon {
+            xoffset: 0,
+            yoffset: rows.start,
+            zoffset: 0,
+            width,
+            height:  rows.end - rows.start,
+            depth:   0,
+            format:  (),
+            mipmap:  0,
+        };
+
+        let (_, _, encoder, _, _) = graphics::gfx_objects(context);
+
+        encoder.update_texture::<TextureSurface, TextureFormat>(&texture, None, info, gfx::memory::cast_slice(pixels))
+        .map_err(|error| GameError::RenderError(format!("Could not update the framebuffer texture: {:?}", error)))
+    }
+}
+
+impl fmt::Display for UploadBenchmark {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let milliseconds = |duration: Duration| duration.as_secs_f64() * 1000.0;
+
+        write!(
+            formatter,
+            "{}x{}: new image {:.3} ms, streaming every row {:.3} ms, streaming {} rows {:.3} ms",
+            self.width,
+            self.height,
+            milliseconds(self.new_image),
+            milliseconds(self.streaming_all),
+            PARTIAL_ROWS,
+            milliseconds(self.streaming_some),
+        )
+    }
+}
+
+/*
+* Uploads, draws and presents frames of `width` × `height` pixels: new images like `State::draw` used to make,
+* then a streaming image updated whole and in part. Every frame is presented, which flushes its upload to the
+* GPU, so the window should not wait for the screen.
+*/
+pub fn run_benchmark(context: &mut Context, width: u16, height: u16) -> GameResult<UploadBenchmark> {
+    let row_size        = (width as usize) * (PIXEL_SIZE as usize);
+    let mut framebuffer = vec![0; row_size * (height as usize)];
+    let screen          = graphics::screen_coordinates(context);
+    let param           = DrawParam::new().scale([screen.w / (width as f32), screen.h / (height as f32)]);
+
+    let new_image       = time_frames(context, &mut framebuffer, row_size, height, |context, framebuffer, _| {
+        let mut image = Image::from_rgba8(context, width, height, framebuffer)?;
+
+        image.set_filter(FilterMode::Nearest);
+        image.draw(context, param)
+    })?;
+
+    let mut streaming   = StreamingImage::new(context, width, height, &framebuffer)?;
+
+    let streaming_all   = time_frames(context, &mut framebuffer, row_size, height, |context, framebuffer, _| {
+        streaming.update(context, framebuffer, 0 .. height)?;
+        streaming.get_image().draw(context, param)
+    })?;
+
+    let streaming_some  = time_frames(context, &mut framebuffer, row_size, PARTIAL_ROWS.min(height), |context, framebuffer, rows| {
+        streaming.update(context, framebuffer, rows)?;
+        streaming.get_image().draw(context, param)
+    })?;
+
+    Ok(UploadBenchmark { width, height, new_image, streaming_all, streaming_some })
+}
+
+// Average time of a frame, each changing the first `changed_rows` rows of the framebuffer before `upload`.
+fn time_frames<U>(context: &mut Context, framebuffer: &mut [u8], row_size: usize, changed_rows: u16, mut upload: U) -> GameResult<Duration>
+where
+    U: FnMut(&mut Context, &[u8], Range<u16>) -> GameResult,
+{
+    let changed = row_size * (changed_rows as usize);
+    let start   = Instant::now();
+
+    for frame in 0 .. BENCHMARK_FRAMES {
+        for (index, byte) in framebuffer[.. changed].iter_mut().enumerate() {
+            *byte = (index as u32).wrapping_add(frame) as u8;
+        }
+
+        upload(context, framebuffer, 0 .. changed_rows)?;
+
+        graphics::present(context)?;
+    }
+
+    Ok(start.elapsed() / BENCHMARK_FRAMES)
+}
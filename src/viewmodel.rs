@@ -0,0 +1,94 @@
+use std::f32::consts::{PI, TAU};
+
+use crate::{raycaster::Raycaster, texture::{self, Texture}, weapon::WeaponId, world::WorldLength};
+
+// In screen heights, the textures being square.
+const VIEW_MODEL_SIZE: f32         = 0.5;
+// Walking this fast, in tiles per second, bobs the weapon all the way.
+const FULL_BOB_SPEED:  f32         = 3.0;
+// The weapon sways from one side to the other every this many tiles walked.
+const BOB_STRIDE:      WorldLength = 1.0;
+// How far the weapon sways sideways, and dips, in screen heights.
+const BOB_SWAY:        f32         = 0.04;
+const BOB_DIP:         f32         = 0.03;
+// How fast bobbing starts and stops, per second.
+const BOB_EASING:      f32         = 8.0;
+// How far the weapon kicks down when fired, in screen heights.
+const RECOIL_KICK:     f32         = 0.06;
+// The muzzle flash shows while the recoil is above this.
+const FLASH_RECOIL:    f32         = 0.7;
+
+/*
+* The weapon in the hands of the player, drawn over the 3D view at the bottom of the screen. It sways and
+* dips as the player walks, and kicks down when fired, coming back up as it gets ready to fire again.
+*/
+pub struct ViewModel {
+    // Nothing is drawn when nothing is held.
+    pub weapon:     Option<WeaponId>,
+    // From 0 (ready to fire) to 1 (just fired), see `Arsenal::get_recoil`.
+    pub recoil:     f32,
+        bob_phase:  f32,
+        bob_amount: f32,
+        // Idle and firing frames of each weapon, by weapon ID.
+        textures:   Vec<[Texture; 2]>,
+}
+
+impl ViewModel {
+    pub fn new() -> ViewModel {
+        ViewModel { weapon: None, recoil: 0.0, bob_phase: 0.0, bob_amount: 0.0, textures: make_view_model_textures() }
+    }
+
+    // The player walked `walked` in the last `seconds`. Bobbing follows how fast, easing in and out.
+    pub fn update(&mut self, walked: WorldLength, seconds: f32) {
+        let speed = if seconds > 0.0 { (walked / seconds / FULL_BOB_SPEED).min(1.0) } else { 0.0 };
+
+        self.bob_amount += (speed - self.bob_amount) * (BOB_EASING * seconds).min(1.0);
+        self.bob_phase   = (self.bob_phase + walked / BOB_STRIDE * PI).rem_euclid(TAU);
+    }
+
+    /*
+    * The weapon is highest in the middle of a sway and lowest at its ends. Dipping or kicking pushes the bottom
+    * of the weapon off the screen. Textures are fully opaque or fully transparent, nothing is blended.
+    */
+    pub fn draw(&self, raycaster: &mut Raycaster) {
+        let weapon  = match self.weapon {
+            Some(weapon) => weapon,
+            None         => return,
+        };
+
+        let texture = &self.textures[weapon][(self.recoil > FLASH_RECOIL) as usize];
+        let width   = raycaster.get_width() as f32;
+        let height  = raycaster.get_height() as f32;
+        let size    = height * VIEW_MODEL_SIZE;
+
+        let left    = (width - size) / 2.0 + self.bob_phase.sin() * self.bob_amount * BOB_SWAY * height;
+        let top     = height - size + self.bob_phase.cos().abs() * self.bob_amount * BOB_DIP * height + self.recoil * self.recoil * RECOIL_KICK * height;
+
+        for y in (top.max(0.0) as u16) .. raycaster.get_height() {
+            for x in (left.max(0.0) as u16) .. ((left + size).min(width).max(0.0) as u16) {
+                let u     = ((x as f32) + 0.5 - left) / size;
+                let v     = ((y as f32) + 0.5 - top) / size;
+                let color = texture.sample(u, v);
+
+                if (0.0 .. 1.0).contains(&u) && (0.0 .. 1.0).contains(&v) && color.a > 0.0 {
+                    raycaster.set_pixel(x, y, &color);
+                }
+            }
+        }
+    }
+}
+
+impl Default for ViewModel {
+    fn default() -> ViewModel {
+        ViewModel::new()
+    }
+}
+
+// Ordered by weapon ID.
+fn make_view_model_textures() -> Vec<[Texture; 2]> {
+    vec![
+        [texture::make_pistol_texture(false),     texture::make_pistol_texture(true)],
+        [texture::make_shotgun_texture(false),    texture::make_shotgun_texture(true)],
+        [texture::make_plasma_gun_texture(false), texture::make_plasma_gun_texture(true)],
+    ]
+}
@@ -0,0 +1,250 @@
+use serde::Deserialize;
+
+use crate::{entity::{AiMode, Billboard, Collider, Entities, Entity, Projectile, Transform}, level::Level, random::Random, sprite::{self, SpriteTextureId}, world::{self, WorldAngle, WorldDirection, WorldLength, WorldPosition}};
+
+// Index into `WEAPONS`.
+pub type WeaponId = usize;
+
+// Projectiles start this far beyond the collider of whoever fired them, so that they do not hit them.
+const PROJECTILE_SPAWN_MARGIN: WorldLength = 0.05;
+
+// How shots get where they are aimed.
+#[derive(Clone, Copy, Debug)]
+pub enum Delivery {
+    // Right away, along a ray.
+    Hitscan    { range: WorldLength },
+    // As an entity flying straight, in tiles per second, for `lifetime` seconds at most.
+    Projectile { speed: f32, radius: WorldLength, lifetime: f32, texture: SpriteTextureId },
+}
+
+/*
+* Every shot takes one unit of ammo, and fires `pellets` at once. Each pellet goes in its own direction, within
+* `spread` (the whole width of the cone, in radians) of where the weapon is aimed, and takes `damage` health
+* from what it hits.
+*/
+#[derive(Clone, Copy, Debug)]
+pub struct Weapon {
+    pub name:       &'static str,
+    // Shots per second.
+    pub fire_rate:  f32,
+    pub damage:     f32,
+    pub pellets:    u32,
+    pub spread:     WorldAngle,
+    pub max_ammo:   u32,
+    // Ammo the player starts with.
+    pub start_ammo: u32,
+    pub delivery:   Delivery,
+}
+
+// Ordered by weapon ID, which is also the order weapons are switched in.
+pub const WEAPONS: [Weapon; 3] = [
+    Weapon { name: "pistol",  fire_rate: 3.0, damage: 15.0, pellets: 1, spread: 0.02, max_ammo: 200, start_ammo: 50, delivery: Delivery::Hitscan { range: 32.0 } },
+    Weapon { name: "shotgun", fire_rate: 1.2, damage: 8.0,  pellets: 7, spread: 0.2,  max_ammo: 50,  start_ammo: 8,  delivery: Delivery::Hitscan { range: 16.0 } },
+    Weapon {
+        name:       "plasma",
+        fire_rate:  6.0,
+        damage:     12.0,
+        pellets:    1,
+        spread:     0.0,
+        max_ammo:   300,
+        start_ammo: 40,
+        delivery:   Delivery::Projectile { speed: 10.0, radius: 0.1, lifetime: 4.0, texture: sprite::PLASMA_TEXTURE },
+    },
+];
+
+// What the player asks their weapons for, during one tick.
+#[derive(PartialEq, Clone, Copy, Default, Debug)]
+pub struct Trigger {
+    // Held down: the weapon fires again whenever it is ready.
+    pub is_firing: bool,
+    // Weapons to go forward (or backward) by, in the order of `WEAPONS`.
+    pub switch:    i32,
+}
+
+// Something a shot hit, and how far along it.
+#[derive(Clone, Copy, Debug)]
+pub struct ShotHit {
+    pub entity:   Entity,
+    pub distance: WorldLength,
+}
+
+/*
+* The weapons of `shooter`, aimed where it looks. Weapons cool down between shots. Spread comes from `random`,
+* so that shots go the same way when a game is played again. Out of ammo, the next weapon that has some is
+* taken out.
+*/
+pub fn run_arsenal(entities: &mut Entities, level: &dyn Level, random: &mut Random, shooter: Entity, trigger: Trigger, seconds: f32) {
+    let is_dead = entities.healths.get(shooter).is_some_and(|health| health.is_dead());
+    let radius  = entities.colliders.get(shooter).map_or(0.0, |collider| collider.radius);
+
+    let (transform, arsenal) = match (entities.transforms.get(shooter), entities.arsenals.get_mut(shooter)) {
+        (Some(transform), Some(arsenal)) if !is_dead => (*transform, arsenal),
+        _                                            => return,
+    };
+
+    let origin  = transform.position;
+    let aim     = world::rotate_clockwise(world::TOP_UNIT_VECTOR, transform.angle);
+
+    arsenal.cooldown = (arsenal.cooldown - seconds).max(0.0);
+
+    if trigger.switch != 0 {
+        arsenal.selected = (arsenal.selected as i32 + trigger.switch).rem_euclid(WEAPONS.len() as i32) as WeaponId;
+    }
+
+    if !trigger.is_firing || arsenal.cooldown > 0.0 {
+        return;
+    }
+
+    if arsenal.ammo[arsenal.selected] == 0 {
+        if let Some(loaded) = (1 .. WEAPONS.len()).map(|offset| (arsenal.selected + offset) % WEAPONS.len()).find(|weapon| arsenal.ammo[*weapon] > 0) {
+            arsenal.selected = loaded;
+        }
+
+        return;
+    }
+
+    let weapon = WEAPONS[arsenal.selected];
+
+    arsenal.ammo[arsenal.selected] -= 1;
+    arsenal.cooldown                = 1.0 / weapon.fire_rate;
+
+    for _ in 0 .. weapon.pellets {
+        let direction = if weapon.spread > 0.0 { world::rotate_clockwise(aim, (random.next_f32() - 0.5) * weapon.spread) } else { aim };
+
+        match weapon.delivery {
+            Delivery::Hitscan { range } => {
+                if let Some(hit) = trace_shot(entities, level, origin, direction, range, 0.0, Some(shooter)) {
+                    hurt(entities, hit.entity, weapon.damage, origin);
+                }
+            },
+
+            Delivery::Projectile { speed, radius: projectile_radius, lifetime, texture } => {
+                let projectile = entities.spawn();
+                let position   = origin + direction * (radius + projectile_radius + PROJECTILE_SPAWN_MARGIN);
+
+                entities.transforms.insert(projectile, Transform { position, angle: world::get_clockwise_angle(direction) });
+                entities.billboards.insert(projectile, Billboard { texture, width: 0.6, height: 0.6 });
+                entities.colliders.insert(projectile, Collider { radius: projectile_radius, is_solid: false });
+                entities.projectiles.insert(projectile, Projectile { velocity: direction * speed, damage: weapon.damage, lifetime });
+            },
+        }
+    }
+}
+
+/*
+* Projectiles fly straight, and are gone once they hit a wall or something with health, or when they have
+* flown for too long.
+*/
+pub fn run_projectiles(entities: &mut Entities, level: &dyn Level, seconds: f32) {
+    let projectiles: Vec<_> = entities.projectiles.iter().map(|(entity, projectile)| (entity, *projectile)).collect();
+
+    for (entity, mut projectile) in projectiles {
+        let (position, radius) = match (entities.transforms.get(entity), entities.colliders.get(entity)) {
+            (Some(transform), collider) => (transform.position, collider.map_or(0.0, |collider| collider.radius)),
+            (None, _)                   => continue,
+        };
+
+        let motion    = projectile.velocity * seconds;
+        let distance  = motion.length();
+
+        projectile.lifetime -= seconds;
+
+        if projectile.lifetime <= 0.0 || distance == 0.0 {
+            entities.despawn(entity);
+
+            continue;
+        }
+
+        /*
+        * The wall is met when the edge of the projectile gets to it, not its centre. Targets are looked for as
+        * far, so that whatever is between the projectile and the wall is hit before the projectile is gone.
+        */
+        let direction = motion / distance;
+        let reach     = distance + radius;
+        let wall      = level.trace_ray(position, direction, reach, &|tile| tile.blocks_projectiles());
+        let target    = trace_shot(entities, level, position, direction, reach, radius, Some(entity));
+
+        match (target, wall) {
+            (Some(hit), _) => {
+                hurt(entities, hit.entity, projectile.damage, position);
+                entities.despawn(entity);
+            },
+
+            (None, Some(_)) => entities.despawn(entity),
+
+            (None, None) => {
+                entities.transforms.insert(entity, Transform { position: position + motion, angle: world::get_clockwise_angle(direction) });
+                entities.projectiles.insert(entity, projectile);
+            },
+        }
+    }
+}
+
+/*
+* Gameplay ray query for shots: the closest entity with health whose collider the ray goes through, before
+* a tile blocking projectiles stops it. `thickness` is the radius of what flies along the ray, 0 for bullets. `ignored` is
+* not hit, whoever fired the shot usually. The direction is normalized.
+*/
+pub fn trace_shot(entities: &Entities, level: &dyn Level, origin: WorldPosition, direction: WorldDirection, range: WorldLength, thickness: WorldLength, ignored: Option<Entity>) -> Option<ShotHit> {
+    let wall = level.trace_ray(origin, direction, range, &|tile| tile.blocks_projectiles()).map_or(range, |hit| hit.distance);
+
+    entities
+    .healths
+    .iter()
+    .filter(|(entity, _)| Some(*entity) != ignored)
+    .filter_map(|(entity, _)| {
+        let position = entities.transforms.get(entity)?.position;
+        let radius   = entities.colliders.get(entity)?.radius + thickness;
+
+        /*
+        * The ray gets closest to the centre of the collider `along` from its origin, `squared_gap` squared away
+        * from it. It goes in the collider that far before, by Pythagoras. Rays starting inside hit right away.
+        */
+        let to_centre   = position - origin;
+        let along       = to_centre.dot(direction);
+        let squared_gap = to_centre.length_squared() - along * along;
+
+        if squared_gap > radius * radius {
+            return None;
+        }
+
+        let distance = (along - (radius * radius - squared_gap).sqrt()).max(0.0);
+
+        (along >= 0.0 && distance <= wall).then_some(ShotHit { entity, distance })
+    })
+    .min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
+
+pub fn find_weapon(name: &str) -> Option<WeaponId> {
+    WEAPONS.iter().position(|weapon| weapon.name == name)
+}
+
+/*
+* Takes health from `entity`, shot from `from`. Enemies that are shot go and look where the shot came from,
+* whether they noticed who fired it or not.
+*/
+fn hurt(entities: &mut Entities, entity: Entity, damage: f32, from: WorldPosition) {
+    if let Some(health) = entities.healths.get_mut(entity) {
+        health.current -= damage;
+    }
+
+    if let Some(state) = entities.ai_states.get_mut(entity) {
+        state.target         = Some(from);
+        state.unseen_seconds = 0.0;
+
+        if matches!(state.mode, AiMode::Idle | AiMode::Patrol) {
+            state.mode         = AiMode::Chase;
+            state.mode_seconds = 0.0;
+        }
+    }
+}
+
+pub fn serialize_weapon<S: serde::Serializer>(weapon: &WeaponId, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(WEAPONS[*weapon].name)
+}
+
+pub fn deserialize_weapon<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<WeaponId, D::Error> {
+    let name = String::deserialize(deserializer)?;
+
+    find_weapon(&name).ok_or_else(|| serde::de::Error::custom(format!("there is no weapon named {:?}", name)))
+}
@@ -2,6 +2,14 @@ use glam::Vec2;
 
 use crate::map::MapPosition;
 
+/*
+* World coordinate system, shared by the map, the camera and the minimap:
+* - One unit is one tile. Tile (x, y) covers [x, x + 1[ × [y, y + 1[, and row 0 is the first row of the map data.
+* - X grows towards the right of the map, Y grows towards the bottom of the map, like in screen space. This
+*   is why drawing the map from above (the minimap) is only a matter of scaling and translating positions.
+* - Angles are clockwise when looking at the map from above, and 0 points to the top of the map (TOP_UNIT_VECTOR).
+*/
+
 // Clockwise angle around the axis going up from the player's perspective.
 pub type WorldAngle     = f32;
 pub type WorldLength    = f32;
@@ -33,6 +41,11 @@ pub fn rotate_clockwise(vector: WorldVector, radians: f32) -> WorldVector {
     }
 }
 
+// Angle of `direction`, clockwise from the top of the map.
+pub fn get_clockwise_angle(direction: WorldDirection) -> WorldAngle {
+    direction.x.atan2(-direction.y)
+}
+
 pub fn get_scaled_right_vector(x: f32) -> WorldVector {
     Vec2 { x, y: 0.0 }
 }